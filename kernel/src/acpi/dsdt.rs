@@ -0,0 +1,103 @@
+use crate::acpi::sdt::SDT_HEADER_SIZE;
+
+// The AML opcodes needed to find the sleep types. More info can be found in chapter 20 of the ACPI
+// specification.
+const NAME_OP: u8 = 0x08;
+const PACKAGE_OP: u8 = 0x12;
+const BYTE_PREFIX: u8 = 0x0A;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const ROOT_PREFIX: u8 = b'\\';
+
+// The values written to the SLP_TYP fields of the PM1a and PM1b control registers to enter a sleep
+// state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SleepType {
+    pub pm1a: u8,
+    pub pm1b: u8,
+}
+
+// Finds the sleep types of the soft off state S5 in the AML code of the DSDT. They are defined by
+// the \_S5_ package. Running AML requires an interpreter, so only the plain package encoding that
+// firmware uses in practice is understood. Returns None if the package is not found.
+// https://wiki.osdev.org/Shutdown
+pub fn parse_s5(bytes: &[u8]) -> Option<SleepType> {
+    let aml = bytes.get(SDT_HEADER_SIZE..)?;
+    aml.windows(4)
+        .enumerate()
+        .filter(|(_, name)| name == b"_S5_")
+        .find_map(|(position, _)| parse_s5_package(aml, position))
+}
+
+// Parses the package at position, which needs to be the definition of the _S5_ name, i.e. preceded
+// by NameOp and optionally the root prefix.
+fn parse_s5_package(aml: &[u8], position: usize) -> Option<SleepType> {
+    let is_definition = match position {
+        0 => false,
+        1 => aml[0] == NAME_OP,
+        _ => {
+            aml[position - 1] == NAME_OP
+                || (aml[position - 1] == ROOT_PREFIX && aml[position - 2] == NAME_OP)
+        }
+    };
+    if !is_definition || *aml.get(position + 4)? != PACKAGE_OP {
+        return None;
+    }
+
+    // The lead byte of the package length tells in bits 6-7 how many bytes follow. The number of
+    // elements comes next.
+    let package_length_size = 1 + (*aml.get(position + 5)? >> 6) as usize;
+    let mut offset = position + 5 + package_length_size + 1;
+
+    let pm1a = parse_byte_data(aml, &mut offset)?;
+    let pm1b = parse_byte_data(aml, &mut offset)?;
+    Some(SleepType { pm1a, pm1b })
+}
+
+// Parses an integer that fits into a byte and moves offset past it.
+#[inline]
+fn parse_byte_data(aml: &[u8], offset: &mut usize) -> Option<u8> {
+    let value = match *aml.get(*offset)? {
+        BYTE_PREFIX => {
+            *offset += 1;
+            *aml.get(*offset)?
+        }
+        ZERO_OP => 0,
+        ONE_OP => 1,
+        _ => return None,
+    };
+
+    *offset += 1;
+    Some(value)
+}
+
+#[test_case]
+fn test_parse_s5() {
+    let mut bytes = [0u8; SDT_HEADER_SIZE].to_vec();
+
+    // A reference to \_S5_ is not its definition.
+    bytes.extend_from_slice(&[0x70, b'_', b'S', b'5', b'_']);
+
+    // Name (\_S5_, Package (4) { 0x05, 0x05, Zero, Zero })
+    bytes.extend_from_slice(&[
+        NAME_OP,
+        ROOT_PREFIX,
+        b'_',
+        b'S',
+        b'5',
+        b'_',
+        PACKAGE_OP,
+        0x0A,
+        0x04,
+    ]);
+    bytes.extend_from_slice(&[BYTE_PREFIX, 0x05, BYTE_PREFIX, 0x07, ZERO_OP, ZERO_OP]);
+    assert_eq!(parse_s5(&bytes), Some(SleepType { pm1a: 5, pm1b: 7 }));
+
+    // QEMU uses Zero for both sleep types.
+    let mut bytes = [0u8; SDT_HEADER_SIZE].to_vec();
+    bytes.extend_from_slice(&[NAME_OP, b'_', b'S', b'5', b'_', PACKAGE_OP, 0x06, 0x04]);
+    bytes.extend_from_slice(&[ZERO_OP, ZERO_OP, ZERO_OP, ZERO_OP]);
+    assert_eq!(parse_s5(&bytes), Some(SleepType { pm1a: 0, pm1b: 0 }));
+
+    assert_eq!(parse_s5(&bytes[..SDT_HEADER_SIZE + 7]), None);
+}
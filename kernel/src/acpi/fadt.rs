@@ -0,0 +1,286 @@
+use bitflags::bitflags;
+
+use crate::acpi::sdt::{read_u16, read_u32, read_u64, GenericAddress};
+use crate::acpi::AcpiError;
+use crate::memory::paddr::PhysicalAddress;
+
+// Offsets of the FADT fields, see https://wiki.osdev.org/FADT#Structure. Fields added by later
+// revisions are only present if the table is long enough.
+const DSDT: usize = 40;
+const SCI_INTERRUPT: usize = 46;
+const SMI_COMMAND: usize = 48;
+const ACPI_ENABLE: usize = 52;
+const ACPI_DISABLE: usize = 53;
+const PM1A_EVENT_BLOCK: usize = 56;
+const PM1B_EVENT_BLOCK: usize = 60;
+const PM1A_CONTROL_BLOCK: usize = 64;
+const PM1B_CONTROL_BLOCK: usize = 68;
+const PM_TIMER_BLOCK: usize = 76;
+const PM1_EVENT_LENGTH: usize = 88;
+const PM1_CONTROL_LENGTH: usize = 89;
+const PM_TIMER_LENGTH: usize = 91;
+const CENTURY: usize = 108;
+const BOOT_ARCHITECTURE_FLAGS: usize = 109;
+const FLAGS: usize = 112;
+const RESET_REGISTER: usize = 116;
+const RESET_VALUE: usize = 128;
+const X_DSDT: usize = 140;
+const X_PM1A_EVENT_BLOCK: usize = 148;
+const X_PM1B_EVENT_BLOCK: usize = 160;
+const X_PM1A_CONTROL_BLOCK: usize = 172;
+const X_PM1B_CONTROL_BLOCK: usize = 184;
+const X_PM_TIMER_BLOCK: usize = 208;
+const SLEEP_CONTROL_REGISTER: usize = 244;
+const SLEEP_STATUS_REGISTER: usize = 256;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct FadtFlags: u32 {
+        const WBINVD = 1;
+        const PROCESSOR_C1 = 1 << 2;
+        const POWER_BUTTON = 1 << 4;
+        const SLEEP_BUTTON = 1 << 5;
+        const RTC_S4 = 1 << 7;
+
+        // The PM timer counts with 32 instead of 24 bits
+        const TIMER_VALUE_EXTENDED = 1 << 8;
+
+        // The reset register can be used to reset the machine
+        const RESET_REGISTER_SUPPORTED = 1 << 10;
+
+        // The machine has no fixed hardware, e.g. PM1 blocks, and uses the sleep registers instead
+        const HARDWARE_REDUCED_ACPI = 1 << 20;
+    }
+}
+
+// The legacy devices of the IA-PC boot architecture.
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct BootArchitectureFlags: u16 {
+        const LEGACY_DEVICES = 1;
+        const PS2_CONTROLLER = 1 << 1;
+        const VGA_NOT_PRESENT = 1 << 2;
+        const MSI_NOT_SUPPORTED = 1 << 3;
+        const PCIE_ASPM_CONTROLS = 1 << 4;
+        const CMOS_RTC_NOT_PRESENT = 1 << 5;
+    }
+}
+
+// The fixed ACPI description table describes the power management hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fadt {
+    pub revision: u8,
+    pub flags: FadtFlags,
+    pub boot_architecture: BootArchitectureFlags,
+
+    // The differentiated system description table, which holds the AML code of the machine.
+    pub dsdt: PhysicalAddress,
+
+    // The interrupt ACPI events are signaled on.
+    pub sci_interrupt: u16,
+
+    // Writing acpi_enable to the SMI command port switches the firmware to ACPI mode. Both are zero
+    // if the machine is always in ACPI mode.
+    pub smi_command_port: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+
+    pub pm1a_event_block: Option<GenericAddress>,
+    pub pm1b_event_block: Option<GenericAddress>,
+    pub pm1a_control_block: Option<GenericAddress>,
+    pub pm1b_control_block: Option<GenericAddress>,
+
+    // The power management timer, which counts at 3.579545 MHz.
+    pub pm_timer_block: Option<GenericAddress>,
+
+    // Writing reset_value to the reset register resets the machine.
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+
+    // Used instead of the PM1 control and status registers on hardware reduced machines.
+    pub sleep_control_register: Option<GenericAddress>,
+    pub sleep_status_register: Option<GenericAddress>,
+
+    // The CMOS RAM index of the century of the RTC, or 0 if the RTC has none.
+    pub century_register: u8,
+}
+
+impl Fadt {
+    pub fn parse(bytes: &[u8]) -> Result<Fadt, AcpiError> {
+        if bytes.len() < BOOT_ARCHITECTURE_FLAGS {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        let flags = if bytes.len() >= FLAGS + 4 {
+            FadtFlags::from_bits_truncate(read_u32(bytes, FLAGS))
+        } else {
+            FadtFlags::empty()
+        };
+
+        // ACPI 1.0 only had I/O port blocks. Later revisions added generic addresses, which take
+        // precedence.
+        let block = |x_offset: usize, offset: usize, length_offset: usize| {
+            GenericAddress::parse(bytes, x_offset).or_else(|| {
+                let port = read_u32(bytes, offset);
+                (port != 0)
+                    .then(|| GenericAddress::io_port(port, bytes[length_offset].saturating_mul(8)))
+            })
+        };
+
+        let x_dsdt = if bytes.len() >= X_DSDT + 8 {
+            read_u64(bytes, X_DSDT)
+        } else {
+            0
+        };
+        let dsdt = if x_dsdt != 0 {
+            x_dsdt
+        } else {
+            read_u32(bytes, DSDT) as u64
+        };
+
+        // A table that ends right after the reset register has no reset value, so the register
+        // can't be used.
+        let reset_register = GenericAddress::parse(bytes, RESET_REGISTER)
+            .filter(|_| flags.contains(FadtFlags::RESET_REGISTER_SUPPORTED));
+        let (reset_register, reset_value) = match (reset_register, bytes.get(RESET_VALUE)) {
+            (Some(register), Some(&value)) => (Some(register), value),
+            _ => (None, 0),
+        };
+
+        Ok(Fadt {
+            revision: bytes[8],
+            flags,
+            boot_architecture: if bytes.len() >= BOOT_ARCHITECTURE_FLAGS + 2 {
+                BootArchitectureFlags::from_bits_truncate(read_u16(bytes, BOOT_ARCHITECTURE_FLAGS))
+            } else {
+                BootArchitectureFlags::empty()
+            },
+            dsdt: PhysicalAddress::new(dsdt),
+            sci_interrupt: read_u16(bytes, SCI_INTERRUPT),
+            smi_command_port: read_u32(bytes, SMI_COMMAND),
+            acpi_enable: bytes[ACPI_ENABLE],
+            acpi_disable: bytes[ACPI_DISABLE],
+            pm1a_event_block: block(X_PM1A_EVENT_BLOCK, PM1A_EVENT_BLOCK, PM1_EVENT_LENGTH),
+            pm1b_event_block: block(X_PM1B_EVENT_BLOCK, PM1B_EVENT_BLOCK, PM1_EVENT_LENGTH),
+            pm1a_control_block: block(X_PM1A_CONTROL_BLOCK, PM1A_CONTROL_BLOCK, PM1_CONTROL_LENGTH),
+            pm1b_control_block: block(X_PM1B_CONTROL_BLOCK, PM1B_CONTROL_BLOCK, PM1_CONTROL_LENGTH),
+            pm_timer_block: block(X_PM_TIMER_BLOCK, PM_TIMER_BLOCK, PM_TIMER_LENGTH),
+            reset_register,
+            reset_value,
+            sleep_control_register: GenericAddress::parse(bytes, SLEEP_CONTROL_REGISTER),
+            sleep_status_register: GenericAddress::parse(bytes, SLEEP_STATUS_REGISTER),
+            century_register: bytes[CENTURY],
+        })
+    }
+
+    // Maps the registers in system memory into the kernel address space. Registers that can't be
+    // mapped stay inaccessible, and the first error is returned.
+    pub fn map_registers(&mut self) -> Result<(), AcpiError> {
+        let mut result = Ok(());
+        for register in [
+            &mut self.pm1a_event_block,
+            &mut self.pm1b_event_block,
+            &mut self.pm1a_control_block,
+            &mut self.pm1b_control_block,
+            &mut self.pm_timer_block,
+            &mut self.reset_register,
+            &mut self.sleep_control_register,
+            &mut self.sleep_status_register,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let (Err(error), Ok(())) = (register.map(), result) {
+                result = Err(error);
+            }
+        }
+
+        result
+    }
+
+    // Returns true if the PM timer counts with 32 bits. Otherwise it wraps around after 24 bits.
+    #[inline]
+    pub fn pm_timer_is_32_bit(&self) -> bool {
+        self.flags.contains(FadtFlags::TIMER_VALUE_EXTENDED)
+    }
+
+    // Logs the power management hardware.
+    pub fn dump(&self) {
+        log::info!(
+            "FADT: revision {}, SCI IRQ {}, DSDT at {:?}, flags {:?}, boot architecture {:?}",
+            self.revision,
+            self.sci_interrupt,
+            self.dsdt,
+            self.flags,
+            self.boot_architecture
+        );
+
+        let registers = [
+            ("PM1a control", self.pm1a_control_block),
+            ("PM1b control", self.pm1b_control_block),
+            ("PM timer", self.pm_timer_block),
+            ("Reset register", self.reset_register),
+            ("Sleep control", self.sleep_control_register),
+            ("Sleep status", self.sleep_status_register),
+        ];
+        for (name, register) in registers.iter() {
+            if let Some(register) = register {
+                log::info!("  {}: {}", name, register);
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_parse_fadt() {
+    // An ACPI 1.0 FADT only has I/O port blocks.
+    let mut bytes = [0u8; 116];
+    bytes[8] = 1;
+    bytes[SCI_INTERRUPT] = 9;
+    bytes[PM1A_CONTROL_BLOCK..PM1A_CONTROL_BLOCK + 4].copy_from_slice(&0x604u32.to_le_bytes());
+    bytes[PM_TIMER_BLOCK..PM_TIMER_BLOCK + 4].copy_from_slice(&0x608u32.to_le_bytes());
+    bytes[PM1_CONTROL_LENGTH] = 2;
+    bytes[PM_TIMER_LENGTH] = 4;
+    bytes[CENTURY] = 0x32;
+
+    let fadt = Fadt::parse(&bytes).unwrap();
+    assert_eq!(fadt.sci_interrupt, 9);
+    assert_eq!(
+        fadt.pm1a_control_block,
+        Some(GenericAddress::io_port(0x604, 16))
+    );
+    assert_eq!(
+        fadt.pm_timer_block,
+        Some(GenericAddress::io_port(0x608, 32))
+    );
+    assert_eq!(fadt.pm1b_control_block, None);
+    assert_eq!(fadt.reset_register, None);
+    assert!(!fadt.pm_timer_is_32_bit());
+    assert_eq!(fadt.century_register, 0x32);
+
+    assert_eq!(Fadt::parse(&bytes[..64]), Err(AcpiError::InvalidLength));
+}
+
+#[test_case]
+fn test_parse_fadt_without_reset_value() {
+    let mut bytes = [0u8; RESET_VALUE + 1];
+    bytes[8] = 2;
+    bytes[FLAGS..FLAGS + 4]
+        .copy_from_slice(&FadtFlags::RESET_REGISTER_SUPPORTED.bits().to_le_bytes());
+    bytes[RESET_REGISTER] = 1;
+    bytes[RESET_REGISTER + 1] = 8;
+    bytes[RESET_REGISTER + 4..RESET_REGISTER + 12].copy_from_slice(&0xcf9u64.to_le_bytes());
+    bytes[RESET_VALUE] = 0x06;
+
+    let fadt = Fadt::parse(&bytes).unwrap();
+    assert_eq!(
+        fadt.reset_register.map(|register| register.address),
+        Some(0xcf9)
+    );
+    assert_eq!(fadt.reset_value, 0x06);
+
+    // The table is truncated right before the reset value.
+    let fadt = Fadt::parse(&bytes[..RESET_VALUE]).unwrap();
+    assert_eq!(fadt.reset_register, None);
+    assert_eq!(fadt.reset_value, 0);
+}
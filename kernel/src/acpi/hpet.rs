@@ -0,0 +1,52 @@
+use crate::acpi::sdt::{read_u16, read_u32, GenericAddress, SDT_HEADER_SIZE};
+use crate::acpi::AcpiError;
+
+// The HPET table describes the high precision event timer.
+// https://wiki.osdev.org/HPET#Detecting_HPET_using_ACPI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hpet {
+    pub hardware_revision: u8,
+    pub comparators: u8,
+    pub counter_is_64_bit: bool,
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+
+    // The memory mapped registers of the timer block.
+    pub address: GenericAddress,
+    pub hpet_number: u8,
+
+    // The minimum number of counter ticks between two periodic interrupts.
+    pub minimum_tick: u16,
+}
+
+impl Hpet {
+    pub fn parse(bytes: &[u8]) -> Result<Hpet, AcpiError> {
+        if bytes.len() < SDT_HEADER_SIZE + 20 {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        let event_timer_block_id = read_u32(bytes, SDT_HEADER_SIZE);
+        Ok(Hpet {
+            hardware_revision: event_timer_block_id as u8,
+            comparators: ((event_timer_block_id >> 8) & 0x1F) as u8 + 1,
+            counter_is_64_bit: event_timer_block_id & (1 << 13) != 0,
+            legacy_replacement: event_timer_block_id & (1 << 15) != 0,
+            pci_vendor_id: (event_timer_block_id >> 16) as u16,
+            address: GenericAddress::parse(bytes, SDT_HEADER_SIZE + 4)
+                .ok_or(AcpiError::InvalidLength)?,
+            hpet_number: bytes[SDT_HEADER_SIZE + 16],
+            minimum_tick: read_u16(bytes, SDT_HEADER_SIZE + 17),
+        })
+    }
+
+    pub fn dump(&self) {
+        log::info!(
+            "HPET {}: {}, {} comparators, 64 bit counter {}, minimum tick {}",
+            self.hpet_number,
+            self.address,
+            self.comparators,
+            self.counter_is_64_bit,
+            self.minimum_tick
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use alloc::vec::Vec;
+
+use crate::acpi::sdt::{read_u16, read_u32, read_u64, SDT_HEADER_SIZE};
+use crate::acpi::AcpiError;
+use crate::memory::paddr::PhysicalAddress;
+
+// The MADT entries follow the local APIC address and the flags.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+
+// The MADT flag telling that the machine has the legacy PICs as well.
+const PCAT_COMPAT: u32 = 1;
+
+// The local APIC flags telling that the processor is usable or can be brought online.
+const PROCESSOR_ENABLED: u32 = 1;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Processor {
+    // The ACPI processor UID, which identifies the processor in the DSDT.
+    pub processor_id: u32,
+    pub apic_id: u32,
+
+    // Disabled processors can't be started, unless they are online capable.
+    pub enabled: bool,
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IoApicEntry {
+    pub id: u8,
+    pub address: PhysicalAddress,
+
+    // The first global system interrupt handled by the I/O APIC.
+    pub gsi_base: u32,
+}
+
+// The polarity and trigger mode of an interrupt. Conforming interrupts follow the default of their
+// bus, which is active high and edge triggered for ISA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Polarity {
+    Conforming,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriggerMode {
+    Conforming,
+    Edge,
+    Level,
+}
+
+// Describes how an ISA IRQ is connected to a global system interrupt, when this is not identity
+// mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InterruptSourceOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+// The local APIC input a non-maskable interrupt is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LocalApicNmi {
+    // The ACPI processor UID, or 0xFF for all processors.
+    pub processor_id: u8,
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+// The multiple APIC description table lists the interrupt controllers of the machine.
+// https://wiki.osdev.org/MADT
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Madt {
+    pub local_apic_address: PhysicalAddress,
+    pub has_legacy_pics: bool,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicEntry>,
+    pub interrupt_source_overrides: Vec<InterruptSourceOverride>,
+    pub nmis: Vec<LocalApicNmi>,
+}
+
+impl Madt {
+    pub fn parse(bytes: &[u8]) -> Result<Madt, AcpiError> {
+        if bytes.len() < ENTRIES_OFFSET {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        let mut madt = Madt {
+            local_apic_address: PhysicalAddress::new(read_u32(bytes, SDT_HEADER_SIZE) as u64),
+            has_legacy_pics: read_u32(bytes, SDT_HEADER_SIZE + 4) & PCAT_COMPAT != 0,
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            interrupt_source_overrides: Vec::new(),
+            nmis: Vec::new(),
+        };
+
+        // Every entry starts with its type and length.
+        let mut offset = ENTRIES_OFFSET;
+        while offset + 2 <= bytes.len() {
+            let length = bytes[offset + 1] as usize;
+            if length < 2 || offset + length > bytes.len() {
+                return Err(AcpiError::InvalidLength);
+            }
+
+            madt.parse_entry(bytes[offset], &bytes[offset..offset + length])?;
+            offset += length;
+        }
+
+        Ok(madt)
+    }
+
+    fn parse_entry(&mut self, entry_type: u8, entry: &[u8]) -> Result<(), AcpiError> {
+        let minimum_length = match entry_type {
+            ENTRY_LOCAL_APIC => 8,
+            ENTRY_IO_APIC => 12,
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => 10,
+            ENTRY_LOCAL_APIC_NMI => 6,
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => 12,
+            ENTRY_LOCAL_X2APIC => 16,
+
+            // Entries the kernel does not use are skipped.
+            _ => return Ok(()),
+        };
+        if entry.len() < minimum_length {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        match entry_type {
+            ENTRY_LOCAL_APIC => {
+                let flags = read_u32(entry, 4);
+                self.processors.push(Processor {
+                    processor_id: entry[2] as u32,
+                    apic_id: entry[3] as u32,
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            ENTRY_IO_APIC => self.io_apics.push(IoApicEntry {
+                id: entry[2],
+                address: PhysicalAddress::new(read_u32(entry, 4) as u64),
+                gsi_base: read_u32(entry, 8),
+            }),
+            ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+                let (polarity, trigger_mode) = decode_mps_flags(read_u16(entry, 8));
+                self.interrupt_source_overrides
+                    .push(InterruptSourceOverride {
+                        irq: entry[3],
+                        gsi: read_u32(entry, 4),
+                        polarity,
+                        trigger_mode,
+                    });
+            }
+            ENTRY_LOCAL_APIC_NMI => {
+                let (polarity, trigger_mode) = decode_mps_flags(read_u16(entry, 3));
+                self.nmis.push(LocalApicNmi {
+                    processor_id: entry[2],
+                    lint: entry[5],
+                    polarity,
+                    trigger_mode,
+                });
+            }
+            ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                self.local_apic_address = PhysicalAddress::new(read_u64(entry, 4));
+            }
+            ENTRY_LOCAL_X2APIC => {
+                let flags = read_u32(entry, 8);
+                self.processors.push(Processor {
+                    processor_id: read_u32(entry, 12),
+                    apic_id: read_u32(entry, 4),
+                    enabled: flags & PROCESSOR_ENABLED != 0,
+                    online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                });
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    // Returns the override of the ISA IRQ, or None if the IRQ is identity mapped to a global system
+    // interrupt.
+    #[inline]
+    pub fn interrupt_source_override(&self, irq: u8) -> Option<&InterruptSourceOverride> {
+        self.interrupt_source_overrides
+            .iter()
+            .find(|source_override| source_override.irq == irq)
+    }
+
+    // Returns the processors that can be started.
+    #[inline]
+    pub fn usable_processors(&self) -> impl Iterator<Item = &Processor> {
+        self.processors
+            .iter()
+            .filter(|processor| processor.enabled || processor.online_capable)
+    }
+
+    // Logs the interrupt controllers.
+    pub fn dump(&self) {
+        log::info!(
+            "MADT: local APIC at {:?}, legacy PICs {}",
+            self.local_apic_address,
+            self.has_legacy_pics
+        );
+        for processor in self.processors.iter() {
+            log::info!(
+                "  Processor {}: APIC ID {}, enabled {}, online capable {}",
+                processor.processor_id,
+                processor.apic_id,
+                processor.enabled,
+                processor.online_capable
+            );
+        }
+        for io_apic in self.io_apics.iter() {
+            log::info!(
+                "  I/O APIC {}: {:?}, GSI base {}",
+                io_apic.id,
+                io_apic.address,
+                io_apic.gsi_base
+            );
+        }
+        for source_override in self.interrupt_source_overrides.iter() {
+            log::info!(
+                "  IRQ {} -> GSI {} ({:?}, {:?})",
+                source_override.irq,
+                source_override.gsi,
+                source_override.polarity,
+                source_override.trigger_mode
+            );
+        }
+        for nmi in self.nmis.iter() {
+            log::info!(
+                "  NMI on LINT{} of processor {:#x}",
+                nmi.lint,
+                nmi.processor_id
+            );
+        }
+    }
+}
+
+// Decodes the MPS INTI flags of interrupt source overrides and NMI entries.
+#[inline]
+fn decode_mps_flags(flags: u16) -> (Polarity, TriggerMode) {
+    let polarity = match flags & 0b11 {
+        0b01 => Polarity::ActiveHigh,
+        0b11 => Polarity::ActiveLow,
+        _ => Polarity::Conforming,
+    };
+    let trigger_mode = match (flags >> 2) & 0b11 {
+        0b01 => TriggerMode::Edge,
+        0b11 => TriggerMode::Level,
+        _ => TriggerMode::Conforming,
+    };
+
+    (polarity, trigger_mode)
+}
+
+#[test_case]
+fn test_parse_madt() {
+    let mut bytes = alloc::vec![0u8; ENTRIES_OFFSET];
+    bytes[SDT_HEADER_SIZE..SDT_HEADER_SIZE + 4].copy_from_slice(&0xFEE0_0000u32.to_le_bytes());
+    bytes[SDT_HEADER_SIZE + 4] = PCAT_COMPAT as u8;
+
+    // Two processors, the second one disabled.
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC, 8, 1, 1, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&[ENTRY_IO_APIC, 12, 0, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0]);
+
+    // The PIT is connected to GSI 2, and the SCI is level triggered and active low.
+    bytes.extend_from_slice(&[ENTRY_INTERRUPT_SOURCE_OVERRIDE, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&[
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE,
+        10,
+        0,
+        9,
+        9,
+        0,
+        0,
+        0,
+        0x0F,
+        0,
+    ]);
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC_NMI, 6, 0xFF, 0, 0, 1]);
+
+    // Unknown entries are skipped.
+    bytes.extend_from_slice(&[0x7F, 4, 0, 0]);
+
+    let madt = Madt::parse(&bytes).unwrap();
+    assert_eq!(madt.local_apic_address, PhysicalAddress::new(0xFEE0_0000));
+    assert!(madt.has_legacy_pics);
+    assert_eq!(madt.processors.len(), 2);
+    assert_eq!(madt.usable_processors().count(), 1);
+    assert_eq!(
+        madt.io_apics,
+        [IoApicEntry {
+            id: 0,
+            address: PhysicalAddress::new(0xFEC0_0000),
+            gsi_base: 0
+        }]
+    );
+    assert_eq!(madt.interrupt_source_override(0).unwrap().gsi, 2);
+    assert_eq!(
+        madt.interrupt_source_override(9),
+        Some(&InterruptSourceOverride {
+            irq: 9,
+            gsi: 9,
+            polarity: Polarity::ActiveLow,
+            trigger_mode: TriggerMode::Level
+        })
+    );
+    assert!(madt.interrupt_source_override(1).is_none());
+    assert_eq!(madt.nmis[0].lint, 1);
+
+    // An entry running past the end of the table is rejected.
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC, 8, 2]);
+    assert_eq!(Madt::parse(&bytes), Err(AcpiError::InvalidLength));
+}
@@ -0,0 +1,90 @@
+use alloc::vec::Vec;
+
+use crate::acpi::sdt::{read_u16, read_u64, SDT_HEADER_SIZE};
+use crate::acpi::AcpiError;
+use crate::memory::paddr::PhysicalAddress;
+
+// The entries follow 8 reserved bytes after the header.
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+// The memory mapped PCI Express configuration space of a range of buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct McfgEntry {
+    pub base_address: PhysicalAddress,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    // Returns the address of the 4 KiB configuration space of a PCI function, or None if the bus
+    // is not part of this entry.
+    #[inline]
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> Option<PhysicalAddress> {
+        if bus < self.start_bus || bus > self.end_bus || device >= 32 || function >= 8 {
+            return None;
+        }
+
+        let offset =
+            ((bus - self.start_bus) as u64) << 20 | (device as u64) << 15 | (function as u64) << 12;
+        Some(self.base_address + offset)
+    }
+}
+
+// The MCFG table lists where the PCI Express configuration space is mapped.
+// https://wiki.osdev.org/PCI_Express
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+impl Mcfg {
+    pub fn parse(bytes: &[u8]) -> Result<Mcfg, AcpiError> {
+        if bytes.len() < ENTRIES_OFFSET {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        let entries = bytes[ENTRIES_OFFSET..]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| McfgEntry {
+                base_address: PhysicalAddress::new(read_u64(entry, 0)),
+                segment_group: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+
+        Ok(Mcfg { entries })
+    }
+
+    pub fn dump(&self) {
+        for entry in self.entries.iter() {
+            log::info!(
+                "MCFG: segment {}, buses {}-{} at {:?}",
+                entry.segment_group,
+                entry.start_bus,
+                entry.end_bus,
+                entry.base_address
+            );
+        }
+    }
+}
+
+#[test_case]
+fn test_mcfg_config_address() {
+    let entry = McfgEntry {
+        base_address: PhysicalAddress::new(0xB000_0000),
+        segment_group: 0,
+        start_bus: 0,
+        end_bus: 0xFF,
+    };
+
+    assert_eq!(
+        entry.config_address(1, 2, 3),
+        Some(PhysicalAddress::new(
+            0xB000_0000 + (1 << 20) + (2 << 15) + (3 << 12)
+        ))
+    );
+    assert_eq!(entry.config_address(0, 32, 0), None);
+}
@@ -0,0 +1,221 @@
+// Support for the ACPI tables, which the firmware uses to describe the hardware of the machine that
+// can't be discovered otherwise, e.g. the interrupt controllers, the power management registers,
+// the HPET and the PCI Express configuration space. More info can be found at
+// https://wiki.osdev.org/ACPI.
+
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+
+use crate::acpi::dsdt::SleepType;
+use crate::acpi::fadt::Fadt;
+use crate::acpi::hpet::Hpet;
+use crate::acpi::madt::Madt;
+use crate::acpi::mcfg::Mcfg;
+use crate::acpi::rsdp::Rsdp;
+use crate::acpi::sdt::{root_table_entries, table_bytes, SdtHeader};
+use crate::memory::mmio::MmioError;
+use crate::memory::paddr::PhysicalAddress;
+
+pub mod dsdt;
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+pub mod pm_timer;
+pub mod rsdp;
+pub mod sdt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AcpiError {
+    // The structure does not start with the expected signature.
+    InvalidSignature,
+
+    // The bytes of the structure do not sum up to zero.
+    InvalidChecksum,
+
+    // The structure is shorter than its fields, or an entry runs past its end.
+    InvalidLength,
+
+    // The tables were already parsed.
+    AlreadyInitialized,
+
+    // A register lives in an address space the kernel can't access, e.g. PCI configuration space.
+    UnsupportedAddressSpace,
+
+    // A register in system memory could not be mapped.
+    Mmio(MmioError),
+
+    // A register in system memory was accessed before it was mapped.
+    RegisterNotMapped,
+}
+
+// A table listed by the RSDT or XSDT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TableInfo {
+    pub header: SdtHeader,
+    pub address: PhysicalAddress,
+}
+
+// The ACPI tables the kernel understands. Tables the firmware does not provide are None.
+#[derive(Debug)]
+pub struct AcpiTables {
+    pub rsdp: Rsdp,
+    pub tables: Vec<TableInfo>,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+
+    // The sleep types of the soft off state, taken from the DSDT.
+    pub s5: Option<SleepType>,
+}
+
+static ACPI_TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+
+// Returns the ACPI tables, or None if they were not found.
+#[inline]
+pub fn tables() -> Option<&'static AcpiTables> {
+    ACPI_TABLES.get()
+}
+
+// Returns the MADT, if the firmware provides one.
+#[inline]
+pub fn madt() -> Option<&'static Madt> {
+    tables().and_then(|tables| tables.madt.as_ref())
+}
+
+// Returns the FADT, if the firmware provides one.
+#[inline]
+pub fn fadt() -> Option<&'static Fadt> {
+    tables().and_then(|tables| tables.fadt.as_ref())
+}
+
+// Validates the RSDP and the root table at rsdp_address, and parses all tables they list. Tables
+// that fail to validate are skipped. The heap needs to be initialized.
+//
+// ## Safety
+// rsdp_address needs to be the RSDP address reported by the firmware, and the complete physical
+// memory needs to be mapped at paddr_offset.
+pub unsafe fn init(
+    rsdp_address: PhysicalAddress,
+    paddr_offset: u64,
+) -> Result<&'static AcpiTables, AcpiError> {
+    if ACPI_TABLES.is_initialized() {
+        return Err(AcpiError::AlreadyInitialized);
+    }
+
+    let rsdp = unsafe { Rsdp::read(rsdp_address, paddr_offset) }?;
+
+    // The XSDT replaces the RSDT since ACPI 2.0 and uses 64 bit addresses.
+    let (root_address, signature, entry_size) = match rsdp.xsdt_address {
+        Some(address) => (address, b"XSDT", 8),
+        None => (rsdp.rsdt_address, b"RSDT", 4),
+    };
+    let root = unsafe { table_bytes(root_address, paddr_offset) }?;
+    if &SdtHeader::parse(root)?.signature != signature {
+        return Err(AcpiError::InvalidSignature);
+    }
+
+    let mut tables = AcpiTables {
+        rsdp,
+        tables: Vec::new(),
+        madt: None,
+        fadt: None,
+        hpet: None,
+        mcfg: None,
+        s5: None,
+    };
+
+    for address in root_table_entries(root, entry_size) {
+        let parsed = unsafe { table_bytes(address, paddr_offset) }
+            .and_then(|bytes| tables.parse_table(address, bytes));
+        if let Err(error) = parsed {
+            log::info!("Skipping ACPI table at {:?}: {:?}", address, error);
+        }
+    }
+
+    // The DSDT is not listed by the root table, but referenced by the FADT.
+    if let Some(dsdt) = tables.fadt.map(|fadt| fadt.dsdt) {
+        let parsed = unsafe { table_bytes(dsdt, paddr_offset) }
+            .and_then(|bytes| tables.parse_table(dsdt, bytes));
+        if let Err(error) = parsed {
+            log::info!("Skipping DSDT at {:?}: {:?}", dsdt, error);
+        }
+    }
+
+    // The power management registers are used where the memory locks may be held, e.g. to reboot
+    // after a panic, so the ones in system memory are mapped up front.
+    if let Some(fadt) = tables.fadt.as_mut() {
+        if let Err(error) = fadt.map_registers() {
+            log::info!("FADT registers could not be mapped: {:?}", error);
+        }
+    }
+
+    ACPI_TABLES.init_once(|| tables);
+    Ok(ACPI_TABLES.get().unwrap())
+}
+
+impl AcpiTables {
+    fn parse_table(&mut self, address: PhysicalAddress, bytes: &[u8]) -> Result<(), AcpiError> {
+        let header = SdtHeader::parse(bytes)?;
+        match &header.signature {
+            b"APIC" => self.madt = Some(Madt::parse(bytes)?),
+            b"FACP" => self.fadt = Some(Fadt::parse(bytes)?),
+            b"HPET" => self.hpet = Some(Hpet::parse(bytes)?),
+            b"MCFG" => self.mcfg = Some(Mcfg::parse(bytes)?),
+            b"DSDT" => self.s5 = dsdt::parse_s5(bytes),
+            _ => {}
+        }
+
+        self.tables.push(TableInfo { header, address });
+        Ok(())
+    }
+
+    // Logs the tables and their contents.
+    pub fn dump(&self) {
+        log::info!(
+            "ACPI revision {}, OEM {}",
+            self.rsdp.revision,
+            core::str::from_utf8(&self.rsdp.oem_id)
+                .unwrap_or("")
+                .trim_end()
+        );
+        for table in self.tables.iter() {
+            log::info!(
+                "  {} at {:?}: {} bytes, revision {}, OEM {}",
+                table.header.signature(),
+                table.address,
+                table.header.length,
+                table.header.revision,
+                table.header.oem_id()
+            );
+        }
+
+        if let Some(madt) = &self.madt {
+            madt.dump();
+        }
+        if let Some(fadt) = &self.fadt {
+            fadt.dump();
+        }
+        if let Some(hpet) = &self.hpet {
+            hpet.dump();
+        }
+        if let Some(mcfg) = &self.mcfg {
+            mcfg.dump();
+        }
+        if let Some(s5) = &self.s5 {
+            log::info!("DSDT: S5 sleep types {:#x}, {:#x}", s5.pm1a, s5.pm1b);
+        }
+    }
+}
+
+#[test_case]
+fn test_acpi_tables_are_found() {
+    // QEMU provides an MADT and a FADT on every machine type.
+    let tables = tables().expect("ACPI tables were not parsed");
+    let madt = tables.madt.as_ref().unwrap();
+    assert!(madt.usable_processors().count() >= 1);
+    assert!(!madt.io_apics.is_empty());
+    assert!(tables.fadt.is_some());
+    assert!(tables.s5.is_some());
+}
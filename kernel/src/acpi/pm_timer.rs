@@ -0,0 +1,70 @@
+// The ACPI power management timer is a counter running at 3.579545 MHz, no matter the frequency of
+// the CPU. It needs no setup and raises no interrupts, so it is polled for short delays where
+// interrupts can't be used, e.g. while other CPUs are started or the machine is reset.
+
+use core::hint::spin_loop;
+
+use crate::acpi;
+use crate::acpi::sdt::GenericAddress;
+
+pub const PM_TIMER_FREQUENCY: u64 = 3_579_545;
+
+// A rough number of spin loop iterations per microsecond, used when there is no PM timer.
+const SPINS_PER_MICROSECOND: u64 = 100;
+
+// Returns the PM timer register and the mask of its counter bits, if it can be accessed.
+#[inline]
+fn pm_timer() -> Option<(GenericAddress, u64)> {
+    let fadt = acpi::fadt()?;
+    let register = fadt
+        .pm_timer_block
+        .filter(|register| register.is_accessible())?;
+
+    // The counter wraps around after 24 or 32 bits.
+    let mask = if fadt.pm_timer_is_32_bit() {
+        0xFFFF_FFFF
+    } else {
+        0xFF_FFFF
+    };
+
+    Some((register, mask))
+}
+
+// Returns true if the firmware describes a PM timer that can be polled.
+#[inline]
+pub fn is_available() -> bool {
+    pm_timer().is_some()
+}
+
+// Returns the current count, or None if there is no PM timer.
+#[inline]
+pub fn read() -> Option<u64> {
+    let (register, mask) = pm_timer()?;
+    unsafe { register.read() }.ok().map(|count| count & mask)
+}
+
+// Busy waits for about us microseconds. Without a PM timer, the CPU spins for a rough estimate.
+pub fn delay_us(us: u64) {
+    let (register, mask) = match pm_timer() {
+        Some(pm_timer) => pm_timer,
+        None => {
+            for _ in 0..us * SPINS_PER_MICROSECOND {
+                spin_loop();
+            }
+            return;
+        }
+    };
+
+    // Reading accessible registers can't fail.
+    let read = || unsafe { register.read() }.unwrap_or(0) & mask;
+    let ticks = us * PM_TIMER_FREQUENCY / 1_000_000;
+
+    let mut last = read();
+    let mut elapsed = 0;
+    while elapsed < ticks {
+        let now = read();
+        elapsed += now.wrapping_sub(last) & mask;
+        last = now;
+        spin_loop();
+    }
+}
@@ -0,0 +1,104 @@
+use crate::acpi::sdt::{checksum_is_valid, read_u32, read_u64};
+use crate::acpi::AcpiError;
+use crate::memory::paddr::PhysicalAddress;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+// The size of the ACPI 1.0 part of the RSDP, which the first checksum covers.
+const RSDP_V1_SIZE: usize = 20;
+
+// The size of the RSDP of ACPI 2.0 and later.
+const RSDP_V2_SIZE: usize = 36;
+
+// The root system description pointer, which the firmware hands to the bootloader. It points to
+// the RSDT, or on ACPI 2.0 and later to the XSDT, which list all other tables.
+// https://wiki.osdev.org/RSDP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rsdp {
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: PhysicalAddress,
+    pub xsdt_address: Option<PhysicalAddress>,
+}
+
+impl Rsdp {
+    // Reads the RSDP at paddr.
+    //
+    // ## Safety
+    // The complete physical memory needs to be mapped at paddr_offset.
+    pub unsafe fn read(paddr: PhysicalAddress, paddr_offset: u64) -> Result<Rsdp, AcpiError> {
+        let start = (paddr.address() + paddr_offset) as *const u8;
+        let bytes = unsafe { core::slice::from_raw_parts(start, RSDP_V1_SIZE) };
+
+        // The revision decides how large the structure is.
+        if bytes[15] < 2 {
+            return Rsdp::parse(bytes);
+        }
+
+        Rsdp::parse(unsafe { core::slice::from_raw_parts(start, RSDP_V2_SIZE) })
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Rsdp, AcpiError> {
+        if bytes.len() < RSDP_V1_SIZE {
+            return Err(AcpiError::InvalidLength);
+        }
+        if &bytes[0..8] != RSDP_SIGNATURE {
+            return Err(AcpiError::InvalidSignature);
+        }
+        if !checksum_is_valid(&bytes[..RSDP_V1_SIZE]) {
+            return Err(AcpiError::InvalidChecksum);
+        }
+
+        let mut rsdp = Rsdp {
+            oem_id: bytes[9..15].try_into().unwrap(),
+            revision: bytes[15],
+            rsdt_address: PhysicalAddress::new(read_u32(bytes, 16) as u64),
+            xsdt_address: None,
+        };
+        if rsdp.revision < 2 {
+            return Ok(rsdp);
+        }
+
+        if bytes.len() < RSDP_V2_SIZE || (read_u32(bytes, 20) as usize) < RSDP_V2_SIZE {
+            return Err(AcpiError::InvalidLength);
+        }
+        if !checksum_is_valid(&bytes[..RSDP_V2_SIZE]) {
+            return Err(AcpiError::InvalidChecksum);
+        }
+
+        let xsdt_address = read_u64(bytes, 24);
+        if xsdt_address != 0 {
+            rsdp.xsdt_address = Some(PhysicalAddress::new(xsdt_address));
+        }
+
+        Ok(rsdp)
+    }
+}
+
+#[test_case]
+fn test_parse_rsdp() {
+    let mut bytes = [0u8; RSDP_V2_SIZE];
+    bytes[0..8].copy_from_slice(RSDP_SIGNATURE);
+    bytes[9..15].copy_from_slice(b"BOCHS ");
+    bytes[16..20].copy_from_slice(&0x7FE_1234u32.to_le_bytes());
+
+    // The checksum covers the first 20 bytes.
+    bytes[8] = 0u8.wrapping_sub(
+        bytes[..RSDP_V1_SIZE]
+            .iter()
+            .fold(0u8, |a, b| a.wrapping_add(*b)),
+    );
+    let rsdp = Rsdp::parse(&bytes[..RSDP_V1_SIZE]).unwrap();
+    assert_eq!(rsdp.revision, 0);
+    assert_eq!(rsdp.rsdt_address, PhysicalAddress::new(0x7FE_1234));
+    assert_eq!(rsdp.xsdt_address, None);
+
+    bytes[8] = bytes[8].wrapping_add(1);
+    assert_eq!(
+        Rsdp::parse(&bytes[..RSDP_V1_SIZE]),
+        Err(AcpiError::InvalidChecksum)
+    );
+
+    bytes[0] = b'X';
+    assert_eq!(Rsdp::parse(&bytes), Err(AcpiError::InvalidSignature));
+}
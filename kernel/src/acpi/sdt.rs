@@ -0,0 +1,325 @@
+use core::fmt;
+use x86_64::instructions::port::Port;
+
+use crate::acpi::AcpiError;
+use crate::memory::mmio::map_mmio;
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::vaddr::VirtualAddress;
+
+// The size of the header every system description table starts with.
+pub const SDT_HEADER_SIZE: usize = 36;
+
+// The header shared by all system description tables.
+// https://wiki.osdev.org/RSDT#Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub fn parse(bytes: &[u8]) -> Result<SdtHeader, AcpiError> {
+        if bytes.len() < SDT_HEADER_SIZE {
+            return Err(AcpiError::InvalidLength);
+        }
+
+        Ok(SdtHeader {
+            signature: bytes[0..4].try_into().unwrap(),
+            length: read_u32(bytes, 4),
+            revision: bytes[8],
+            oem_id: bytes[10..16].try_into().unwrap(),
+            oem_table_id: bytes[16..24].try_into().unwrap(),
+            oem_revision: read_u32(bytes, 24),
+            creator_id: read_u32(bytes, 28),
+            creator_revision: read_u32(bytes, 32),
+        })
+    }
+
+    #[inline]
+    pub fn signature(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+
+    #[inline]
+    pub fn oem_id(&self) -> &str {
+        core::str::from_utf8(&self.oem_id).unwrap_or("").trim_end()
+    }
+}
+
+// The address space a generic address refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AddressSpace {
+    SystemMemory,
+    SystemIo,
+    PciConfiguration,
+    Other(u8),
+}
+
+// The location of a register, used by the ACPI tables to describe fixed hardware.
+// https://wiki.osdev.org/FADT#Generic_Address_Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GenericAddress {
+    pub address_space: AddressSpace,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+
+    // Where a register in system memory is mapped into the kernel address space, see map.
+    vaddr: Option<VirtualAddress>,
+}
+
+// The size of a generic address structure in the tables.
+pub const GENERIC_ADDRESS_SIZE: usize = 12;
+
+impl GenericAddress {
+    // Parses the generic address at offset. Returns None if it does not fit into the table or is
+    // zero, which the firmware uses for registers that do not exist.
+    pub fn parse(bytes: &[u8], offset: usize) -> Option<GenericAddress> {
+        if bytes.len() < offset + GENERIC_ADDRESS_SIZE {
+            return None;
+        }
+
+        let address = read_u64(bytes, offset + 4);
+        if address == 0 {
+            return None;
+        }
+
+        Some(GenericAddress {
+            address_space: match bytes[offset] {
+                0 => AddressSpace::SystemMemory,
+                1 => AddressSpace::SystemIo,
+                2 => AddressSpace::PciConfiguration,
+                other => AddressSpace::Other(other),
+            },
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address,
+            vaddr: None,
+        })
+    }
+
+    // A register in the I/O port space, as described by the fields of ACPI 1.0 tables.
+    #[inline]
+    pub fn io_port(port: u32, bit_width: u8) -> GenericAddress {
+        GenericAddress {
+            address_space: AddressSpace::SystemIo,
+            bit_width,
+            bit_offset: 0,
+            access_size: 0,
+            address: port as u64,
+            vaddr: None,
+        }
+    }
+
+    // Maps a register in system memory into the kernel address space, so that it can be accessed.
+    // Registers in the I/O port space need no mapping. Mapping takes the memory locks, which is why
+    // it is done once, before the register is used.
+    pub fn map(&mut self) -> Result<(), AcpiError> {
+        if self.address_space != AddressSpace::SystemMemory || self.vaddr.is_some() {
+            return Ok(());
+        }
+
+        let vaddr = map_mmio(PhysicalAddress::new(self.address), 8).map_err(AcpiError::Mmio)?;
+        self.vaddr = Some(vaddr);
+        Ok(())
+    }
+
+    // Returns true if the register can be read and written, i.e. it is an I/O port or a mapped
+    // register in system memory.
+    #[inline]
+    pub fn is_accessible(&self) -> bool {
+        match self.address_space {
+            AddressSpace::SystemIo => true,
+            AddressSpace::SystemMemory => self.vaddr.is_some(),
+            _ => false,
+        }
+    }
+
+    // The width of a single access in bits. Firmware often leaves the access size undefined, in
+    // which case the register is accessed as a whole.
+    #[inline]
+    fn access_width(&self) -> u8 {
+        match self.access_size {
+            1 => 8,
+            2 => 16,
+            3 => 32,
+            4 => 64,
+            _ => match self.bit_offset.saturating_add(self.bit_width) {
+                0..=8 => 8,
+                9..=16 => 16,
+                17..=32 => 32,
+                _ => 64,
+            },
+        }
+    }
+
+    // Reads the register. Registers in system memory need to be mapped first. No locks are taken, so
+    // registers can be accessed in any context, e.g. while panicking.
+    //
+    // ## Safety
+    // Reading device registers can have side effects.
+    pub unsafe fn read(&self) -> Result<u64, AcpiError> {
+        let value = match self.address_space {
+            // There are no 64 bit I/O ports.
+            AddressSpace::SystemIo => unsafe {
+                let port = self.address as u16;
+                match self.access_width() {
+                    8 => Port::<u8>::new(port).read() as u64,
+                    16 => Port::<u16>::new(port).read() as u64,
+                    _ => Port::<u32>::new(port).read() as u64,
+                }
+            },
+            AddressSpace::SystemMemory => {
+                let ptr = self.vaddr.ok_or(AcpiError::RegisterNotMapped)?.address();
+                unsafe {
+                    match self.access_width() {
+                        8 => core::ptr::read_volatile(ptr as *const u8) as u64,
+                        16 => core::ptr::read_volatile(ptr as *const u16) as u64,
+                        32 => core::ptr::read_volatile(ptr as *const u32) as u64,
+                        _ => core::ptr::read_volatile(ptr as *const u64),
+                    }
+                }
+            }
+            _ => return Err(AcpiError::UnsupportedAddressSpace),
+        };
+
+        Ok(value >> self.bit_offset)
+    }
+
+    // Writes value to the register. Like for reads, registers in system memory need to be mapped.
+    //
+    // ## Safety
+    // Writing device registers can have side effects, e.g. resetting the machine.
+    pub unsafe fn write(&self, value: u64) -> Result<(), AcpiError> {
+        let value = value << self.bit_offset;
+        match self.address_space {
+            AddressSpace::SystemIo => unsafe {
+                let port = self.address as u16;
+                match self.access_width() {
+                    8 => Port::<u8>::new(port).write(value as u8),
+                    16 => Port::<u16>::new(port).write(value as u16),
+                    _ => Port::<u32>::new(port).write(value as u32),
+                }
+            },
+            AddressSpace::SystemMemory => {
+                let ptr = self.vaddr.ok_or(AcpiError::RegisterNotMapped)?.address();
+                unsafe {
+                    match self.access_width() {
+                        8 => core::ptr::write_volatile(ptr as *mut u8, value as u8),
+                        16 => core::ptr::write_volatile(ptr as *mut u16, value as u16),
+                        32 => core::ptr::write_volatile(ptr as *mut u32, value as u32),
+                        _ => core::ptr::write_volatile(ptr as *mut u64, value),
+                    }
+                }
+            }
+            _ => return Err(AcpiError::UnsupportedAddressSpace),
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for GenericAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address_space {
+            AddressSpace::SystemMemory => write!(f, "memory {:#x}", self.address),
+            AddressSpace::SystemIo => write!(f, "port {:#x}", self.address),
+            AddressSpace::PciConfiguration => write!(f, "PCI configuration {:#x}", self.address),
+            AddressSpace::Other(space) => write!(f, "space {} {:#x}", space, self.address),
+        }
+    }
+}
+
+// Returns true if the bytes sum up to zero, which is how ACPI structures are checksummed.
+#[inline]
+pub fn checksum_is_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+// Returns the table at paddr after checking its length and checksum.
+//
+// ## Safety
+// paddr needs to point to a system description table, and the complete physical memory needs to be
+// mapped at paddr_offset.
+pub unsafe fn table_bytes(
+    paddr: PhysicalAddress,
+    paddr_offset: u64,
+) -> Result<&'static [u8], AcpiError> {
+    let start = (paddr.address() + paddr_offset) as *const u8;
+    let header = unsafe { core::slice::from_raw_parts(start, SDT_HEADER_SIZE) };
+    let length = SdtHeader::parse(header)?.length as usize;
+    if length < SDT_HEADER_SIZE {
+        return Err(AcpiError::InvalidLength);
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(start, length) };
+    if !checksum_is_valid(bytes) {
+        return Err(AcpiError::InvalidChecksum);
+    }
+
+    Ok(bytes)
+}
+
+// Returns the table addresses listed by the RSDT, which uses 4 byte entries, or the XSDT, which
+// uses 8 byte entries.
+pub fn root_table_entries(
+    bytes: &[u8],
+    entry_size: usize,
+) -> impl Iterator<Item = PhysicalAddress> + '_ {
+    bytes[SDT_HEADER_SIZE..]
+        .chunks_exact(entry_size)
+        .map(move |entry| {
+            PhysicalAddress::new(match entry_size {
+                4 => read_u32(entry, 0) as u64,
+                _ => read_u64(entry, 0),
+            })
+        })
+}
+
+// Tables are little endian and their fields are not aligned.
+#[inline]
+pub fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+#[inline]
+pub fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline]
+pub fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test_case]
+fn test_memory_register_is_mapped_once() {
+    // The version register of the local APIC, which can be read without side effects.
+    let mut bytes = [0u8; GENERIC_ADDRESS_SIZE];
+    bytes[1] = 32;
+    bytes[3] = 3;
+    bytes[4..].copy_from_slice(&0x_FEE0_0030u64.to_le_bytes());
+    let mut register = GenericAddress::parse(&bytes, 0).unwrap();
+    assert_eq!(register.address_space, AddressSpace::SystemMemory);
+
+    assert!(!register.is_accessible());
+    assert_eq!(
+        unsafe { register.read() },
+        Err(AcpiError::RegisterNotMapped)
+    );
+
+    register.map().unwrap();
+    let vaddr = register.vaddr;
+    register.map().unwrap();
+    assert_eq!(register.vaddr, vaddr);
+    assert!(register.is_accessible());
+    assert!(unsafe { register.read() }.is_ok());
+}
@@ -0,0 +1,320 @@
+// Support for the local APIC. Every CPU has a local advanced programmable interrupt controller,
+// which receives interrupts from the I/O APIC and other CPUs, and has a timer of its own. More
+// info can be found at https://wiki.osdev.org/APIC.
+//
+// The registers are accessed through a page of memory mapped registers (xAPIC mode), or through
+// MSRs if the CPU supports x2APIC mode. Every CPU sees its own local APIC at the same address, so
+// one LocalApic serves all CPUs.
+
+use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
+use core::hint::spin_loop;
+
+use crate::interrupts::idt::IdtIndex;
+use crate::memory::mmio::{map_mmio, MmioError};
+use crate::memory::vaddr::VirtualAddress;
+use crate::registers::cpuid::{self, CpuFeatures};
+use crate::registers::model_specific::{ApicBase, ApicBaseFlags, Msr};
+
+// Register offsets in the memory mapped register page. In x2APIC mode, register offset / 16 is
+// the offset of the MSR from X2APIC_MSR_BASE.
+const REGISTER_ID: u32 = 0x20;
+const REGISTER_VERSION: u32 = 0x30;
+const REGISTER_TASK_PRIORITY: u32 = 0x80;
+const REGISTER_EOI: u32 = 0xB0;
+const REGISTER_SPURIOUS_INTERRUPT_VECTOR: u32 = 0xF0;
+const REGISTER_ERROR_STATUS: u32 = 0x280;
+const REGISTER_INTERRUPT_COMMAND: u32 = 0x300;
+const REGISTER_INTERRUPT_COMMAND_HIGH: u32 = 0x310;
+const REGISTER_LVT_TIMER: u32 = 0x320;
+const REGISTER_LVT_LINT0: u32 = 0x350;
+const REGISTER_LVT_LINT1: u32 = 0x360;
+const REGISTER_LVT_ERROR: u32 = 0x370;
+const REGISTER_TIMER_INITIAL_COUNT: u32 = 0x380;
+const REGISTER_TIMER_CURRENT_COUNT: u32 = 0x390;
+const REGISTER_TIMER_DIVIDE_CONFIGURATION: u32 = 0x3E0;
+
+const X2APIC_MSR_BASE: u32 = 0x800;
+const REGISTER_PAGE_SIZE: u64 = 0x400;
+
+// Setting this bit in the spurious interrupt vector register enables the local APIC.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+// Interprocessor interrupts other than INIT deassert need to be sent with the level asserted.
+const IPI_LEVEL_ASSERT: u32 = 1 << 14;
+
+// https://wiki.osdev.org/APIC#Local_Vector_Table_Registers
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct LvtFlags: u32 {
+        const DELIVERY_MODE_NMI = 0b100 << 8;
+        const DELIVERY_MODE_EXTINT = 0b111 << 8;
+        const DELIVERY_PENDING = 1 << 12;
+        const ACTIVE_LOW = 1 << 13;
+        const LEVEL_TRIGGERED = 1 << 15;
+        const MASKED = 1 << 16;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum TimerMode {
+    // The timer counts down from the initial count once.
+    OneShot = 0b00 << 17,
+
+    // The timer reloads the initial count whenever it reaches zero.
+    Periodic = 0b01 << 17,
+
+    // The timer fires when the TSC reaches the value written to the TSC deadline MSR.
+    TscDeadline = 0b10 << 17,
+}
+
+// The timer counts at the bus frequency divided by this value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum TimerDivide {
+    By1 = 0b1011,
+    By2 = 0b0000,
+    By4 = 0b0001,
+    By8 = 0b0010,
+    By16 = 0b0011,
+    By32 = 0b1000,
+    By64 = 0b1001,
+    By128 = 0b1010,
+}
+
+// How an interprocessor interrupt is handled by the receiving CPU.
+// https://wiki.osdev.org/APIC#Interrupt_Command_Register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum IpiDeliveryMode {
+    // The interrupt is delivered on the given vector.
+    Fixed = 0b000 << 8,
+    Nmi = 0b100 << 8,
+
+    // Resets the CPU, which then waits for a startup IPI.
+    Init = 0b101 << 8,
+
+    // Starts a CPU waiting after INIT in real mode, at the start of the frame given as vector.
+    StartUp = 0b110 << 8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ApicError {
+    // The CPU has no local APIC.
+    NotSupported,
+
+    // The register page could not be mapped.
+    Mmio(MmioError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Registers {
+    XApic(VirtualAddress),
+    X2Apic,
+}
+
+#[derive(Debug)]
+pub struct LocalApic {
+    registers: Registers,
+}
+
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+
+// Returns the local APIC, or None if the APIC is not in use and interrupts are still handled by
+// the PICs.
+#[inline]
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+impl LocalApic {
+    // Switches the local APIC of the calling CPU to x2APIC mode if supported, or maps its register
+    // page otherwise.
+    fn new() -> Result<LocalApic, ApicError> {
+        if !cpuid::has(CpuFeatures::APIC) {
+            return Err(ApicError::NotSupported);
+        }
+
+        let (frame, flags) = ApicBase::read();
+        if cpuid::has(CpuFeatures::X2APIC) {
+            unsafe {
+                ApicBase::write(
+                    frame,
+                    flags | ApicBaseFlags::GLOBAL_ENABLE | ApicBaseFlags::X2APIC_ENABLE,
+                )
+            };
+            return Ok(LocalApic {
+                registers: Registers::X2Apic,
+            });
+        }
+
+        unsafe { ApicBase::write(frame, flags | ApicBaseFlags::GLOBAL_ENABLE) };
+        let base = map_mmio(frame.start_address(), REGISTER_PAGE_SIZE).map_err(ApicError::Mmio)?;
+        Ok(LocalApic {
+            registers: Registers::XApic(base),
+        })
+    }
+
+    // Enables the local APIC of the calling CPU. Every CPU needs to call this once.
+    pub fn enable(&self) {
+        if self.registers == Registers::X2Apic {
+            let (frame, flags) = ApicBase::read();
+            unsafe {
+                ApicBase::write(
+                    frame,
+                    flags | ApicBaseFlags::GLOBAL_ENABLE | ApicBaseFlags::X2APIC_ENABLE,
+                )
+            };
+        }
+
+        unsafe {
+            // LINT0 and LINT1 are wired to the PICs and NMI sources. All device interrupts come
+            // through the I/O APIC instead.
+            self.write(REGISTER_LVT_LINT0, LvtFlags::MASKED.bits());
+            self.write(REGISTER_LVT_LINT1, LvtFlags::DELIVERY_MODE_NMI.bits());
+            self.write(REGISTER_LVT_TIMER, LvtFlags::MASKED.bits());
+            self.write(REGISTER_LVT_ERROR, IdtIndex::ApicErrorInterruptIndex as u32);
+
+            // Writing the error status register latches the current errors, which clears them.
+            self.write(REGISTER_ERROR_STATUS, 0);
+            self.write(REGISTER_ERROR_STATUS, 0);
+
+            // Accept interrupts of every priority.
+            self.write(REGISTER_TASK_PRIORITY, 0);
+            self.write(
+                REGISTER_SPURIOUS_INTERRUPT_VECTOR,
+                SOFTWARE_ENABLE | IdtIndex::ApicSpuriousInterruptIndex as u32,
+            );
+        }
+    }
+
+    // The ID of the local APIC of the calling CPU.
+    #[inline]
+    pub fn id(&self) -> u32 {
+        let id = unsafe { self.read(REGISTER_ID) };
+        match self.registers {
+            Registers::XApic(_) => id >> 24,
+            Registers::X2Apic => id,
+        }
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        unsafe { self.read(REGISTER_VERSION) as u8 }
+    }
+
+    #[inline]
+    pub fn is_x2apic(&self) -> bool {
+        self.registers == Registers::X2Apic
+    }
+
+    // Signals the end of the current interrupt. Interrupt handlers need to call this before they
+    // return, otherwise the local APIC does not deliver interrupts of the same or lower priority.
+    #[inline]
+    pub fn end_of_interrupt(&self) {
+        unsafe { self.write(REGISTER_EOI, 0) };
+    }
+
+    // Reads and clears the errors the local APIC detected while sending or receiving interrupts.
+    #[inline]
+    pub fn error_status(&self) -> u32 {
+        unsafe {
+            self.write(REGISTER_ERROR_STATUS, 0);
+            self.read(REGISTER_ERROR_STATUS)
+        }
+    }
+
+    // Sends an interprocessor interrupt to the CPU with the given local APIC ID. The vector is
+    // ignored by INIT IPIs.
+    pub fn send_ipi(&self, destination: u32, mode: IpiDeliveryMode, vector: u8) {
+        let command = mode as u32 | IPI_LEVEL_ASSERT | vector as u32;
+        match self.registers {
+            Registers::XApic(_) => unsafe {
+                self.write(REGISTER_INTERRUPT_COMMAND_HIGH, destination << 24);
+                self.write(REGISTER_INTERRUPT_COMMAND, command);
+
+                // The next IPI can only be sent once this one was accepted.
+                while self.read(REGISTER_INTERRUPT_COMMAND) & LvtFlags::DELIVERY_PENDING.bits() != 0
+                {
+                    spin_loop();
+                }
+            },
+
+            // The x2APIC takes the destination and command with a single write, and sends IPIs
+            // without delay.
+            Registers::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + REGISTER_INTERRUPT_COMMAND / 16)
+                    .write((destination as u64) << 32 | command as u64)
+            },
+        }
+    }
+
+    // Starts the timer of the calling CPU. It raises an interrupt on the given vector when it
+    // counts down from initial_count to zero.
+    pub fn start_timer(
+        &self,
+        vector: IdtIndex,
+        mode: TimerMode,
+        divide: TimerDivide,
+        initial_count: u32,
+    ) {
+        unsafe {
+            self.write(REGISTER_TIMER_DIVIDE_CONFIGURATION, divide as u32);
+            self.write(REGISTER_LVT_TIMER, mode as u32 | vector as u32);
+            self.write(REGISTER_TIMER_INITIAL_COUNT, initial_count);
+        }
+    }
+
+    #[inline]
+    pub fn stop_timer(&self) {
+        unsafe {
+            self.write(REGISTER_LVT_TIMER, LvtFlags::MASKED.bits());
+            self.write(REGISTER_TIMER_INITIAL_COUNT, 0);
+        }
+    }
+
+    #[inline]
+    pub fn timer_current_count(&self) -> u32 {
+        unsafe { self.read(REGISTER_TIMER_CURRENT_COUNT) }
+    }
+
+    // ## Safety
+    // The register needs to exist and be readable.
+    #[inline]
+    unsafe fn read(&self, register: u32) -> u32 {
+        match self.registers {
+            Registers::XApic(base) => unsafe {
+                ((base.address() + register as u64) as *const u32).read_volatile()
+            },
+            Registers::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + register / 16).read() as u32 },
+        }
+    }
+
+    // ## Safety
+    // The register needs to exist and be writable. Writes can change how interrupts are delivered.
+    #[inline]
+    unsafe fn write(&self, register: u32, value: u32) {
+        match self.registers {
+            Registers::XApic(base) => unsafe {
+                ((base.address() + register as u64) as *mut u32).write_volatile(value)
+            },
+            Registers::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + register / 16).write(value as u64)
+            },
+        }
+    }
+}
+
+// Sets up the local APIC of the bootstrap processor and enables it. Returns the local APIC, which
+// stays in use from here on.
+pub fn init_local_apic() -> Result<&'static LocalApic, ApicError> {
+    if !LOCAL_APIC.is_initialized() {
+        let local_apic = LocalApic::new()?;
+        LOCAL_APIC.init_once(|| local_apic);
+    }
+
+    let local_apic = LOCAL_APIC.get().unwrap();
+    local_apic.enable();
+    Ok(local_apic)
+}
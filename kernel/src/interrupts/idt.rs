@@ -56,6 +56,15 @@ pub enum IdtIndex {
     ControlProtectionExceptionInterruptIndex = 21,
     TimerInterruptIndex = 104,
     KeyboardInterruptIndex = 105,
+
+    // IRQ 8, the first input of the secondary PIC.
+    RtcInterruptIndex = 112,
+
+    // Vectors of the local APIC. The spurious vector needs to have its lowest 4 bits set on older
+    // processors.
+    ApicTimerInterruptIndex = 240,
+    ApicErrorInterruptIndex = 254,
+    ApicSpuriousInterruptIndex = 255,
 }
 
 #[derive(Clone, Debug)]
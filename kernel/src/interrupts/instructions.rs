@@ -1,3 +1,5 @@
+use crate::registers::rflags::{RFlags, RFLAGS};
+
 pub fn run_without_interrupts<Callback, Return>(callback: Callback) -> Return
 where
     Callback: FnOnce() -> Return,
@@ -25,22 +27,13 @@ fn enable_interrupts() {
 }
 
 #[inline]
-fn disable_interrupts() {
+pub fn disable_interrupts() {
     unsafe {
         core::arch::asm!("cli", options(preserves_flags, nostack));
     }
 }
 
 #[inline]
-fn are_interrupts_enabled() -> bool {
-    let rflags: u64;
-    unsafe {
-        core::arch::asm!(
-            "pushfq",
-            "pop {}",
-            out(reg) rflags,
-            options(nomem, preserves_flags)
-        );
-    }
-    (rflags & (1 << 9)) != 0
+pub fn are_interrupts_enabled() -> bool {
+    RFLAGS::read().contains(RFlags::INTERRUPT_FLAG)
 }
@@ -0,0 +1,204 @@
+// Support for the I/O APIC. The I/O APIC receives the interrupts of devices and forwards them to
+// the local APIC of a CPU, using a redirection table with one entry per input pin. More info can
+// be found at https://wiki.osdev.org/IOAPIC.
+
+use bitflags::bitflags;
+
+use crate::memory::mmio::{map_mmio, MmioError};
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::vaddr::VirtualAddress;
+
+// The I/O APIC is accessed through an index register selecting one of its registers, and a data
+// register giving access to the selected register.
+const REGISTER_SELECT: u64 = 0x00;
+const REGISTER_WINDOW: u64 = 0x10;
+const REGISTER_PAGE_SIZE: u64 = 0x20;
+
+const REGISTER_ID: u32 = 0x00;
+const REGISTER_VERSION: u32 = 0x01;
+const REGISTER_REDIRECTION_TABLE: u32 = 0x10;
+
+// The physical address of the first I/O APIC on PC compatible machines.
+pub const DEFAULT_IO_APIC_ADDRESS: u64 = 0x_FEC0_0000;
+
+// https://wiki.osdev.org/IOAPIC#IOREDTBL
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct RedirectionFlags: u64 {
+        const DELIVERY_MODE_LOWEST_PRIORITY = 0b001 << 8;
+        const DELIVERY_MODE_NMI = 0b100 << 8;
+
+        // The destination is a set of logical APIC IDs instead of a single APIC ID
+        const LOGICAL_DESTINATION = 1 << 11;
+
+        const DELIVERY_PENDING = 1 << 12;
+        const ACTIVE_LOW = 1 << 13;
+        const LEVEL_TRIGGERED = 1 << 15;
+        const MASKED = 1 << 16;
+    }
+}
+
+// An entry of the redirection table, describing how the interrupt on one input pin is delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RedirectionEntry {
+    pub vector: u8,
+    pub flags: RedirectionFlags,
+
+    // The APIC ID of the CPU receiving the interrupt.
+    pub destination: u8,
+}
+
+impl RedirectionEntry {
+    #[inline]
+    fn from_bits(value: u64) -> RedirectionEntry {
+        RedirectionEntry {
+            vector: value as u8,
+            flags: RedirectionFlags::from_bits_truncate(value),
+            destination: (value >> 56) as u8,
+        }
+    }
+
+    #[inline]
+    fn bits(&self) -> u64 {
+        self.vector as u64 | self.flags.bits() | (self.destination as u64) << 56
+    }
+}
+
+#[derive(Debug)]
+pub struct IoApic {
+    base: VirtualAddress,
+
+    // The first global system interrupt handled by this I/O APIC. Input pin n receives global
+    // system interrupt gsi_base + n.
+    gsi_base: u32,
+}
+
+impl IoApic {
+    // Maps the registers of the I/O APIC at paddr and masks all of its interrupts.
+    pub fn new(paddr: PhysicalAddress, gsi_base: u32) -> Result<IoApic, MmioError> {
+        let base = map_mmio(paddr, REGISTER_PAGE_SIZE)?;
+        let mut io_apic = IoApic { base, gsi_base };
+
+        for pin in 0..io_apic.num_pins() {
+            let mut entry = io_apic.read_entry(pin);
+            entry.flags |= RedirectionFlags::MASKED;
+            unsafe { io_apic.write_entry(pin, entry) };
+        }
+
+        Ok(io_apic)
+    }
+
+    #[inline]
+    pub fn id(&mut self) -> u8 {
+        (unsafe { self.read(REGISTER_ID) } >> 24) as u8 & 0xF
+    }
+
+    #[inline]
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    // The number of input pins, and thus redirection table entries.
+    #[inline]
+    pub fn num_pins(&mut self) -> u8 {
+        ((unsafe { self.read(REGISTER_VERSION) } >> 16) as u8).wrapping_add(1)
+    }
+
+    // Returns true if the global system interrupt is connected to this I/O APIC.
+    #[inline]
+    pub fn handles_gsi(&mut self, gsi: u32) -> bool {
+        self.gsi_base <= gsi && gsi < self.gsi_base + self.num_pins() as u32
+    }
+
+    #[inline]
+    pub fn read_entry(&mut self, pin: u8) -> RedirectionEntry {
+        let register = REGISTER_REDIRECTION_TABLE + 2 * pin as u32;
+        let value = unsafe { (self.read(register + 1) as u64) << 32 | self.read(register) as u64 };
+        RedirectionEntry::from_bits(value)
+    }
+
+    // ## Safety
+    // The vector needs an interrupt handler that signals the end of the interrupt to the local
+    // APIC.
+    #[inline]
+    pub unsafe fn write_entry(&mut self, pin: u8, entry: RedirectionEntry) {
+        let register = REGISTER_REDIRECTION_TABLE + 2 * pin as u32;
+        let value = entry.bits();
+
+        // Mask the pin while the entry is half written.
+        unsafe {
+            self.write(register, RedirectionFlags::MASKED.bits() as u32);
+            self.write(register + 1, (value >> 32) as u32);
+            self.write(register, value as u32);
+        }
+    }
+
+    // Delivers the global system interrupt to the vector of the CPU with the given APIC ID. Returns
+    // false if the interrupt is not connected to this I/O APIC.
+    //
+    // ## Safety
+    // Same as write_entry.
+    pub unsafe fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        destination: u8,
+        flags: RedirectionFlags,
+    ) -> bool {
+        if !self.handles_gsi(gsi) {
+            return false;
+        }
+
+        let entry = RedirectionEntry {
+            vector,
+            flags,
+            destination,
+        };
+        unsafe { self.write_entry((gsi - self.gsi_base) as u8, entry) };
+        true
+    }
+
+    #[inline]
+    pub fn mask(&mut self, pin: u8) {
+        let mut entry = self.read_entry(pin);
+        entry.flags |= RedirectionFlags::MASKED;
+        unsafe { self.write_entry(pin, entry) };
+    }
+
+    // ## Safety
+    // Same as write_entry.
+    #[inline]
+    pub unsafe fn unmask(&mut self, pin: u8) {
+        let mut entry = self.read_entry(pin);
+        entry.flags -= RedirectionFlags::MASKED;
+        unsafe { self.write_entry(pin, entry) };
+    }
+
+    #[inline]
+    unsafe fn read(&mut self, register: u32) -> u32 {
+        unsafe {
+            ((self.base.address() + REGISTER_SELECT) as *mut u32).write_volatile(register);
+            ((self.base.address() + REGISTER_WINDOW) as *const u32).read_volatile()
+        }
+    }
+
+    #[inline]
+    unsafe fn write(&mut self, register: u32, value: u32) {
+        unsafe {
+            ((self.base.address() + REGISTER_SELECT) as *mut u32).write_volatile(register);
+            ((self.base.address() + REGISTER_WINDOW) as *mut u32).write_volatile(value);
+        }
+    }
+}
+
+#[test_case]
+fn test_redirection_entry_bits() {
+    let entry = RedirectionEntry {
+        vector: 0x68,
+        flags: RedirectionFlags::LEVEL_TRIGGERED | RedirectionFlags::ACTIVE_LOW,
+        destination: 3,
+    };
+
+    assert_eq!(entry.bits(), 0x0300_0000_0000_A068);
+    assert_eq!(RedirectionEntry::from_bits(entry.bits()), entry);
+}
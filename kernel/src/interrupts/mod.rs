@@ -1,23 +1,37 @@
+use conquer_once::spin::OnceCell;
 use lazy_static::lazy_static;
 
+use crate::acpi;
+use crate::acpi::madt::{Polarity, TriggerMode};
+use crate::interrupts::apic::{local_apic, ApicError};
 use crate::interrupts::idt::IdtIndex;
+use crate::interrupts::ioapic::{IoApic, RedirectionFlags, DEFAULT_IO_APIC_ADDRESS};
 use crate::interrupts::pic::Pics;
 use crate::interrupts::tss::load_tss;
 use crate::kprint;
 
+use crate::memory::fault::resolve_page_fault;
+use crate::memory::paddr::PhysicalAddress;
 use crate::memory::page_table::PageFaultErrorCodes;
+use crate::memory::stack::KernelStack;
 use crate::memory::vaddr::VirtualAddress;
+use crate::memory::vma::{AreaKind, KERNEL_AREAS};
+
+use crate::smp::percpu::{self, this_cpu, BSP_CPU_ID};
+use crate::time;
 
 use crate::registers::control::CR2;
 use crate::registers::segment::{Segment, SegmentSelector, CS, DS, ES, FS, GS, SS};
 
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 
+pub mod apic;
 pub mod dtp;
 pub mod gdt;
 pub mod idt;
 pub mod instructions;
+pub mod ioapic;
 pub mod pic;
 pub mod privilege;
 pub mod tss;
@@ -188,6 +202,20 @@ lazy_static! {
 
         idt.add_interrupt_handler(IdtIndex::TimerInterruptIndex, handler!(timer_interrupt_handler));
         idt.add_interrupt_handler(IdtIndex::KeyboardInterruptIndex, handler!(keyboard_interrupt_handler));
+        idt.add_interrupt_handler(IdtIndex::RtcInterruptIndex, handler!(rtc_interrupt_handler));
+
+        idt.add_interrupt_handler(
+            IdtIndex::ApicTimerInterruptIndex,
+            handler!(apic_timer_interrupt_handler),
+        );
+        idt.add_interrupt_handler(
+            IdtIndex::ApicErrorInterruptIndex,
+            handler!(apic_error_interrupt_handler),
+        );
+        idt.add_interrupt_handler(
+            IdtIndex::ApicSpuriousInterruptIndex,
+            handler!(apic_spurious_interrupt_handler),
+        );
 
         idt
     };
@@ -199,17 +227,19 @@ struct Selectors {
     tss_selector: SegmentSelector,
 }
 
-struct GdtContainer {
+// Every CPU has a GDT of its own, kept in its per-CPU data, as the TSS holds the stacks the CPU
+// switches to, and a TSS marked busy by one CPU can't be loaded by another.
+pub(crate) struct GdtContainer {
     table: gdt::GlobalDescriptorTable,
     selectors: Selectors,
 }
 
-lazy_static! {
-    static ref GDT: GdtContainer = {
+impl GdtContainer {
+    pub(crate) fn new(tss: &'static tss::TaskStateSegment) -> GdtContainer {
         let mut gdt = gdt::GlobalDescriptorTable::new();
         let code_selector = gdt.add(gdt::Descriptor::kernel_code_segment());
         let data_selector = gdt.add(gdt::Descriptor::kernel_data_segment());
-        let tss_selector = gdt.add(gdt::Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add(gdt::Descriptor::tss_segment(tss));
 
         GdtContainer {
             table: gdt,
@@ -219,26 +249,19 @@ lazy_static! {
                 tss_selector,
             },
         }
-    };
+    }
 }
 
+// The double fault handler runs on its own stack, so that it can handle kernel stack overflows.
+// The stack has a guard page, so an overflow of the double fault stack itself faults instead of
+// overwriting the memory below it.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub(crate) const DOUBLE_FAULT_STACK_PAGES: u64 = 5;
 
-lazy_static! {
-    static ref TSS: tss::TaskStateSegment = {
-        let mut tss = tss::TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            // This needs to be a static mut. If this was immutable, the bootloader would make it
-            // a read only page.
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtualAddress::from_ptr(&raw const STACK);
-            let stack_end = stack_start + STACK_SIZE as u64;
-            stack_end
-        };
-        tss
-    };
+// The stack the double fault handler of the calling CPU runs on.
+#[inline]
+pub fn double_fault_stack() -> &'static KernelStack {
+    this_cpu().double_fault_stack()
 }
 
 lazy_static! {
@@ -255,60 +278,218 @@ pub const SECONDARY_PIC_OFFSET: u8 = 112;
 pub static PICS: spin::Mutex<Pics> =
     spin::Mutex::new(unsafe { Pics::new(PRIMARY_PIC_OFFSET, SECONDARY_PIC_OFFSET) });
 
+// The ISA IRQs of the PIT, the PS/2 keyboard and the RTC.
+const TIMER_IRQ: u8 = 0;
+const KEYBOARD_IRQ: u8 = 1;
+const RTC_IRQ: u8 = 8;
+
+// The vectors the ISA IRQs are delivered on, the same the PICs use.
+const ISA_ROUTES: [(u8, IdtIndex); 3] = [
+    (TIMER_IRQ, IdtIndex::TimerInterruptIndex),
+    (KEYBOARD_IRQ, IdtIndex::KeyboardInterruptIndex),
+    (RTC_IRQ, IdtIndex::RtcInterruptIndex),
+];
+
+static IO_APIC: OnceCell<Mutex<IoApic>> = OnceCell::uninit();
+
+// Returns the I/O APIC, or None if it was not set up yet.
 #[inline]
-pub fn testonly_gdt_init() {
-    GDT.table.load();
+pub fn io_apic() -> Option<MutexGuard<'static, IoApic>> {
+    IO_APIC.get().map(|io_apic| io_apic.lock())
+}
+
+// Returns the global system interrupt an ISA IRQ is connected to and how it is signaled. The MADT
+// lists the IRQs that are not identity mapped. Without ACPI tables, a PC compatible machine is
+// assumed, which connects the PIT to pin 2 of the I/O APIC.
+#[inline]
+fn isa_irq_to_gsi(irq: u8) -> (u32, RedirectionFlags) {
+    let madt = match acpi::madt() {
+        Some(madt) => madt,
+        None if irq == TIMER_IRQ => return (2, RedirectionFlags::empty()),
+        None => return (irq as u32, RedirectionFlags::empty()),
+    };
+
+    // ISA interrupts are edge triggered and active high, unless overridden.
+    let source_override = match madt.interrupt_source_override(irq) {
+        Some(source_override) => source_override,
+        None => return (irq as u32, RedirectionFlags::empty()),
+    };
+
+    let mut flags = RedirectionFlags::empty();
+    if source_override.polarity == Polarity::ActiveLow {
+        flags |= RedirectionFlags::ACTIVE_LOW;
+    }
+    if source_override.trigger_mode == TriggerMode::Level {
+        flags |= RedirectionFlags::LEVEL_TRIGGERED;
+    }
+
+    (source_override.gsi, flags)
+}
+
+// Hands interrupt delivery from the chained PICs over to the APIC. The timer, keyboard and RTC IRQs
+// are routed to the local APIC of the calling CPU, on the same vectors the PICs used, and the PICs
+// are disabled. Memory needs to be initialized, as the APIC registers are mapped into the kernel
+// address space. The I/O APIC is taken from the MADT if the ACPI tables were parsed.
+//
+// If this fails, the PICs stay in charge.
+pub fn init_apic() -> Result<(), ApicError> {
+    // The ISA IRQs are handled by the I/O APIC starting at global system interrupt 0.
+    let (io_apic_address, gsi_base) = acpi::madt()
+        .and_then(|madt| madt.io_apics.iter().find(|io_apic| io_apic.gsi_base == 0))
+        .map_or(
+            (PhysicalAddress::new(DEFAULT_IO_APIC_ADDRESS), 0),
+            |io_apic| (io_apic.address, io_apic.gsi_base),
+        );
+
+    // The registers are only mapped once, even if this is called again.
+    if !IO_APIC.is_initialized() {
+        let io_apic = IoApic::new(io_apic_address, gsi_base).map_err(ApicError::Mmio)?;
+        IO_APIC.init_once(|| Mutex::new(io_apic));
+    }
 
+    let local_apic = apic::init_local_apic()?;
+    let mut io_apic = IO_APIC.get().unwrap().lock();
+
+    for (irq, index) in ISA_ROUTES {
+        let (gsi, flags) = isa_irq_to_gsi(irq);
+        unsafe { io_apic.route(gsi, index as u8, local_apic.id() as u8, flags) };
+    }
+
+    // The PICs are remapped before they are masked, as they can still raise spurious interrupts.
     unsafe {
-        // We changed our GDT, so we should reload the code segment register. This is required
-        // since the old segment selector could now point to a different GDT descriptor.
-        CS::set_reg(GDT.selectors.code_selector);
-        SS::set_reg(GDT.selectors.data_selector);
-        DS::set_reg(GDT.selectors.data_selector);
-        ES::set_reg(GDT.selectors.data_selector);
-        FS::set_reg(GDT.selectors.data_selector);
-        GS::set_reg(GDT.selectors.data_selector);
-
-        // We loaded a GDT that contains a TSS selector, but we still need to tell the CPU that it
-        // should use that TSS.
-        load_tss(GDT.selectors.tss_selector);
+        let mut pics = PICS.lock();
+        pics.init();
+        pics.disable();
     }
+
+    Ok(())
 }
 
+// The global system interrupt the PIT is connected to.
 #[inline]
-pub fn init() {
-    log::info!("Load the GDT");
-    GDT.table.load();
+pub fn timer_gsi() -> u32 {
+    isa_irq_to_gsi(TIMER_IRQ).0
+}
 
-    unsafe {
-        // We changed our GDT, so we should reload the code segment register. This is required
-        // since the old segment selector could now point to a different GDT descriptor.
-        log::info!("Reload the segment register");
-        CS::set_reg(GDT.selectors.code_selector);
-        SS::set_reg(GDT.selectors.data_selector);
-        DS::set_reg(GDT.selectors.data_selector);
-        ES::set_reg(GDT.selectors.data_selector);
-        FS::set_reg(GDT.selectors.data_selector);
-        GS::set_reg(GDT.selectors.data_selector);
-
-        // We loaded a GDT that contains a TSS selector, but we still need to tell the CPU that it
-        // should use that TSS.
-        log::info!("Make the CPU use the TSS");
-        load_tss(GDT.selectors.tss_selector);
+// Routes a global system interrupt to a vector of the local APIC of the calling CPU. Returns false
+// if the APIC is not in use or no I/O APIC handles the interrupt.
+//
+// ## Safety
+// The handler of the vector needs to expect the interrupt.
+pub unsafe fn route_gsi(gsi: u32, index: IdtIndex, flags: RedirectionFlags) -> bool {
+    let (Some(local_apic), Some(mut io_apic)) = (local_apic(), io_apic()) else {
+        return false;
+    };
+
+    unsafe { io_apic.route(gsi, index as u8, local_apic.id() as u8, flags) }
+}
+
+// Undoes route_gsi. An interrupt an ISA IRQ is connected to gets the route of the IRQ back, all
+// others are masked.
+pub fn unroute_gsi(gsi: u32) {
+    let (Some(local_apic), Some(mut io_apic)) = (local_apic(), io_apic()) else {
+        return;
+    };
+    if !io_apic.handles_gsi(gsi) {
+        return;
+    }
+
+    for (irq, index) in ISA_ROUTES {
+        let (isa_gsi, flags) = isa_irq_to_gsi(irq);
+        if isa_gsi == gsi {
+            unsafe { io_apic.route(gsi, index as u8, local_apic.id() as u8, flags) };
+            return;
+        }
     }
 
+    let pin = (gsi - io_apic.gsi_base()) as u8;
+    io_apic.mask(pin);
+}
+
+// Signals the end of a hardware interrupt to the interrupt controller in charge.
+#[inline]
+pub fn notify_end_of_interrupt(index: IdtIndex) {
+    match local_apic() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(index as u8) },
+    }
+}
+
+// Sets up the per-CPU data of the calling CPU, which loads its GDT and TSS. Tests may call this
+// more than once.
+//
+// The double fault stack of the TSS is allocated from the kernel address space, so memory::init
+// must have been called before.
+#[inline]
+pub fn testonly_gdt_init() {
+    percpu::init(BSP_CPU_ID);
+}
+
+#[inline]
+pub fn testonly_idt_init() {
+    IDT.load();
+}
+
+#[inline]
+pub fn init() {
+    // The per-CPU data holds the GDT and the TSS, so setting it up loads them.
+    log::info!("Set up the per-CPU data and load the GDT");
+    percpu::init(BSP_CPU_ID);
+
     log::info!("Load the IDT");
     IDT.load();
 
-    unsafe {
+    log::info!("Initialize the APIC");
+    if let Err(error) = init_apic() {
+        log::info!("APIC initialization failed: {:?}", error);
         log::info!("Initialize Chained PICs");
-        PICS.lock().init();
+        unsafe { PICS.lock().init() };
     }
 
     log::info!("Enable Hardware Interrupts");
     enable_hardware_interrupts();
 }
 
+// Sets up interrupt handling on an application processor. Every CPU gets a GDT and TSS of its own
+// in its per-CPU data. The IDT is shared. The bootstrap processor needs to have called init before.
+pub fn init_application_processor(cpu_id: usize) {
+    percpu::init(cpu_id);
+    IDT.load();
+
+    if let Some(local_apic) = local_apic() {
+        local_apic.enable();
+    }
+}
+
+// Loads the GDT, reloads the segment registers and makes the CPU use the TSS.
+#[inline]
+pub(crate) fn load_gdt(gdt: &'static GdtContainer) {
+    reload_gdt(gdt);
+
+    // We loaded a GDT that contains a TSS selector, but we still need to tell the CPU that it
+    // should use that TSS.
+    unsafe { load_tss(gdt.selectors.tss_selector) };
+}
+
+// Loads the GDT and reloads the segment registers, but keeps the task register. This is for a GDT
+// whose TSS the CPU already uses: loading the TSS marked it busy, and a busy TSS can't be loaded
+// again.
+#[inline]
+pub(crate) fn reload_gdt(gdt: &'static GdtContainer) {
+    gdt.table.load();
+
+    unsafe {
+        // We changed our GDT, so we should reload the code segment register. This is required
+        // since the old segment selector could now point to a different GDT descriptor.
+        CS::set_reg(gdt.selectors.code_selector);
+        SS::set_reg(gdt.selectors.data_selector);
+        DS::set_reg(gdt.selectors.data_selector);
+        ES::set_reg(gdt.selectors.data_selector);
+        FS::set_reg(gdt.selectors.data_selector);
+        GS::set_reg(gdt.selectors.data_selector);
+    }
+}
+
 extern "C" fn divide_error_handler(stack_frame: &ExceptionStackFrame) -> ! {
     log::info!("\nEXCEPTION: DIVIDE BY ZERO\n{:#?}", &*stack_frame);
     crate::hlt()
@@ -335,35 +516,64 @@ extern "C" fn double_fault_interrupt_handler(
         &*stack_frame
     );
 
+    // A double fault is commonly caused by a page fault that cannot be delivered, because the stack
+    // ran into its guard page.
+    let fault_address = CR2::read();
+    let hit_guard_page = KERNEL_AREAS.try_lock().is_some_and(|areas| {
+        areas
+            .find(fault_address)
+            .is_some_and(|area| area.kind() == AreaKind::GuardPage)
+    });
+    if hit_guard_page {
+        log::info!(
+            "Stack overflow: guard page at {:?} was accessed",
+            fault_address
+        );
+    }
+
     crate::hlt()
 }
 
+// Page faults in lazily backed areas are resolved by mapping the faulting page. The handler then
+// returns, and iretq restarts the faulting instruction. All other page faults are fatal.
 extern "C" fn page_fault_interrupt_handler(
     stack_frame: &ExceptionStackFrame,
     error_code: PageFaultErrorCodes,
-) -> ! {
+) {
+    let fault_address = CR2::read();
+    let error = match resolve_page_fault(fault_address, error_code) {
+        Ok(()) => return,
+        Err(error) => error,
+    };
+
     log::info!(
         "\nEXCEPTION: PAGE FAULT with error code {:?}\n{:#?}",
         error_code,
         &*stack_frame
     );
 
-    log::info!("Page Fault Address (CR2) contents: {:#?}", CR2::read());
+    log::info!("Page Fault Address (CR2) contents: {:#?}", fault_address);
+    log::info!("Page fault could not be resolved: {:?}", error);
 
     crate::hlt()
 }
 
 extern "C" fn timer_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
-    crate::serial_print!(".");
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(IdtIndex::TimerInterruptIndex as u8);
-    }
+    let _guard = this_cpu().enter_interrupt();
+    time::tick();
+    notify_end_of_interrupt(IdtIndex::TimerInterruptIndex);
+}
+
+extern "C" fn rtc_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    let _guard = this_cpu().enter_interrupt();
+    time::rtc_interrupt();
+    notify_end_of_interrupt(IdtIndex::RtcInterruptIndex);
 }
 
 extern "C" fn keyboard_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
     use x86_64::instructions::port::Port;
 
+    let _guard = this_cpu().enter_interrupt();
     let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
@@ -377,8 +587,22 @@ extern "C" fn keyboard_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
         }
     }
 
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(IdtIndex::KeyboardInterruptIndex as u8);
+    notify_end_of_interrupt(IdtIndex::KeyboardInterruptIndex);
+}
+
+extern "C" fn apic_timer_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    let _guard = this_cpu().enter_interrupt();
+    notify_end_of_interrupt(IdtIndex::ApicTimerInterruptIndex);
+}
+
+extern "C" fn apic_error_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    let _guard = this_cpu().enter_interrupt();
+    if let Some(local_apic) = local_apic() {
+        log::info!("APIC error: {:#x}", local_apic.error_status());
     }
+    notify_end_of_interrupt(IdtIndex::ApicErrorInterruptIndex);
 }
+
+// The local APIC raises a spurious interrupt when the interrupt it was about to deliver went away.
+// Spurious interrupts must not be acknowledged.
+extern "C" fn apic_spurious_interrupt_handler(_stack_frame: &ExceptionStackFrame) {}
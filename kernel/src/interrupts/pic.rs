@@ -66,9 +66,10 @@ impl Pics {
         self.secondary.data_port.write(CMD_8086_MODE);
         io_wait();
 
-        // Unmask the PICs to allow future interrupts
-        self.primary.data_port.write(0xFC);
-        self.secondary.data_port.write(0xFF);
+        // Unmask the PICs to allow future interrupts: the timer, the keyboard and the cascade on
+        // the primary PIC, and the RTC on the secondary PIC.
+        self.primary.data_port.write(0xF8);
+        self.secondary.data_port.write(0xFE);
     }
 
     #[inline]
@@ -1,23 +1,66 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
+#![feature(alloc_error_handler)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "run_tests"]
 
+// The alloc crate provides heap backed types such as Box, Vec, String and BTreeMap. They are backed
+// by the global allocator registered in memory::heap.
+extern crate alloc;
+
 use core::panic::PanicInfo;
 
+pub mod acpi;
 pub mod interrupts;
 pub mod memory;
+pub mod power;
 pub mod print;
 pub mod registers;
+pub mod smp;
+pub mod time;
+
+// The bootloader configuration of the kernel and the tests. The kernel and all bootloader mappings
+// live in the upper half, which is shared by all address spaces.
+pub const fn bootloader_config() -> bootloader_api::BootloaderConfig {
+    let mut config = bootloader_api::BootloaderConfig::new_default();
+    config.mappings.physical_memory = Some(bootloader_api::config::Mapping::Dynamic);
+    config.mappings.dynamic_range_start = Some(memory::address_space::KERNEL_SPACE_START);
+    config
+}
 
 #[cfg(test)]
-bootloader_api::entry_point!(test_kernel_main);
+pub static BOOTLOADER_CONFIG: bootloader_api::BootloaderConfig = bootloader_config();
+
+#[cfg(test)]
+bootloader_api::entry_point!(test_kernel_main, config = &BOOTLOADER_CONFIG);
 
 // This entry point is for all unit tests belonging to modules linked to lib.rs. We have a separate
 // entry point in the main function for all unit tests part of main.rs.
 #[cfg(test)]
-fn test_kernel_main(_boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+fn test_kernel_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    // Unit tests are allowed to use the heap and the global memory state.
+    unsafe { memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // Unit tests are allowed to use the ACPI tables.
+    if let Some(rsdp_address) = boot_info.rsdp_addr.into_option() {
+        unsafe {
+            acpi::init(
+                memory::paddr::PhysicalAddress::new(rsdp_address),
+                physical_memory_offset,
+            )
+        }
+        .expect("ACPI initialization failed");
+    }
+
+    // Unit tests are allowed to use the per-CPU data, which loads the GDT and the TSS.
+    interrupts::testonly_gdt_init();
+
     run_tests();
     hlt();
 }
@@ -19,19 +19,15 @@
 // framework.
 #![reexport_test_harness_main = "run_tests"]
 
-use bootloader_api::{config::Mapping, BootloaderConfig};
+use bootloader_api::BootloaderConfig;
 use core::panic::PanicInfo;
 use kernel::{
-    hlt, interrupts,
-    memory::{paging::Paging, vaddr::VirtualAddress},
-    print,
+    acpi, hlt, interrupts,
+    memory::{self, paddr::PhysicalAddress, vaddr::VirtualAddress},
+    print, registers, smp, time,
 };
 
-pub static BOOTLOADER_CONFIG: BootloaderConfig = {
-    let mut config = BootloaderConfig::new_default();
-    config.mappings.physical_memory = Some(Mapping::Dynamic);
-    config
-};
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
 
 bootloader_api::entry_point!(kernel, config = &BOOTLOADER_CONFIG);
 
@@ -54,13 +50,13 @@ fn kernel(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     // Extract the raw frame buffer
     let frame_buffer_info = frame_buffer_struct.info().clone();
     let raw_frame_buffer = frame_buffer_struct.buffer_mut();
+    let frame_buffer_start = VirtualAddress::from_ptr(raw_frame_buffer.as_ptr());
+    let frame_buffer_size = raw_frame_buffer.len() as u64;
 
     print::log::init_logger(raw_frame_buffer, frame_buffer_info);
 
     log::info!("Triad: A x86 kernel written in Rust");
-
-    // Initialize all software and hardware interrupts.
-    interrupts::init();
+    registers::cpuid::cpu_info().log();
 
     // Get the physical memory offset used to get the virtual address equivalent of the physical
     // memory.
@@ -69,13 +65,54 @@ fn kernel(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
         None => panic!("Physical memory offset not enabled in the bootloader"),
     };
 
-    // Initialize address translation and the Level 4 page table
-    let paging: Paging = Paging::init(physical_memory_offset);
+    // Initialize address translation, the physical frame allocator and the kernel heap. From here
+    // on, the alloc crate can be used.
+    unsafe { memory::init(&boot_info.memory_regions, physical_memory_offset) };
 
     let vaddr = VirtualAddress::new(physical_memory_offset);
-    let paddr = paging.translate(vaddr);
+    let paddr = memory::paging().translate(vaddr);
     log::info!("{:?} -> {:?}", vaddr, paddr);
 
+    // Parse the ACPI tables, which describe the interrupt controllers and the power management
+    // hardware. The interrupt setup below depends on them.
+    match boot_info.rsdp_addr.into_option() {
+        Some(rsdp_address) => {
+            match unsafe { acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+            {
+                Ok(tables) => tables.dump(),
+                Err(error) => log::info!("ACPI tables could not be parsed: {:?}", error),
+            }
+        }
+        None => log::info!("The firmware did not provide ACPI tables"),
+    }
+
+    // Initialize all software and hardware interrupts. The double fault stack is allocated from the
+    // kernel address space, so this needs to happen after memory is initialized.
+    interrupts::init();
+
+    // Count timer ticks. The timer interrupt was routed and enabled above.
+    time::init(time::ClockSource::Hpet, time::DEFAULT_TIMER_FREQUENCY);
+
+    // Start the other CPUs. They share the kernel page tables and the IDT set up above.
+    match smp::init() {
+        Ok(cpus) => log::info!("{} CPUs online", cpus),
+        Err(error) => log::info!("Application processors could not be started: {:?}", error),
+    }
+
+    let frame_allocator = memory::frame_allocator();
+    log::info!(
+        "Physical frames: {} free, {} used, {} total",
+        frame_allocator.free_frames(),
+        frame_allocator.used_frames(),
+        frame_allocator.total_frames()
+    );
+    drop(frame_allocator);
+    memory::meminfo().dump();
+
+    // Record the framebuffer in the kernel address space and log the address space layout.
+    memory::register_frame_buffer(frame_buffer_start, frame_buffer_size);
+    memory::vma::KERNEL_AREAS.lock().dump();
+
     // We use Rust's conditional compilation feature here. This function is only called in unit
     // tests part of main.rs.
     #[cfg(test)]
@@ -93,10 +130,23 @@ fn kernel(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // The panic may have happened while the logger was locked.
+    if let Some(logger) = kernel::print::log::LOGGER.get() {
+        unsafe { logger.force_unlock() };
+    }
     log::info!("{}", info);
+
+    // Leave some time to read the panic message before the machine is reset.
+    #[cfg(feature = "reboot-on-panic")]
+    kernel::power::reboot_after(PANIC_REBOOT_DELAY_MS);
+
+    #[cfg(not(feature = "reboot-on-panic"))]
     hlt();
 }
 
+#[cfg(all(not(test), feature = "reboot-on-panic"))]
+const PANIC_REBOOT_DELAY_MS: u64 = 5000;
+
 // This panic handler is called when we run unit tests associated with main.rs.
 #[cfg(test)]
 #[panic_handler]
@@ -0,0 +1,525 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::memory::cow::share_page;
+use crate::memory::frame_allocator::{FrameAllocator, MemoryMapFrameAllocator};
+use crate::memory::frame_descriptor::FrameOwner;
+use crate::memory::page::Page;
+use crate::memory::page_table::{PageTable, PageTableEntry, PageTableFlags, PTE_COUNT};
+use crate::memory::paging::{get_page_table_ptr, tlb_generation, MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::{frame::Frame, frame_allocator, paging};
+use crate::registers::control::{CR3Flags, CR4Flags, Pcid, CR3, CR4};
+use crate::registers::cpuid::{self, CpuFeatures};
+
+// The kernel lives in the upper half of the virtual address space, which is shared by all address
+// spaces. The lower half belongs to the individual address spaces.
+pub const KERNEL_SPACE_START: u64 = 0x_ffff_8000_0000_0000;
+
+// The level 4 entries covering the upper half.
+const KERNEL_ENTRIES: Range<usize> = (PTE_COUNT / 2)..PTE_COUNT;
+
+// The TLB entries of the kernel page tables are tagged with PCID 0. Address spaces get one of the
+// others while there are any left, and share PCID 0 with the kernel otherwise.
+const KERNEL_PCID: Pcid = match Pcid::new(0) {
+    Some(pcid) => pcid,
+    None => unreachable!(),
+};
+const PCID_COUNT: usize = Pcid::MAX as usize + 1;
+
+// The TLB generation a PCID was last flushed at, see paging::tlb_generation. NEVER_FLUSHED makes
+// the next activation flush.
+//
+// Address spaces are only activated on the bootstrap processor so far. Once other CPUs switch
+// address spaces, this needs to be tracked per CPU.
+const NEVER_FLUSHED: u64 = u64::MAX;
+static KERNEL_PCID_GENERATION: AtomicU64 = AtomicU64::new(NEVER_FLUSHED);
+
+static PCIDS: Mutex<PcidAllocator> = Mutex::new(PcidAllocator::new());
+
+// Hands out the PCIDs other than KERNEL_PCID.
+struct PcidAllocator {
+    used: [u64; PCID_COUNT / 64],
+}
+
+impl PcidAllocator {
+    #[inline]
+    const fn new() -> PcidAllocator {
+        let mut used = [0; PCID_COUNT / 64];
+        used[0] = 1 << KERNEL_PCID.value();
+        PcidAllocator { used }
+    }
+
+    fn allocate(&mut self) -> Option<Pcid> {
+        let (index, word) = self
+            .used
+            .iter_mut()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)?;
+        let bit = word.trailing_ones();
+        *word |= 1 << bit;
+
+        Pcid::new((index * 64) as u16 + bit as u16)
+    }
+
+    #[inline]
+    fn deallocate(&mut self, pcid: Pcid) {
+        let value = pcid.value() as usize;
+        self.used[value / 64] &= !(1 << (value % 64));
+    }
+}
+
+// Returns true if CR3 holds a PCID on the calling CPU, see protection::enable_protection_features.
+#[inline]
+fn pcids_enabled() -> bool {
+    CR4::read().contains(CR4Flags::PCID)
+}
+
+// Switches to the level 4 page table in the given frame, tagging TLB entries with the PCID. The
+// TLB entries of the PCID are flushed unless the PCID was flushed at the current TLB generation.
+//
+// ## Safety
+// Same as CR3::write_pcid.
+#[inline]
+unsafe fn write_cr3(frame: Frame, pcid: Pcid, generation: &AtomicU64) {
+    let current = tlb_generation();
+    let flush = generation.swap(current, Ordering::AcqRel) != current;
+    unsafe { CR3::write_pcid(frame, pcid, flush) };
+}
+
+// Points every unused upper half level 4 entry of the kernel page table to an empty level 3 table.
+//
+// Address spaces copy the upper half level 4 entries when they are created. As long as these
+// entries never change, kernel mappings created later show up in every address space.
+pub fn populate_kernel_space<A: FrameAllocator + ?Sized>(
+    paging: &Paging,
+    frame_allocator: &mut A,
+) -> Result<(), MappingError> {
+    let level_4_table: &mut PageTable = unsafe {
+        &mut *get_page_table_ptr(
+            paging.paddr_offset(),
+            paging.level_4_page_table_frame().start_address(),
+        )
+    };
+
+    for index in KERNEL_ENTRIES {
+        let pte: &mut PageTableEntry = &mut level_4_table[index];
+        if !pte.is_unused() {
+            continue;
+        }
+
+        let frame = frame_allocator
+            .allocate_frame_for(FrameOwner::PageTable)
+            .ok_or(MappingError::FrameAllocationFailed)?;
+        let page_table: &mut PageTable =
+            unsafe { &mut *get_page_table_ptr(paging.paddr_offset(), frame.start_address()) };
+        page_table.zero();
+
+        pte.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    }
+
+    Ok(())
+}
+
+// An address space with its own level 4 page table. The upper half is shared with the kernel, the
+// lower half starts out empty.
+//
+// If the CPU supports PCIDs, the address space gets one, so that its TLB entries survive switching
+// to another address space and back.
+#[derive(Debug)]
+pub struct AddressSpace {
+    paging: Paging,
+    pcid: Option<Pcid>,
+
+    // The TLB generation the PCID was last flushed at.
+    pcid_generation: AtomicU64,
+}
+
+impl AddressSpace {
+    // Creates an address space by copying the upper half entries of the kernel level 4 page table
+    // into a fresh one.
+    pub fn new() -> Result<AddressSpace, MappingError> {
+        let kernel_paging: Paging = *paging();
+        let paddr_offset = kernel_paging.paddr_offset();
+
+        let frame = frame_allocator()
+            .allocate_frame_for(FrameOwner::PageTable)
+            .ok_or(MappingError::FrameAllocationFailed)?;
+
+        let level_4_table: &mut PageTable =
+            unsafe { &mut *get_page_table_ptr(paddr_offset, frame.start_address()) };
+        let kernel_level_4_table: &PageTable = unsafe {
+            &*get_page_table_ptr(
+                paddr_offset,
+                kernel_paging.level_4_page_table_frame().start_address(),
+            )
+        };
+
+        level_4_table.zero();
+        for index in KERNEL_ENTRIES {
+            level_4_table[index] = kernel_level_4_table[index];
+        }
+
+        // A PCID freed by a dropped address space can still have TLB entries, so the first
+        // activation flushes them.
+        let pcid = match cpuid::has(CpuFeatures::PCID) {
+            true => PCIDS.lock().allocate(),
+            false => None,
+        };
+
+        Ok(AddressSpace {
+            paging: Paging::new(paddr_offset, frame),
+            pcid,
+            pcid_generation: AtomicU64::new(NEVER_FLUSHED),
+        })
+    }
+
+    // Creates a copy of the address space that shares all lower half frames copy-on-write. Both
+    // address spaces see the same memory until one of them writes to a page, which gets a private
+    // copy of it.
+    //
+    // Only 4KB pages can be shared. If the lower half contains huge pages, this fails with
+    // ParentEntryHugePage. On failure, the address space is left as it was.
+    pub fn fork(&self) -> Result<AddressSpace, MappingError> {
+        let child = AddressSpace::new()?;
+
+        let mut made_copy_on_write = Vec::new();
+        if let Err(error) = self.share_lower_half(&child, &mut made_copy_on_write) {
+            // Dropping the child releases its references to the shared frames, which leaves the
+            // pages made copy-on-write as the only mappings of their frames.
+            drop(child);
+
+            let mut paging = self.paging;
+            for (page, flags) in made_copy_on_write {
+                paging
+                    .update_flags(page, flags)
+                    .expect("Failed to restore the flags of a page");
+            }
+            return Err(error);
+        }
+
+        Ok(child)
+    }
+
+    // Shares every lower half page with the child, see fork. The pages that were writable before
+    // are recorded along with their flags.
+    fn share_lower_half(
+        &self,
+        child: &AddressSpace,
+        made_copy_on_write: &mut Vec<(Page, PageTableFlags)>,
+    ) -> Result<(), MappingError> {
+        let mut paging = self.paging;
+        let mut child_paging = child.paging;
+        let paddr_offset = paging.paddr_offset();
+
+        let mut frame_allocator = frame_allocator();
+        let level_4_table: &PageTable = unsafe {
+            &*get_page_table_ptr(
+                paddr_offset,
+                self.level_4_page_table_frame().start_address(),
+            )
+        };
+        for (level_4_index, level_4_pte) in
+            level_4_table.iter().enumerate().take(KERNEL_ENTRIES.start)
+        {
+            for (level_3_index, level_3_pte) in page_table_entries(paddr_offset, level_4_pte)? {
+                for (level_2_index, level_2_pte) in page_table_entries(paddr_offset, level_3_pte)? {
+                    for (level_1_index, level_1_pte) in
+                        page_table_entries(paddr_offset, level_2_pte)?
+                    {
+                        if level_1_pte.frame().is_none() {
+                            continue;
+                        }
+
+                        let page: Page = Page::new(VirtualAddress::new(
+                            (level_4_index as u64) << 39
+                                | (level_3_index as u64) << 30
+                                | (level_2_index as u64) << 21
+                                | (level_1_index as u64) << 12,
+                        ));
+                        let flags = level_1_pte.flags();
+                        share_page(&mut paging, &mut child_paging, page, &mut frame_allocator)?;
+                        if flags.contains(PageTableFlags::WRITABLE) {
+                            made_copy_on_write.push((page, flags));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The page tables of the address space. Page tables allocated for lower half mappings are owned
+    // by the address space and freed when it is dropped. Upper half mappings are shared with the
+    // kernel and should be made through memory::paging instead.
+    #[inline]
+    pub fn paging(&self) -> Paging {
+        self.paging
+    }
+
+    #[inline]
+    pub fn level_4_page_table_frame(&self) -> Frame {
+        self.paging.level_4_page_table_frame()
+    }
+
+    // The PCID the TLB entries of the address space are tagged with, or None if the CPU does not
+    // support PCIDs or all of them are in use.
+    #[inline]
+    pub fn pcid(&self) -> Option<Pcid> {
+        self.pcid
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        CR3::read().0 == self.level_4_page_table_frame()
+    }
+
+    // Switches the CPU to this address space.
+    //
+    // ## Safety
+    // The address space must stay alive while it is active. The caller has to switch to another
+    // address space before dropping it.
+    #[inline]
+    pub unsafe fn activate(&self) {
+        let frame = self.level_4_page_table_frame();
+        if !pcids_enabled() {
+            unsafe { CR3::write(frame, CR3Flags::empty()) };
+            return;
+        }
+
+        match self.pcid {
+            Some(pcid) => unsafe { write_cr3(frame, pcid, &self.pcid_generation) },
+            None => {
+                // The address space borrows the PCID of the kernel, whose TLB entries no longer
+                // match the kernel page tables afterwards.
+                KERNEL_PCID_GENERATION.store(NEVER_FLUSHED, Ordering::Release);
+                unsafe { CR3::write_pcid(frame, KERNEL_PCID, true) };
+            }
+        }
+    }
+}
+
+// Switches the CPU back to the kernel page tables.
+//
+// ## Safety
+// The lower half mappings of the active address space become inaccessible, so no references into
+// them may be used afterwards.
+#[inline]
+pub unsafe fn activate_kernel_space() {
+    let frame = paging().level_4_page_table_frame();
+    match pcids_enabled() {
+        true => unsafe { write_cr3(frame, KERNEL_PCID, &KERNEL_PCID_GENERATION) },
+        false => unsafe { CR3::write(frame, CR3Flags::empty()) },
+    }
+}
+
+// Frees the page tables of the lower half and the level 4 page table. The frames mapped by the
+// address space are not freed, they belong to whoever mapped them. Frames shared with other
+// address spaces lose a reference, so that the last sharer writes to them in place.
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "Dropping the active address space");
+
+        let paddr_offset = self.paging.paddr_offset();
+        let level_4_frame = self.level_4_page_table_frame();
+        let level_4_table: &PageTable =
+            unsafe { &*get_page_table_ptr(paddr_offset, level_4_frame.start_address()) };
+
+        let mut frame_allocator = frame_allocator();
+        for pte in level_4_table.iter().take(KERNEL_ENTRIES.start) {
+            free_page_table(paddr_offset, pte, 3, &mut *frame_allocator);
+        }
+        frame_allocator.deallocate_frame(level_4_frame);
+
+        if let Some(pcid) = self.pcid {
+            PCIDS.lock().deallocate(pcid);
+        }
+    }
+}
+
+// Returns the entries of the page table the entry points to. Unused entries yield no page table.
+#[inline]
+fn page_table_entries(
+    paddr_offset: u64,
+    pte: &PageTableEntry,
+) -> Result<impl Iterator<Item = (usize, &'static PageTableEntry)>, MappingError> {
+    if pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return Err(MappingError::ParentEntryHugePage);
+    }
+
+    let page_table: Option<&'static PageTable> = pte
+        .frame()
+        .map(|frame| unsafe { &*get_page_table_ptr(paddr_offset, frame.start_address()) });
+
+    Ok(page_table
+        .into_iter()
+        .flat_map(|page_table| page_table.iter().enumerate()))
+}
+
+// Frees the page table of the given level the entry points to, along with all page tables below
+// it. Entries mapping huge pages do not point to a page table. The references of level 1 entries
+// to shared frames are released.
+fn free_page_table(
+    paddr_offset: u64,
+    pte: &PageTableEntry,
+    level: u16,
+    frame_allocator: &mut MemoryMapFrameAllocator,
+) {
+    let frame = match pte.frame() {
+        Some(frame) if !pte.flags().contains(PageTableFlags::HUGE_PAGE) => frame,
+        _ => return,
+    };
+
+    let page_table: &PageTable =
+        unsafe { &*get_page_table_ptr(paddr_offset, frame.start_address()) };
+    for pte in page_table.iter() {
+        if level > 1 {
+            free_page_table(paddr_offset, pte, level - 1, frame_allocator);
+        } else {
+            release_shared_frame(pte, frame_allocator);
+        }
+    }
+
+    frame_allocator.deallocate_frame(frame);
+}
+
+// Drops the reference of an entry to its frame while other entries still share it. The last
+// reference to a copy made by resolve_copy_on_write frees the copy, any other frame belongs to
+// whoever mapped it.
+#[inline]
+fn release_shared_frame(pte: &PageTableEntry, frame_allocator: &mut MemoryMapFrameAllocator) {
+    let frame = match pte.frame() {
+        Some(frame) => frame,
+        None => return,
+    };
+
+    let descriptor = match frame_allocator
+        .descriptors()
+        .and_then(|descriptors| descriptors.get(frame))
+    {
+        Some(descriptor) => *descriptor,
+        None => return,
+    };
+
+    if descriptor.refcount() > 1 {
+        if let Some(descriptors) = frame_allocator.descriptors_mut() {
+            descriptors.unshare(frame);
+        }
+    } else if descriptor.owner() == FrameOwner::User {
+        frame_allocator.deallocate_frame(frame);
+    }
+}
+
+#[cfg(test)]
+const TEST_ADDRESS: u64 = 0x_5555_2000_0000;
+
+#[test_case]
+fn test_address_space_shares_kernel_half() {
+    let address_space = AddressSpace::new().unwrap();
+    assert!(!address_space.is_active());
+
+    // Kernel code, data and the heap are mapped the same way in both address spaces.
+    let boxed = alloc::boxed::Box::new(42u64);
+    for vaddr in [
+        VirtualAddress::from_ptr(activate_kernel_space as *const u8),
+        VirtualAddress::from_ptr(&KERNEL_SPACE_START),
+        VirtualAddress::from_ptr(&*boxed),
+    ] {
+        assert!(vaddr.address() >= KERNEL_SPACE_START);
+        assert!(address_space.paging().translate(vaddr).is_some());
+        assert_eq!(
+            address_space.paging().translate(vaddr),
+            paging().translate(vaddr)
+        );
+    }
+
+    // Lower half mappings are private to the address space.
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let frame = frame_allocator().allocate_frame().unwrap();
+    address_space
+        .paging()
+        .map_to(
+            page,
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            &mut *frame_allocator(),
+        )
+        .unwrap();
+    assert_eq!(
+        address_space.paging().translate(page.start_address()),
+        Some(frame.start_address())
+    );
+    assert!(paging().translate(page.start_address()).is_none());
+
+    drop(address_space);
+    frame_allocator().deallocate_frame(frame);
+}
+
+#[test_case]
+fn test_address_space_drop_frees_page_tables() {
+    let used_frames = frame_allocator().used_frames();
+
+    let address_space = AddressSpace::new().unwrap();
+    let frame = frame_allocator().allocate_frame().unwrap();
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    address_space
+        .paging()
+        .map_to(
+            page,
+            frame,
+            PageTableFlags::PRESENT,
+            &mut *frame_allocator(),
+        )
+        .unwrap();
+
+    // The level 4 table, the three tables below it and the mapped frame.
+    assert_eq!(frame_allocator().used_frames(), used_frames + 5);
+
+    drop(address_space);
+    assert_eq!(frame_allocator().used_frames(), used_frames + 1);
+
+    frame_allocator().deallocate_frame(frame);
+    assert_eq!(frame_allocator().used_frames(), used_frames);
+}
+
+#[test_case]
+fn test_failed_fork_restores_parent() {
+    use crate::memory::cow::frame_refcount;
+    use crate::memory::paddr::PhysicalAddress;
+    use crate::memory::page_size::Size2MiB;
+
+    let parent = AddressSpace::new().unwrap();
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let frame = frame_allocator().allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    parent
+        .paging()
+        .map_to(page, frame, flags, &mut *frame_allocator())
+        .unwrap();
+
+    // Huge pages cannot be shared, so the fork fails after the page above was made copy-on-write.
+    let huge_page: Page<Size2MiB> = Page::new(VirtualAddress::new(TEST_ADDRESS + 0x2000_0000));
+    parent
+        .paging()
+        .map_to(
+            huge_page,
+            Frame::new(PhysicalAddress::zero()),
+            PageTableFlags::PRESENT,
+            &mut *frame_allocator(),
+        )
+        .unwrap();
+
+    assert_eq!(
+        parent.fork().unwrap_err(),
+        MappingError::ParentEntryHugePage
+    );
+    assert_eq!(parent.paging().page_flags(page), Some(flags));
+    assert_eq!(frame_refcount(frame), 1);
+
+    drop(parent);
+    frame_allocator().deallocate_frame(frame);
+}
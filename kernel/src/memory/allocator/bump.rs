@@ -0,0 +1,81 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use crate::memory::allocator::{align_up, HeapAllocator, HeapStats, Locked};
+
+// The simplest possible allocator design, meant for early boot.
+//
+// A bump allocator hands out memory linearly by increasing (bumping) a pointer to the next free
+// byte. It does not keep track of individual freed blocks, it only counts the live allocations.
+// Once all allocations are freed, the whole heap becomes available again. This makes allocations
+// very fast, but a single long lived allocation prevents any memory from being reused.
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    #[inline]
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    const NAME: &'static str = "bump";
+
+    #[inline]
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+        self.allocations = 0;
+    }
+
+    fn stats(&self) -> HeapStats {
+        let free_bytes = self.heap_end - self.next;
+
+        HeapStats {
+            free_bytes,
+            free_blocks: if free_bytes > 0 { 1 } else { 0 },
+            largest_free_block: free_bytes,
+            live_allocations: self.allocations,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut bump = self.lock();
+
+        let alloc_start = align_up(bump.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > bump.heap_end {
+            return ptr::null_mut();
+        }
+
+        bump.next = alloc_end;
+        bump.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        let mut bump = self.lock();
+
+        bump.allocations -= 1;
+        if bump.allocations == 0 {
+            bump.next = bump.heap_start;
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr::NonNull;
+
+use crate::memory::allocator::linked_list::LinkedListAllocator;
+use crate::memory::allocator::{HeapAllocator, HeapStats, Locked};
+
+// The block sizes to use. Each block size is also used as the block alignment, so the sizes need
+// to be powers of 2. The smallest block needs to be able to hold a BlockNode once it is freed, and
+// to be a valid allocation size for the fallback allocator.
+const BLOCK_SIZES: &[usize] = &[16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// A node of a free block list. A free block stores a pointer to the next free block of the same
+// size in its first bytes.
+struct BlockNode {
+    next: Option<&'static mut BlockNode>,
+}
+
+// A slab style allocator that rounds every allocation up to the next block size.
+//
+// Each block size has its own list of free blocks, so allocating and freeing a block is a single
+// list operation. Freed blocks are never merged or split, they stay in the list of their size.
+// Allocations that are larger than the largest block size, and blocks that are needed while their
+// list is empty, are served by a linked list allocator as fallback.
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut BlockNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+    allocations: usize,
+}
+
+impl FixedSizeBlockAllocator {
+    #[inline]
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut BlockNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            allocations: 0,
+        }
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let ptr = match list_index(&layout) {
+            Some(index) => match self.list_heads[index].take() {
+                Some(node) => {
+                    self.list_heads[index] = node.next.take();
+                    node as *mut BlockNode as *mut u8
+                }
+                None => {
+                    // The list is empty, so allocate a new block from the fallback allocator.
+                    let block_size = BLOCK_SIZES[index];
+                    let block_layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    self.fallback_allocator.allocate(block_layout)
+                }
+            },
+            None => self.fallback_allocator.allocate(layout),
+        };
+
+        if !ptr.is_null() {
+            self.allocations += 1;
+        }
+
+        ptr
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                // The block size is at least as large and aligned as a BlockNode.
+                assert!(mem::size_of::<BlockNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<BlockNode>() <= BLOCK_SIZES[index]);
+
+                let new_node = BlockNode {
+                    next: self.list_heads[index].take(),
+                };
+                let new_node_ptr = ptr as *mut BlockNode;
+                unsafe {
+                    new_node_ptr.write(new_node);
+                    self.list_heads[index] = Some(&mut *new_node_ptr);
+                }
+            }
+            None => unsafe { self.fallback_allocator.deallocate(ptr, layout) },
+        }
+
+        self.allocations -= 1;
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    const NAME: &'static str = "fixed size block";
+
+    #[inline]
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback_allocator.init(heap_start, heap_size) };
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = self.fallback_allocator.stats();
+
+        // The fallback allocator counts the blocks it handed to us as live allocations.
+        stats.live_allocations = self.allocations;
+
+        for (index, list_head) in self.list_heads.iter().enumerate() {
+            let mut current = list_head.as_deref().map(NonNull::from);
+            while let Some(node) = current {
+                stats.free_bytes += BLOCK_SIZES[index];
+                stats.free_blocks += 1;
+                stats.largest_free_block = stats.largest_free_block.max(BLOCK_SIZES[index]);
+                current = unsafe { node.as_ref() }.next.as_deref().map(NonNull::from);
+            }
+        }
+
+        stats
+    }
+}
+
+// Returns the index of the smallest block size that fits the layout, or None if the layout needs
+// to be served by the fallback allocator.
+#[inline]
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES
+        .iter()
+        .position(|&size| size >= required_block_size)
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
+
+#[test_case]
+fn test_list_index() {
+    assert_eq!(list_index(&Layout::from_size_align(1, 1).unwrap()), Some(0));
+    assert_eq!(
+        list_index(&Layout::from_size_align(16, 8).unwrap()),
+        Some(0)
+    );
+    assert_eq!(
+        list_index(&Layout::from_size_align(17, 8).unwrap()),
+        Some(1)
+    );
+    assert_eq!(
+        list_index(&Layout::from_size_align(8, 64).unwrap()),
+        Some(2)
+    );
+    assert_eq!(
+        list_index(&Layout::from_size_align(2048, 8).unwrap()),
+        Some(7)
+    );
+    assert_eq!(list_index(&Layout::from_size_align(2049, 8).unwrap()), None);
+}
@@ -0,0 +1,225 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem;
+use core::ptr;
+
+use crate::memory::allocator::{align_up, HeapAllocator, HeapStats, Locked};
+
+// A node of the free list. Every free heap region stores its size and a pointer to the next free
+// region in its first bytes.
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    #[inline]
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    #[inline]
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    #[inline]
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+// A first-fit allocator that keeps the free heap regions in a linked list sorted by address.
+//
+// Allocations take the first free region that is large enough and return the unused parts of that
+// region to the list. On deallocation, the freed region is merged with its neighbours if they are
+// free as well, so the heap does not fragment into regions that are too small to be useful.
+pub struct LinkedListAllocator {
+    // A dummy node with size 0 whose next pointer is the first free region.
+    head: ListNode,
+    allocations: usize,
+}
+
+impl LinkedListAllocator {
+    #[inline]
+    pub const fn new() -> Self {
+        LinkedListAllocator {
+            head: ListNode::new(0),
+            allocations: 0,
+        }
+    }
+
+    // Allocates a block for the given layout, or returns a null pointer if no free region is large
+    // enough.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        let Some((region, alloc_start)) = self.find_region(size, align) else {
+            return ptr::null_mut();
+        };
+
+        let region_start = region.start_addr();
+        let region_end = region.end_addr();
+        let alloc_end = alloc_start + size;
+
+        unsafe {
+            if alloc_start > region_start {
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+            if region_end > alloc_end {
+                self.add_free_region(alloc_end, region_end - alloc_end);
+            }
+        }
+
+        self.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    // Returns a block to the free list.
+    //
+    // ## Safety
+    // The pointer must have been returned by allocate with the same layout.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        unsafe { self.add_free_region(ptr as usize, size) };
+        self.allocations -= 1;
+    }
+
+    // Inserts the region into the free list, keeping the list sorted by address, and merges it
+    // with the preceding and following regions if they are adjacent.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        // The freed region needs to be able to hold a ListNode.
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        unsafe {
+            let head: *mut ListNode = &raw mut self.head;
+
+            // Find the last free region that starts before the freed region.
+            let mut previous: *mut ListNode = head;
+            while let Some(next) = (*previous).next.as_deref_mut() {
+                if next.start_addr() > addr {
+                    break;
+                }
+                previous = next;
+            }
+
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(ListNode::new(size));
+            let node: &'static mut ListNode = &mut *node_ptr;
+            node.next = (*previous).next.take();
+
+            // Merge with the following region.
+            if let Some(next) = node.next.take() {
+                if node.end_addr() == next.start_addr() {
+                    node.size += next.size;
+                    node.next = next.next.take();
+                } else {
+                    node.next = Some(next);
+                }
+            }
+
+            // Merge with the preceding region. The dummy head node is never merged.
+            if previous != head && (*previous).end_addr() == node.start_addr() {
+                (*previous).size += node.size;
+                (*previous).next = node.next.take();
+            } else {
+                (*previous).next = Some(node);
+            }
+        }
+    }
+
+    // Looks for the first free region that can hold an allocation with the given size and
+    // alignment, and removes it from the list.
+    //
+    // Returns the region and the start address of the allocation.
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                let next = region.next.take();
+                let region = current.next.take().unwrap();
+                current.next = next;
+                return Some((region, alloc_start));
+            }
+
+            current = current.next.as_mut().unwrap();
+        }
+
+        None
+    }
+
+    // Tries to use the given region for an allocation with the given size and alignment. The
+    // unused space in front of and behind the allocation must either be empty or large enough to
+    // hold a ListNode, so it can be returned to the free list.
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr()
+            && alloc_start - region.start_addr() < mem::size_of::<ListNode>()
+        {
+            alloc_start = align_up(region.start_addr() + mem::size_of::<ListNode>(), align);
+        }
+
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+        if alloc_end > region.end_addr() {
+            return Err(());
+        }
+
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+
+    // Adjusts the layout so that the allocated memory region is capable of storing a ListNode once
+    // it is freed.
+    #[inline]
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("Adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    const NAME: &'static str = "linked list";
+
+    #[inline]
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.add_free_region(heap_start, heap_size) };
+    }
+
+    fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats {
+            free_bytes: 0,
+            free_blocks: 0,
+            largest_free_block: 0,
+            live_allocations: self.allocations,
+        };
+
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            stats.free_bytes += region.size;
+            stats.free_blocks += 1;
+            stats.largest_free_block = stats.largest_free_block.max(region.size);
+            current = region;
+        }
+
+        stats
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.lock().deallocate(ptr, layout) };
+    }
+}
@@ -0,0 +1,255 @@
+use spin::{Mutex, MutexGuard};
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+
+// A wrapper around spin::Mutex that allows us to implement GlobalAlloc for our allocators.
+// GlobalAlloc only hands out &self, so the allocator state needs interior mutability.
+pub struct Locked<A> {
+    inner: Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    #[inline]
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    #[inline]
+    pub fn lock(&self) -> MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+// A snapshot of the state of a heap allocator. The numbers can be used to compare how the different
+// allocator designs fragment the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    // The number of bytes that can still be handed out.
+    pub free_bytes: usize,
+
+    // The number of separate free blocks the free bytes are split into.
+    pub free_blocks: usize,
+
+    // The size of the largest contiguous free block. The further this is from free_bytes, the more
+    // fragmented the heap is.
+    pub largest_free_block: usize,
+
+    // The number of allocations that have not been freed yet.
+    pub live_allocations: usize,
+}
+
+// The interface shared by all heap allocator designs. The allocation itself goes through the
+// GlobalAlloc implementation of Locked<A>.
+pub trait HeapAllocator {
+    const NAME: &'static str;
+
+    // Initializes the allocator with the given heap bounds.
+    //
+    // ## Safety
+    // The caller must guarantee that the given heap bounds are valid and that the heap is unused.
+    // This method must be called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    fn stats(&self) -> HeapStats;
+}
+
+// Aligns the given address upwards to the given alignment. The alignment needs to be a power of 2.
+#[inline]
+pub fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[test_case]
+fn test_align_up() {
+    assert_eq!(align_up(0, 8), 0);
+    assert_eq!(align_up(1, 8), 8);
+    assert_eq!(align_up(8, 8), 8);
+    assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+}
+
+#[cfg(test)]
+const TEST_HEAP_SIZE: usize = 64 * 1024;
+
+// Backing memory for the allocator test suite. Every test initializes a fresh allocator on it.
+#[cfg(test)]
+#[allow(dead_code)]
+#[repr(align(4096))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+#[cfg(test)]
+static mut TEST_HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+
+// The test suite every allocator design has to pass.
+#[cfg(test)]
+fn run_allocator_test_suite<A: HeapAllocator>(allocator: &Locked<A>)
+where
+    Locked<A>: core::alloc::GlobalAlloc,
+{
+    use core::alloc::{GlobalAlloc, Layout};
+
+    let heap_start = (&raw mut TEST_HEAP) as usize;
+    unsafe { allocator.lock().init(heap_start, TEST_HEAP_SIZE) };
+
+    let initial_stats = allocator.lock().stats();
+    assert_eq!(initial_stats.free_bytes, TEST_HEAP_SIZE);
+    assert_eq!(initial_stats.live_allocations, 0);
+
+    unsafe {
+        // Allocations are usable and respect the requested alignment.
+        let mut allocations = [(core::ptr::null_mut(), Layout::new::<u8>()); 8];
+        for (i, allocation) in allocations.iter_mut().enumerate() {
+            let layout = Layout::from_size_align(24 + i * 40, 1 << i).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % layout.align(), 0);
+            assert!(ptr as usize >= heap_start);
+            assert!(ptr as usize + layout.size() <= heap_start + TEST_HEAP_SIZE);
+
+            ptr.write_bytes(i as u8, layout.size());
+            *allocation = (ptr, layout);
+        }
+        assert_eq!(allocator.lock().stats().live_allocations, allocations.len());
+
+        // Allocations must not overlap.
+        for (i, (ptr, layout)) in allocations.iter().enumerate() {
+            for offset in 0..layout.size() {
+                assert_eq!(*ptr.add(offset), i as u8);
+            }
+        }
+
+        for (ptr, layout) in allocations.iter() {
+            allocator.dealloc(*ptr, *layout);
+        }
+
+        // Allocating more than the heap holds in total only works if freed memory is reused.
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        for _ in 0..(2 * TEST_HEAP_SIZE / layout.size()) {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            allocator.dealloc(ptr, layout);
+        }
+
+        // A request larger than the heap fails without touching the heap.
+        let layout = Layout::from_size_align(2 * TEST_HEAP_SIZE, 8).unwrap();
+        assert!(allocator.alloc(layout).is_null());
+    }
+
+    // Once everything is freed, all of the heap is available again.
+    let final_stats = allocator.lock().stats();
+    assert_eq!(final_stats.free_bytes, TEST_HEAP_SIZE);
+    assert_eq!(final_stats.live_allocations, 0);
+}
+
+// Allocators that reuse individual freed blocks keep working while other allocations stay alive.
+#[cfg(test)]
+fn run_long_lived_allocation_test<A: HeapAllocator>(allocator: &Locked<A>)
+where
+    Locked<A>: core::alloc::GlobalAlloc,
+{
+    use core::alloc::{GlobalAlloc, Layout};
+
+    let heap_start = (&raw mut TEST_HEAP) as usize;
+    unsafe { allocator.lock().init(heap_start, TEST_HEAP_SIZE) };
+
+    unsafe {
+        let long_lived_layout = Layout::new::<u64>();
+        let long_lived = allocator.alloc(long_lived_layout) as *mut u64;
+        long_lived.write(42);
+
+        let layout = Layout::from_size_align(100, 4).unwrap();
+        for _ in 0..(2 * TEST_HEAP_SIZE / layout.size()) {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            allocator.dealloc(ptr, layout);
+        }
+
+        assert_eq!(long_lived.read(), 42);
+        allocator.dealloc(long_lived as *mut u8, long_lived_layout);
+    }
+
+    assert_eq!(allocator.lock().stats().free_bytes, TEST_HEAP_SIZE);
+}
+
+#[cfg(test)]
+const BENCHMARK_ROUNDS: usize = 256;
+
+// Times allocations of mixed sizes, every other of which is freed right away, and reports the TSC
+// cycles per allocation next to the fragmentation the surviving allocations leave behind.
+#[cfg(test)]
+fn run_allocator_benchmark<A: HeapAllocator>(allocator: &Locked<A>)
+where
+    Locked<A>: core::alloc::GlobalAlloc,
+{
+    use crate::serial_print;
+    use crate::time::tsc;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    let heap_start = (&raw mut TEST_HEAP) as usize;
+    unsafe { allocator.lock().init(heap_start, TEST_HEAP_SIZE) };
+
+    let mut live_allocations = [(core::ptr::null_mut(), Layout::new::<u8>()); BENCHMARK_ROUNDS / 2];
+    let start = tsc::read();
+    for round in 0..BENCHMARK_ROUNDS {
+        let layout = Layout::from_size_align(16 + (round % 8) * 24, 8).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+
+        match round % 2 {
+            0 => unsafe { allocator.dealloc(ptr, layout) },
+            _ => live_allocations[round / 2] = (ptr, layout),
+        }
+    }
+    let cycles = tsc::read() - start;
+
+    let stats = allocator.lock().stats();
+    serial_print!(
+        "{}: {} cycles per allocation, {} free bytes in {} blocks, largest {}\t",
+        A::NAME,
+        cycles / BENCHMARK_ROUNDS as u64,
+        stats.free_bytes,
+        stats.free_blocks,
+        stats.largest_free_block
+    );
+
+    for (ptr, layout) in live_allocations {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    assert_eq!(allocator.lock().stats().free_bytes, TEST_HEAP_SIZE);
+}
+
+#[test_case]
+fn test_bump_allocator() {
+    let allocator = Locked::new(bump::BumpAllocator::new());
+    run_allocator_test_suite(&allocator);
+
+    let allocator = Locked::new(bump::BumpAllocator::new());
+    run_allocator_benchmark(&allocator);
+}
+
+#[test_case]
+fn test_linked_list_allocator() {
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    run_allocator_test_suite(&allocator);
+
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    run_long_lived_allocation_test(&allocator);
+
+    let allocator = Locked::new(linked_list::LinkedListAllocator::new());
+    run_allocator_benchmark(&allocator);
+}
+
+#[test_case]
+fn test_fixed_size_block_allocator() {
+    let allocator = Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+    run_allocator_test_suite(&allocator);
+
+    let allocator = Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+    run_long_lived_allocation_test(&allocator);
+
+    let allocator = Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+    run_allocator_benchmark(&allocator);
+}
@@ -0,0 +1,349 @@
+use alloc::collections::BTreeSet;
+use bootloader_api::info::MemoryRegion;
+#[cfg(test)]
+use bootloader_api::info::MemoryRegionKind;
+
+use crate::memory::frame::{Frame, FrameRange, FRAME_SIZE};
+use crate::memory::frame_allocator::{usable_frame_range, FrameAllocator};
+use crate::memory::paddr::PhysicalAddress;
+
+// The largest block handed out by the buddy allocator consists of 2^MAX_ORDER frames (4MB).
+pub const MAX_ORDER: usize = 10;
+
+// A binary buddy allocator for physically contiguous frame ranges.
+//
+// Free memory is kept as blocks of 2^order frames, where every block is aligned to its own size.
+// Each block of order n can be split into two halves of order n - 1, its buddies. When a block is
+// freed and its buddy is free as well, both are merged back into a block of the next order. This
+// keeps free memory as contiguous as possible, which drivers that need DMA buffers depend on.
+//
+// The free lists live on the heap, so the heap needs to be initialized before this allocator.
+pub struct BuddyFrameAllocator {
+    // The start addresses of the free blocks of every order, sorted by address.
+    free_lists: [BTreeSet<u64>; MAX_ORDER + 1],
+
+    total_frames: u64,
+    free_frames: u64,
+}
+
+// A snapshot of the free memory of the buddy allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuddyStats {
+    pub total_frames: u64,
+    pub free_frames: u64,
+
+    // The number of free blocks of every order.
+    pub free_blocks: [usize; MAX_ORDER + 1],
+}
+
+impl BuddyStats {
+    // The number of frames in the largest free block.
+    #[inline]
+    pub fn largest_free_block(&self) -> u64 {
+        match self.free_blocks.iter().rposition(|&blocks| blocks > 0) {
+            Some(order) => 1 << order,
+            None => 0,
+        }
+    }
+
+    // The percentage of free memory that is not part of the largest free block. 0 means that all
+    // free memory is available as a single block, values close to 100 mean that free memory is
+    // scattered across many small blocks.
+    #[inline]
+    pub fn fragmentation(&self) -> u64 {
+        if self.free_frames == 0 {
+            return 0;
+        }
+
+        100 - self.largest_free_block() * 100 / self.free_frames
+    }
+}
+
+impl BuddyFrameAllocator {
+    // Creates a buddy allocator that owns all Usable regions of the bootloader memory map.
+    //
+    // ## Safety
+    // The caller must guarantee that all Usable regions in the memory map are really unused, and
+    // that no other frame allocator hands out frames from them.
+    pub unsafe fn init(memory_regions: &[MemoryRegion]) -> BuddyFrameAllocator {
+        let mut allocator = BuddyFrameAllocator {
+            free_lists: Default::default(),
+            total_frames: 0,
+            free_frames: 0,
+        };
+
+        for (start, end) in memory_regions.iter().filter_map(usable_frame_range) {
+            allocator.add_region(start, end);
+        }
+
+        allocator
+    }
+
+    // Returns a block of 2^order contiguous frames that is aligned to its size, or None if no
+    // block of that order is free.
+    pub fn allocate(&mut self, order: usize) -> Option<FrameRange> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        // Take the lowest free block of the smallest order that is large enough.
+        let mut block_order = (order..=MAX_ORDER).find(|&o| !self.free_lists[o].is_empty())?;
+        let block = self.free_lists[block_order].pop_first()?;
+
+        // Split the block until it has the requested size. The upper halves stay free.
+        while block_order > order {
+            block_order -= 1;
+            self.free_lists[block_order].insert(block + block_size(block_order));
+        }
+
+        self.free_frames -= 1 << order;
+        Some(block_range(block, order))
+    }
+
+    // Returns the smallest block that holds at least num_frames contiguous frames.
+    #[inline]
+    pub fn allocate_frames(&mut self, num_frames: u64) -> Option<FrameRange> {
+        if num_frames == 0 {
+            return None;
+        }
+
+        self.allocate(num_frames.next_power_of_two().trailing_zeros() as usize)
+    }
+
+    // Returns a block to the allocator. The range must have been handed out by allocate.
+    pub fn deallocate(&mut self, range: FrameRange) {
+        let num_frames = range.num_frames();
+        let start = range.start_frame().start_address().address();
+
+        if !num_frames.is_power_of_two() || start % (num_frames * FRAME_SIZE) != 0 {
+            panic!("Deallocating {:?}, which is not a buddy block", range);
+        }
+
+        let order = num_frames.trailing_zeros() as usize;
+        if self.free_frames + num_frames > self.total_frames {
+            panic!("Deallocating {:?} while it is not in use", range);
+        }
+
+        self.free_block(start, order);
+        self.free_frames += num_frames;
+    }
+
+    #[inline]
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    #[inline]
+    pub fn free_frames(&self) -> u64 {
+        self.free_frames
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        let mut free_blocks = [0; MAX_ORDER + 1];
+        for (order, free_list) in self.free_lists.iter().enumerate() {
+            free_blocks[order] = free_list.len();
+        }
+
+        BuddyStats {
+            total_frames: self.total_frames,
+            free_frames: self.free_frames,
+            free_blocks,
+        }
+    }
+
+    // Hands the frames in [start, end) to the allocator by cutting the range into the largest
+    // blocks that are aligned to their size.
+    fn add_region(&mut self, start: u64, end: u64) {
+        let mut address = start;
+
+        while address < end {
+            let mut order = MAX_ORDER;
+            while address % block_size(order) != 0 || address + block_size(order) > end {
+                order -= 1;
+            }
+
+            self.free_block(address, order);
+            address += block_size(order);
+        }
+
+        let num_frames = (end - start) / FRAME_SIZE;
+        self.total_frames += num_frames;
+        self.free_frames += num_frames;
+    }
+
+    // Puts a block on its free list, merging it with its buddy for as long as the buddy is free.
+    fn free_block(&mut self, mut block: u64, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = block ^ block_size(order);
+            if !self.free_lists[order].remove(&buddy) {
+                break;
+            }
+
+            block = block.min(buddy);
+            order += 1;
+        }
+
+        self.free_lists[order].insert(block);
+    }
+}
+
+// A buddy allocator can hand out single frames as well, so it can replace the memory map frame
+// allocator once the heap is available.
+impl FrameAllocator for BuddyFrameAllocator {
+    #[inline]
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        self.allocate(0).map(|range| range.start_frame())
+    }
+
+    #[inline]
+    fn deallocate_frame(&mut self, frame: Frame) {
+        self.deallocate(FrameRange::from_frame(frame));
+    }
+}
+
+// The size in bytes of a block of the given order.
+#[inline]
+fn block_size(order: usize) -> u64 {
+    FRAME_SIZE << order
+}
+
+#[inline]
+fn block_range(start: u64, order: usize) -> FrameRange {
+    let start_frame = Frame::new(PhysicalAddress::new(start));
+    if order == 0 {
+        return FrameRange::from_frame(start_frame);
+    }
+
+    let end_frame = Frame::new(PhysicalAddress::new(start + block_size(order)));
+    FrameRange::new(start_frame, end_frame, /*is_inclusive*/ false)
+}
+
+// The buddy allocator never touches the memory it manages, so the tests can use made up memory
+// maps.
+#[cfg(test)]
+fn test_buddy_allocator(regions: &[(u64, u64, MemoryRegionKind)]) -> BuddyFrameAllocator {
+    let regions: alloc::vec::Vec<MemoryRegion> = regions
+        .iter()
+        .map(|&(start, end, kind)| MemoryRegion { start, end, kind })
+        .collect();
+
+    unsafe { BuddyFrameAllocator::init(&regions) }
+}
+
+#[cfg(test)]
+const TEST_REGION_START: u64 = 0x100_0000;
+
+#[test_case]
+fn test_buddy_allocator_splits_regions_into_aligned_blocks() {
+    // Frames 1..16 are split into blocks of 1, 2, 4 and 8 frames.
+    let allocator = test_buddy_allocator(&[(0, 16 * FRAME_SIZE, MemoryRegionKind::Usable)]);
+    let stats = allocator.stats();
+    assert_eq!(stats.total_frames, 15);
+    assert_eq!(stats.free_frames, 15);
+    assert_eq!(&stats.free_blocks[..5], &[1, 1, 1, 1, 0]);
+    assert_eq!(stats.largest_free_block(), 8);
+}
+
+#[test_case]
+fn test_buddy_allocator_skips_reserved_regions() {
+    let mut allocator = test_buddy_allocator(&[
+        (
+            TEST_REGION_START,
+            TEST_REGION_START + 4 * FRAME_SIZE,
+            MemoryRegionKind::Usable,
+        ),
+        (
+            TEST_REGION_START + 4 * FRAME_SIZE,
+            TEST_REGION_START + 8 * FRAME_SIZE,
+            MemoryRegionKind::Bootloader,
+        ),
+    ]);
+    assert_eq!(allocator.total_frames(), 4);
+
+    let range = allocator.allocate(2).unwrap();
+    assert_eq!(
+        range.start_frame().start_address().address(),
+        TEST_REGION_START
+    );
+    assert_eq!(range.num_frames(), 4);
+    assert!(allocator.allocate(0).is_none());
+}
+
+#[test_case]
+fn test_buddy_allocator_merges_buddies() {
+    let mut allocator = test_buddy_allocator(&[(
+        TEST_REGION_START,
+        TEST_REGION_START + 16 * FRAME_SIZE,
+        MemoryRegionKind::Usable,
+    )]);
+    assert_eq!(allocator.stats().free_blocks[4], 1);
+
+    // A single frame splits the block into blocks of 1, 2, 4 and 8 frames.
+    let frame = allocator.allocate_frame().unwrap();
+    assert_eq!(frame.start_address().address(), TEST_REGION_START);
+    assert_eq!(&allocator.stats().free_blocks[..5], &[1, 1, 1, 1, 0]);
+    assert_eq!(allocator.free_frames(), 15);
+
+    // Freeing it merges all blocks again.
+    allocator.deallocate_frame(frame);
+    assert_eq!(&allocator.stats().free_blocks[..5], &[0, 0, 0, 0, 1]);
+    assert_eq!(allocator.free_frames(), 16);
+}
+
+#[test_case]
+fn test_buddy_allocator_rounds_up_to_power_of_two() {
+    let mut allocator = test_buddy_allocator(&[(
+        TEST_REGION_START,
+        TEST_REGION_START + 16 * FRAME_SIZE,
+        MemoryRegionKind::Usable,
+    )]);
+
+    let first = allocator.allocate_frames(1).unwrap();
+    let second = allocator.allocate_frames(3).unwrap();
+    assert_eq!(second.num_frames(), 4);
+    assert_eq!(
+        second.start_frame().start_address().address() % (4 * FRAME_SIZE),
+        0
+    );
+    assert_eq!(allocator.free_frames(), 11);
+
+    assert!(allocator.allocate_frames(16).is_none());
+    assert!(allocator.allocate_frames(0).is_none());
+
+    allocator.deallocate(second);
+    allocator.deallocate(first);
+    assert_eq!(allocator.allocate_frames(16).unwrap().num_frames(), 16);
+}
+
+#[test_case]
+fn test_buddy_allocator_fragmentation() {
+    let mut allocator = test_buddy_allocator(&[(
+        TEST_REGION_START,
+        TEST_REGION_START + 8 * FRAME_SIZE,
+        MemoryRegionKind::Usable,
+    )]);
+    assert_eq!(allocator.stats().fragmentation(), 0);
+
+    let frames: alloc::vec::Vec<Frame> = (0..8)
+        .map(|_| allocator.allocate_frame().unwrap())
+        .collect();
+    assert_eq!(allocator.stats().fragmentation(), 0);
+
+    // Freeing every other frame leaves 4 free frames that cannot be merged.
+    for frame in frames.iter().step_by(2) {
+        allocator.deallocate_frame(*frame);
+    }
+    let stats = allocator.stats();
+    assert_eq!(stats.free_frames, 4);
+    assert_eq!(stats.free_blocks[0], 4);
+    assert_eq!(stats.largest_free_block(), 1);
+    assert_eq!(stats.fragmentation(), 75);
+    assert!(allocator.allocate(1).is_none());
+
+    for frame in frames.iter().skip(1).step_by(2) {
+        allocator.deallocate_frame(*frame);
+    }
+    let stats = allocator.stats();
+    assert_eq!(stats.largest_free_block(), 8);
+    assert_eq!(stats.fragmentation(), 0);
+}
@@ -0,0 +1,181 @@
+use crate::memory::fault::PageFaultError;
+use crate::memory::frame::{Frame, FRAME_SIZE};
+use crate::memory::frame_allocator::{FrameAllocator, MemoryMapFrameAllocator};
+use crate::memory::frame_descriptor::FrameOwner;
+use crate::memory::page::Page;
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+#[cfg(test)]
+use crate::memory::{address_space::AddressSpace, frame_allocator, vaddr::VirtualAddress};
+
+// The reference counts of shared frames are kept in the frame descriptors of the frame allocator.
+// Frames without a descriptor are treated as having a single owner.
+
+// Returns the number of mappings of a frame handed out by share_page.
+#[inline]
+pub fn frame_refcount(frame: Frame) -> u32 {
+    crate::memory::frame_allocator()
+        .descriptors()
+        .and_then(|descriptors| descriptors.get(frame))
+        .map_or(1, |descriptor| descriptor.refcount())
+}
+
+// Maps the page of the source page tables to the same frame in the destination page tables. If the
+// page is writable, it becomes read-only and copy-on-write in both, so that the first write to it
+// gets a private copy of the frame.
+pub fn share_page(
+    source: &mut Paging,
+    destination: &mut Paging,
+    page: Page,
+    frame_allocator: &mut MemoryMapFrameAllocator,
+) -> Result<(), MappingError> {
+    let flags = source.page_flags(page).ok_or(MappingError::PageNotMapped)?;
+    let frame: Frame = Frame::new(
+        source
+            .translate(page.start_address())
+            .ok_or(MappingError::PageNotMapped)?,
+    );
+
+    let shared_flags = if flags.contains(PageTableFlags::WRITABLE) {
+        (flags - PageTableFlags::WRITABLE) | PageTableFlags::COPY_ON_WRITE
+    } else {
+        flags
+    };
+
+    destination.map_to(page, frame, shared_flags, frame_allocator)?;
+    if shared_flags != flags {
+        source.update_flags(page, shared_flags)?;
+    }
+
+    if let Some(descriptors) = frame_allocator.descriptors_mut() {
+        descriptors.share(frame);
+    }
+
+    Ok(())
+}
+
+// Resolves a write to a copy-on-write page. While the frame is shared, its content is copied to a
+// fresh frame that replaces it in the page tables and is owned by User. The last owner of a frame
+// writes to it in place.
+//
+// This runs in the page fault handler, so it must never block.
+pub fn resolve_copy_on_write(
+    paging: &mut Paging,
+    page: Page,
+    frame_allocator: &mut MemoryMapFrameAllocator,
+) -> Result<(), PageFaultError> {
+    let flags = paging
+        .page_flags(page)
+        .ok_or(PageFaultError::AccessViolation)?;
+    if !flags.contains(PageTableFlags::COPY_ON_WRITE) {
+        return Err(PageFaultError::AccessViolation);
+    }
+
+    let frame: Frame = Frame::new(
+        paging
+            .translate(page.start_address())
+            .ok_or(PageFaultError::AccessViolation)?,
+    );
+    let writable_flags = (flags - PageTableFlags::COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+
+    let refcount = frame_allocator
+        .descriptors()
+        .and_then(|descriptors| descriptors.get(frame))
+        .map_or(1, |descriptor| descriptor.refcount());
+    if refcount <= 1 {
+        return paging
+            .update_flags(page, writable_flags)
+            .map_err(PageFaultError::Mapping);
+    }
+
+    let copy = frame_allocator
+        .allocate_frame_for(FrameOwner::User)
+        .ok_or(PageFaultError::FrameAllocationFailed)?;
+    let source = (frame.start_address().address() + paging.paddr_offset()) as *const u8;
+    let destination = (copy.start_address().address() + paging.paddr_offset()) as *mut u8;
+    unsafe { core::ptr::copy_nonoverlapping(source, destination, FRAME_SIZE as usize) };
+
+    paging.unmap(page).map_err(PageFaultError::Mapping)?;
+    paging
+        .map_to(page, copy, writable_flags, frame_allocator)
+        .map_err(PageFaultError::Mapping)?;
+
+    if let Some(descriptors) = frame_allocator.descriptors_mut() {
+        descriptors.unshare(frame);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+const TEST_ADDRESS: u64 = 0x_5555_4000_0000;
+
+#[test_case]
+fn test_copy_on_write() {
+    let parent = AddressSpace::new().unwrap();
+    let child = AddressSpace::new().unwrap();
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let paddr_offset = parent.paging().paddr_offset();
+
+    let frame = frame_allocator().allocate_frame().unwrap();
+    let frame_ptr = (frame.start_address().address() + paddr_offset) as *mut u64;
+    unsafe { frame_ptr.write_volatile(0x1234) };
+    parent
+        .paging()
+        .map_to(page, frame, flags, &mut *frame_allocator())
+        .unwrap();
+
+    share_page(
+        &mut parent.paging(),
+        &mut child.paging(),
+        page,
+        &mut *frame_allocator(),
+    )
+    .unwrap();
+    let shared_flags = (flags - PageTableFlags::WRITABLE) | PageTableFlags::COPY_ON_WRITE;
+    assert_eq!(parent.paging().page_flags(page), Some(shared_flags));
+    assert_eq!(child.paging().page_flags(page), Some(shared_flags));
+    assert_eq!(frame_refcount(frame), 2);
+
+    // The first writer gets a copy of the frame.
+    resolve_copy_on_write(&mut child.paging(), page, &mut *frame_allocator()).unwrap();
+    let copy = child.paging().translate(page.start_address()).unwrap();
+    assert_ne!(copy, frame.start_address());
+    assert_eq!(child.paging().page_flags(page), Some(flags));
+    assert_eq!(
+        unsafe { ((copy.address() + paddr_offset) as *const u64).read_volatile() },
+        0x1234
+    );
+    assert_eq!(frame_refcount(frame), 1);
+    assert_eq!(frame_owner(Frame::new(copy)), Some(FrameOwner::User));
+
+    // The last owner writes in place.
+    resolve_copy_on_write(&mut parent.paging(), page, &mut *frame_allocator()).unwrap();
+    assert_eq!(
+        parent.paging().translate(page.start_address()),
+        Some(frame.start_address())
+    );
+    assert_eq!(parent.paging().page_flags(page), Some(flags));
+
+    // Pages that are not copy-on-write are not touched.
+    assert_eq!(
+        resolve_copy_on_write(&mut parent.paging(), page, &mut *frame_allocator()),
+        Err(PageFaultError::AccessViolation)
+    );
+
+    // The copy is freed along with the child, the frame still belongs to whoever mapped it.
+    drop(parent);
+    drop(child);
+    assert_eq!(frame_owner(Frame::new(copy)), Some(FrameOwner::Free));
+    assert_eq!(frame_owner(frame), Some(FrameOwner::Kernel));
+    frame_allocator().deallocate_frame(frame);
+}
+
+#[cfg(test)]
+fn frame_owner(frame: Frame) -> Option<FrameOwner> {
+    frame_allocator()
+        .descriptors()
+        .and_then(|descriptors| descriptors.get(frame))
+        .map(|descriptor| descriptor.owner())
+}
@@ -0,0 +1,498 @@
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Bound::{Excluded, Included};
+
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page_table::{
+    PageTable, PageTableFlags, PAGE_TABLE_INDEX_LENGTH, PAGE_TABLE_OFFSET_LENGTH,
+};
+use crate::memory::paging::get_page_table_ptr;
+use crate::memory::vaddr::VirtualAddress;
+use crate::registers::control::CR3;
+use crate::serial_println;
+
+// The flags the CPU updates on its own. They are ignored when comparing mappings, otherwise every
+// access would split or change a range.
+const VOLATILE_FLAGS: PageTableFlags = PageTableFlags::ACCESSED
+    .union(PageTableFlags::DIRTY)
+    .union(PageTableFlags::HUGE_PAGE);
+
+// A range of virtual memory that is mapped to a contiguous range of physical memory with the same
+// flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MappedRange {
+    start: VirtualAddress,
+    paddr: PhysicalAddress,
+    size: u64,
+    flags: PageTableFlags,
+}
+
+impl MappedRange {
+    #[inline]
+    pub fn new(
+        start: VirtualAddress,
+        paddr: PhysicalAddress,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> MappedRange {
+        MappedRange {
+            start,
+            paddr,
+            size,
+            flags: flags.difference(VOLATILE_FLAGS),
+        }
+    }
+
+    #[inline]
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    // The first address after the range, or None if the range ends at the top of the address
+    // space or right before the non-canonical hole, where no other range can follow.
+    #[inline]
+    pub fn end(&self) -> Option<VirtualAddress> {
+        let end = self.start.address().checked_add(self.size)?;
+        let vaddr = VirtualAddress::new(end);
+        (vaddr.address() == end).then_some(vaddr)
+    }
+
+    // The last address of the range.
+    #[inline]
+    pub fn last(&self) -> VirtualAddress {
+        VirtualAddress::new(self.start.address() + (self.size - 1))
+    }
+
+    // The physical address the start of the range is mapped to.
+    #[inline]
+    pub fn paddr(&self) -> PhysicalAddress {
+        self.paddr
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn flags(&self) -> PageTableFlags {
+        self.flags
+    }
+
+    // Extends the range by the given range, if it directly follows this range both virtually and
+    // physically and uses the same flags.
+    #[inline]
+    fn try_extend(&mut self, next: &MappedRange) -> bool {
+        let is_contiguous = self.end() == Some(next.start)
+            && self.paddr.address().checked_add(self.size) == Some(next.paddr.address())
+            && self.flags == next.flags;
+
+        if is_contiguous {
+            self.size += next.size;
+        }
+
+        is_contiguous
+    }
+
+    // Returns the part of the range that starts at start and is size bytes long.
+    #[inline]
+    fn slice(&self, start: VirtualAddress, size: u64) -> MappedRange {
+        let offset = start.address() - self.start.address();
+        MappedRange {
+            start,
+            paddr: PhysicalAddress::new(self.paddr.address() + offset),
+            size,
+            flags: self.flags,
+        }
+    }
+}
+
+// Formats a range as a single line, with inclusive ends, e.g.
+// 0x444444440000-0x44444453ffff -> 0x00000041a000-0x000000519fff     1024 KiB PRESENT | WRITABLE
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#014x}-{:#014x} -> {:#014x}-{:#014x} {:>8} KiB {:?}",
+            self.start.address(),
+            self.last().address(),
+            self.paddr.address(),
+            self.paddr.address() + (self.size - 1),
+            self.size / 1024,
+            self.flags
+        )
+    }
+}
+
+// A difference between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MappingChange {
+    // The range is only mapped in the newer snapshot.
+    Mapped(MappedRange),
+
+    // The range is only mapped in the older snapshot.
+    Unmapped(MappedRange),
+
+    // The range is mapped in both snapshots, but to different physical memory or with different
+    // flags.
+    Changed {
+        before: MappedRange,
+        after: MappedRange,
+    },
+}
+
+// All mappings of an address space at one point in time, as coalesced ranges sorted by virtual
+// address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressSpaceSnapshot {
+    ranges: Vec<MappedRange>,
+}
+
+impl AddressSpaceSnapshot {
+    // Walks all four page table levels of the active address space.
+    pub fn take(paddr_offset: u64) -> AddressSpaceSnapshot {
+        let (level_4_page_table_frame, _) = CR3::read();
+        let mut snapshot = AddressSpaceSnapshot { ranges: Vec::new() };
+
+        let level_4_page_table: &PageTable =
+            unsafe { &*get_page_table_ptr(paddr_offset, level_4_page_table_frame.start_address()) };
+        snapshot.walk(paddr_offset, level_4_page_table, 4, 0);
+
+        snapshot
+    }
+
+    // Builds a snapshot from the given ranges, which need to be sorted and must not overlap.
+    pub fn from_ranges(ranges: impl IntoIterator<Item = MappedRange>) -> AddressSpaceSnapshot {
+        let mut snapshot = AddressSpaceSnapshot { ranges: Vec::new() };
+        for range in ranges {
+            snapshot.push(range);
+        }
+
+        snapshot
+    }
+
+    #[inline]
+    pub fn ranges(&self) -> &[MappedRange] {
+        &self.ranges
+    }
+
+    // Returns the range containing the given address.
+    #[inline]
+    pub fn find(&self, vaddr: VirtualAddress) -> Option<&MappedRange> {
+        let index = self.ranges.partition_point(|range| range.last() < vaddr);
+        self.ranges
+            .get(index)
+            .filter(|range| range.start() <= vaddr)
+    }
+
+    // Prints all mapped ranges to serial.
+    pub fn dump(&self) {
+        let mapped: u64 = self.ranges.iter().map(|range| range.size).sum();
+        serial_println!(
+            "Address space: {} ranges, {} KiB mapped",
+            self.ranges.len(),
+            mapped / 1024
+        );
+
+        for range in self.ranges.iter() {
+            serial_println!("  {}", range);
+        }
+    }
+
+    // Returns the changes from this snapshot to the given newer snapshot, sorted by address.
+    pub fn diff(&self, newer: &AddressSpaceSnapshot) -> Vec<MappingChange> {
+        // Split both snapshots at the range boundaries of both, so every piece is either mapped
+        // the same way in both snapshots, or only mapped in one of them.
+        let boundaries: BTreeSet<VirtualAddress> = self
+            .ranges
+            .iter()
+            .chain(newer.ranges.iter())
+            .flat_map(|range| [Some(range.start()), range.end()])
+            .flatten()
+            .collect();
+
+        let before = split_ranges(&self.ranges, &boundaries);
+        let after = split_ranges(&newer.ranges, &boundaries);
+
+        let mut changes: Vec<MappingChange> = Vec::new();
+        let mut push = |change: MappingChange| {
+            // Merge the change with the previous one, if both describe adjacent parts of a range.
+            let merged = match (changes.last_mut(), &change) {
+                (Some(MappingChange::Mapped(last)), MappingChange::Mapped(next)) => {
+                    last.try_extend(next)
+                }
+                (Some(MappingChange::Unmapped(last)), MappingChange::Unmapped(next)) => {
+                    last.try_extend(next)
+                }
+                (
+                    Some(MappingChange::Changed { before, after }),
+                    MappingChange::Changed {
+                        before: next_before,
+                        after: next_after,
+                    },
+                ) => {
+                    let (mut merged_before, mut merged_after) = (*before, *after);
+                    if merged_before.try_extend(next_before) && merged_after.try_extend(next_after)
+                    {
+                        *before = merged_before;
+                        *after = merged_after;
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+
+            if !merged {
+                changes.push(change);
+            }
+        };
+
+        let (mut i, mut j) = (0, 0);
+        while i < before.len() || j < after.len() {
+            match (before.get(i), after.get(j)) {
+                (Some(old), Some(new)) if old.start == new.start => {
+                    if old != new {
+                        push(MappingChange::Changed {
+                            before: *old,
+                            after: *new,
+                        });
+                    }
+                    i += 1;
+                    j += 1;
+                }
+                (Some(old), Some(new)) if old.start < new.start => {
+                    push(MappingChange::Unmapped(*old));
+                    i += 1;
+                }
+                (Some(old), None) => {
+                    push(MappingChange::Unmapped(*old));
+                    i += 1;
+                }
+                (_, Some(new)) => {
+                    push(MappingChange::Mapped(*new));
+                    j += 1;
+                }
+                (None, None) => unreachable!(),
+            }
+        }
+
+        changes
+    }
+
+    // Prints the changes from this snapshot to the given newer snapshot to serial.
+    pub fn dump_diff(&self, newer: &AddressSpaceSnapshot) {
+        let changes = self.diff(newer);
+        serial_println!("Address space changes: {}", changes.len());
+
+        for change in changes.iter() {
+            match change {
+                MappingChange::Mapped(range) => {
+                    serial_println!("  + {}", range);
+                }
+                MappingChange::Unmapped(range) => {
+                    serial_println!("  - {}", range);
+                }
+                MappingChange::Changed { before, after } => {
+                    serial_println!("  - {}", before);
+                    serial_println!("  + {}", after);
+                }
+            }
+        }
+    }
+
+    // Adds the mappings of the given page table to the snapshot. base is the first virtual address
+    // mapped by the table.
+    fn walk(&mut self, paddr_offset: u64, page_table: &PageTable, level: u16, base: u64) {
+        let entry_size: u64 =
+            1 << (PAGE_TABLE_OFFSET_LENGTH + (level - 1) * PAGE_TABLE_INDEX_LENGTH);
+
+        for (index, entry) in page_table.iter().enumerate() {
+            let flags = entry.flags();
+            if entry.is_unused() || !flags.contains(PageTableFlags::PRESENT) {
+                continue;
+            }
+
+            let vaddr = base + index as u64 * entry_size;
+            if level == 1 || flags.contains(PageTableFlags::HUGE_PAGE) {
+                self.push(MappedRange::new(
+                    VirtualAddress::new(vaddr),
+                    entry.paddr(),
+                    entry_size,
+                    flags,
+                ));
+                continue;
+            }
+
+            let next_page_table: &PageTable =
+                unsafe { &*get_page_table_ptr(paddr_offset, entry.paddr()) };
+            self.walk(paddr_offset, next_page_table, level - 1, vaddr);
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, range: MappedRange) {
+        if let Some(last) = self.ranges.last_mut() {
+            if last.try_extend(&range) {
+                return;
+            }
+        }
+
+        self.ranges.push(range);
+    }
+}
+
+// Splits the ranges at the given boundaries.
+fn split_ranges(ranges: &[MappedRange], boundaries: &BTreeSet<VirtualAddress>) -> Vec<MappedRange> {
+    let mut pieces = Vec::new();
+
+    for range in ranges {
+        let mut start = range.start();
+        for &boundary in boundaries.range((Excluded(range.start()), Included(range.last()))) {
+            pieces.push(range.slice(start, boundary.address() - start.address()));
+            start = boundary;
+        }
+        pieces.push(range.slice(start, range.last().address() - start.address() + 1));
+    }
+
+    pieces
+}
+
+#[cfg(test)]
+fn test_range(start: u64, paddr: u64, num_pages: u64, flags: PageTableFlags) -> MappedRange {
+    use crate::memory::page::PAGE_SIZE;
+
+    MappedRange::new(
+        VirtualAddress::new(start),
+        PhysicalAddress::new(paddr),
+        num_pages * PAGE_SIZE,
+        flags,
+    )
+}
+
+#[test_case]
+fn test_snapshot_coalesces_contiguous_ranges() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let snapshot = AddressSpaceSnapshot::from_ranges([
+        test_range(0x1000, 0x5000, 1, flags),
+        test_range(0x2000, 0x6000, 2, flags | PageTableFlags::ACCESSED),
+        // Not physically contiguous.
+        test_range(0x4000, 0x9000, 1, flags),
+        // Different flags.
+        test_range(0x5000, 0xa000, 1, PageTableFlags::PRESENT),
+    ]);
+
+    assert_eq!(
+        snapshot.ranges(),
+        &[
+            test_range(0x1000, 0x5000, 3, flags),
+            test_range(0x4000, 0x9000, 1, flags),
+            test_range(0x5000, 0xa000, 1, PageTableFlags::PRESENT),
+        ]
+    );
+    assert_eq!(
+        snapshot.find(VirtualAddress::new(0x3fff)),
+        Some(&snapshot.ranges()[0])
+    );
+    assert_eq!(snapshot.find(VirtualAddress::new(0x6000)), None);
+}
+
+#[test_case]
+fn test_snapshot_diff() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let before = AddressSpaceSnapshot::from_ranges([
+        test_range(0x1000, 0x5000, 4, flags),
+        test_range(0x10000, 0x20000, 2, flags),
+    ]);
+    let after = AddressSpaceSnapshot::from_ranges([
+        // The second page was made read only and the last page was unmapped.
+        test_range(0x1000, 0x5000, 1, flags),
+        test_range(0x2000, 0x6000, 1, PageTableFlags::PRESENT),
+        test_range(0x3000, 0x7000, 1, flags),
+        // A new mapping next to an unchanged one.
+        test_range(0x10000, 0x20000, 3, flags),
+    ]);
+
+    assert_eq!(
+        before.diff(&after),
+        [
+            MappingChange::Changed {
+                before: test_range(0x2000, 0x6000, 1, flags),
+                after: test_range(0x2000, 0x6000, 1, PageTableFlags::PRESENT),
+            },
+            MappingChange::Unmapped(test_range(0x4000, 0x8000, 1, flags)),
+            MappingChange::Mapped(test_range(0x12000, 0x22000, 1, flags)),
+        ]
+    );
+    assert!(before.diff(&before).is_empty());
+}
+
+#[test_case]
+fn test_snapshot_at_the_ends_of_the_address_space() {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let lower_half_end = test_range(0x_7fff_ffff_f000, 0x5000, 1, flags);
+    let upper_half_start = test_range(0x_ffff_8000_0000_0000, 0x6000, 1, flags);
+    let top = test_range(0x_ffff_ffff_ffff_f000, 0x7000, 1, flags);
+    assert_eq!(lower_half_end.end(), None);
+    assert_eq!(top.end(), None);
+    assert_eq!(top.last(), VirtualAddress::new(u64::MAX));
+
+    // The ranges are physically contiguous, but not across the non-canonical hole.
+    let snapshot = AddressSpaceSnapshot::from_ranges([lower_half_end, upper_half_start, top]);
+    assert_eq!(snapshot.ranges(), &[lower_half_end, upper_half_start, top]);
+    assert_eq!(snapshot.find(VirtualAddress::new(u64::MAX)), Some(&top));
+
+    let empty = AddressSpaceSnapshot::from_ranges([]);
+    assert_eq!(
+        empty.diff(&snapshot),
+        [
+            MappingChange::Mapped(lower_half_end),
+            MappingChange::Mapped(upper_half_start),
+            MappingChange::Mapped(top),
+        ]
+    );
+}
+
+#[test_case]
+fn test_snapshot_of_active_address_space() {
+    use crate::memory::frame_allocator::FrameAllocator;
+    use crate::memory::heap::{HEAP_SIZE, HEAP_START};
+    use crate::memory::page::Page;
+    use crate::memory::{frame_allocator, paging};
+
+    let paddr_offset = paging().paddr_offset();
+    let before = AddressSpaceSnapshot::take(paddr_offset);
+    let heap_start = VirtualAddress::new(HEAP_START);
+    let heap_range = before.find(heap_start).unwrap();
+    assert_eq!(Some(heap_range.paddr()), paging().translate(heap_start));
+    assert!(before
+        .find(VirtualAddress::new(HEAP_START + HEAP_SIZE - 1))
+        .is_some());
+
+    // Map a page and check that the diff shows exactly that page.
+    let page: Page = Page::new(VirtualAddress::new(0x_5555_0000_0000));
+    let frame = frame_allocator().allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    paging()
+        .map_to(page, frame, flags, &mut *frame_allocator())
+        .unwrap();
+
+    let after = AddressSpaceSnapshot::take(paddr_offset);
+    assert_eq!(
+        before.diff(&after),
+        [MappingChange::Mapped(MappedRange::new(
+            page.start_address(),
+            frame.start_address(),
+            page.size(),
+            flags
+        ))]
+    );
+
+    let frame = paging().unmap(page).unwrap();
+    frame_allocator().deallocate_frame(frame);
+    assert!(before
+        .diff(&AddressSpaceSnapshot::take(paddr_offset))
+        .is_empty());
+}
@@ -0,0 +1,176 @@
+use crate::memory::cow::resolve_copy_on_write;
+use crate::memory::frame::FRAME_SIZE;
+use crate::memory::frame_allocator::FrameAllocator;
+use crate::memory::page::Page;
+use crate::memory::page_table::{PageFaultErrorCodes, PageTableFlags};
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::vma::{Backing, KERNEL_AREAS};
+use crate::memory::{FRAME_ALLOCATOR, PAGING};
+use crate::registers::control::CR3;
+
+// The reasons a page fault cannot be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PageFaultError {
+    // Memory is not initialized yet, or the fault happened while the interrupted code held one of
+    // the memory locks.
+    MemoryUnavailable,
+
+    // The address is not part of any registered area.
+    NoArea,
+
+    // The address is part of an area whose pages are not allocated on demand.
+    NotLazy,
+
+    // The page is present, or the access is not allowed by the flags of the area.
+    AccessViolation,
+
+    // The frame allocator ran out of frames.
+    FrameAllocationFailed,
+
+    // The page could not be mapped.
+    Mapping(MappingError),
+}
+
+// Resolves a page fault by mapping the faulting page to a fresh zeroed frame, if the address lies in
+// a lazily backed area and the area allows the access. Writes to copy-on-write pages of the active
+// address space are resolved by copying the frame. Once this returns Ok, the faulting instruction
+// can be restarted.
+//
+// This runs in the page fault handler, so it must never block. If the interrupted code holds one of
+// the memory locks, the fault is reported as unresolvable instead of deadlocking.
+pub fn resolve_page_fault(
+    vaddr: VirtualAddress,
+    error_code: PageFaultErrorCodes,
+) -> Result<(), PageFaultError> {
+    if error_code.contains(
+        PageFaultErrorCodes::PAGE_PROTECTION_VIOLATION | PageFaultErrorCodes::WRITE_VIOLATION,
+    ) {
+        return resolve_write_protection_fault(vaddr);
+    }
+
+    let areas = KERNEL_AREAS
+        .try_lock()
+        .ok_or(PageFaultError::MemoryUnavailable)?;
+    let area = areas.find(vaddr).ok_or(PageFaultError::NoArea)?;
+
+    if area.backing() != Backing::Lazy {
+        return Err(PageFaultError::NotLazy);
+    }
+
+    if !is_access_allowed(area.flags(), error_code) {
+        return Err(PageFaultError::AccessViolation);
+    }
+
+    let mut paging = PAGING
+        .get()
+        .and_then(|paging| paging.try_lock())
+        .ok_or(PageFaultError::MemoryUnavailable)?;
+    let mut frame_allocator = FRAME_ALLOCATOR
+        .get()
+        .and_then(|frame_allocator| frame_allocator.try_lock())
+        .ok_or(PageFaultError::MemoryUnavailable)?;
+
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(PageFaultError::FrameAllocationFailed)?;
+
+    // The frame may contain data of its previous user.
+    let frame_ptr = (frame.start_address().address() + paging.paddr_offset()) as *mut u8;
+    unsafe { frame_ptr.write_bytes(0, FRAME_SIZE as usize) };
+
+    let page: Page = Page::new(vaddr);
+    if let Err(error) = paging.map_to(page, frame, area.flags(), &mut *frame_allocator) {
+        frame_allocator.deallocate_frame(frame);
+        return Err(PageFaultError::Mapping(error));
+    }
+
+    Ok(())
+}
+
+// A write to a present page that is not writable. These are only resolvable for copy-on-write pages,
+// which are looked up in the active page tables, as they might belong to another address space than
+// the kernel's.
+fn resolve_write_protection_fault(vaddr: VirtualAddress) -> Result<(), PageFaultError> {
+    let kernel_paging = PAGING
+        .get()
+        .and_then(|paging| paging.try_lock())
+        .ok_or(PageFaultError::MemoryUnavailable)?;
+    let mut frame_allocator = FRAME_ALLOCATOR
+        .get()
+        .and_then(|frame_allocator| frame_allocator.try_lock())
+        .ok_or(PageFaultError::MemoryUnavailable)?;
+
+    let (level_4_page_table_frame, _) = CR3::read();
+    let mut paging = Paging::new(kernel_paging.paddr_offset(), level_4_page_table_frame);
+
+    resolve_copy_on_write(&mut paging, Page::new(vaddr), &mut *frame_allocator)
+}
+
+// Checks if an access to a page that is not present would be allowed by the given flags.
+#[inline]
+fn is_access_allowed(flags: PageTableFlags, error_code: PageFaultErrorCodes) -> bool {
+    // The page is present, so the access itself is not allowed.
+    if error_code.contains(PageFaultErrorCodes::PAGE_PROTECTION_VIOLATION) {
+        return false;
+    }
+
+    if error_code.contains(PageFaultErrorCodes::WRITE_VIOLATION)
+        && !flags.contains(PageTableFlags::WRITABLE)
+    {
+        return false;
+    }
+
+    if error_code.contains(PageFaultErrorCodes::INSTRUCTION_FETCH)
+        && flags.contains(PageTableFlags::NO_EXECUTE)
+    {
+        return false;
+    }
+
+    if error_code.contains(PageFaultErrorCodes::UNPRIVILEGED_USER)
+        && !flags.contains(PageTableFlags::USER_ACCESSIBLE)
+    {
+        return false;
+    }
+
+    true
+}
+
+#[test_case]
+fn test_is_access_allowed() {
+    let read_only = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    let writable = read_only | PageTableFlags::WRITABLE;
+
+    assert!(is_access_allowed(read_only, PageFaultErrorCodes::empty()));
+    assert!(!is_access_allowed(
+        read_only,
+        PageFaultErrorCodes::WRITE_VIOLATION
+    ));
+    assert!(is_access_allowed(
+        writable,
+        PageFaultErrorCodes::WRITE_VIOLATION
+    ));
+    assert!(!is_access_allowed(
+        writable,
+        PageFaultErrorCodes::INSTRUCTION_FETCH
+    ));
+    assert!(!is_access_allowed(
+        writable,
+        PageFaultErrorCodes::UNPRIVILEGED_USER
+    ));
+    assert!(!is_access_allowed(
+        writable,
+        PageFaultErrorCodes::PAGE_PROTECTION_VIOLATION | PageFaultErrorCodes::WRITE_VIOLATION
+    ));
+}
+
+#[test_case]
+fn test_fault_outside_of_areas_is_not_resolved() {
+    assert_eq!(
+        resolve_page_fault(
+            VirtualAddress::new(0x_7777_0000_0000),
+            PageFaultErrorCodes::empty()
+        ),
+        Err(PageFaultError::NoArea)
+    );
+}
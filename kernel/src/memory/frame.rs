@@ -1,24 +1,31 @@
 use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page_size::{PageSize, Size4KiB};
+#[cfg(test)]
+use crate::memory::page_size::{Size1GiB, Size2MiB};
 
+use core::marker::PhantomData;
 use core::ops::RangeInclusive;
 
 // x86 uses a frame size of 4KB
-pub const FRAME_SIZE: u64 = 4096;
+pub const FRAME_SIZE: u64 = Size4KiB::SIZE;
 
+// A physical frame. Frames default to 4KB, but 2MB and 1GB frames can be used to back huge pages.
 #[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
-pub struct Frame {
+pub struct Frame<S: PageSize = Size4KiB> {
     start_address: PhysicalAddress,
+    size: PhantomData<S>,
 }
 
-impl Frame {
+impl<S: PageSize> Frame<S> {
     // Returns a frame whose start address is the largest that is less than
     // the address provided.
     #[inline]
-    pub fn new(paddr: PhysicalAddress) -> Frame {
-        let address = PhysicalAddress::new(paddr.address() & !(FRAME_SIZE - 1));
+    pub fn new(paddr: PhysicalAddress) -> Frame<S> {
+        let address = PhysicalAddress::new(paddr.address() & !(S::SIZE - 1));
         Frame {
             start_address: address,
+            size: PhantomData,
         }
     }
 
@@ -26,21 +33,26 @@ impl Frame {
     pub fn start_address(&self) -> PhysicalAddress {
         self.start_address
     }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        S::SIZE
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
 #[repr(C)]
-pub struct FrameRange {
-    start_frame: Frame,
-    end_frame: Frame,
+pub struct FrameRange<S: PageSize = Size4KiB> {
+    start_frame: Frame<S>,
+    end_frame: Frame<S>,
     // Indicates if the end frame is part of the range
     is_inclusive: bool,
 }
 
-impl FrameRange {
+impl<S: PageSize> FrameRange<S> {
     #[inline]
-    pub fn new(start_frame: Frame, end_frame: Frame, is_inclusive: bool) -> FrameRange {
-        if (start_frame.start_address.address() + FRAME_SIZE) >= end_frame.start_address.address() {
+    pub fn new(start_frame: Frame<S>, end_frame: Frame<S>, is_inclusive: bool) -> FrameRange<S> {
+        if (start_frame.start_address.address() + S::SIZE) >= end_frame.start_address.address() {
             panic!("Start Frame overlaps with end frame");
         }
 
@@ -51,13 +63,23 @@ impl FrameRange {
         }
     }
 
+    // A range holding just the given frame.
+    #[inline]
+    pub fn from_frame(frame: Frame<S>) -> FrameRange<S> {
+        FrameRange {
+            start_frame: frame,
+            end_frame: frame,
+            is_inclusive: true,
+        }
+    }
+
     #[inline]
-    pub fn start_frame(&self) -> Frame {
+    pub fn start_frame(&self) -> Frame<S> {
         self.start_frame
     }
 
     #[inline]
-    pub fn end_frame(&self) -> Frame {
+    pub fn end_frame(&self) -> Frame<S> {
         self.end_frame
     }
 
@@ -70,7 +92,7 @@ impl FrameRange {
     pub fn num_frames(&self) -> u64 {
         let num_frames: u64 = (self.end_frame.start_address.address()
             - self.start_frame.start_address.address())
-            / FRAME_SIZE;
+            / S::SIZE;
 
         if self.is_inclusive {
             return num_frames + 1;
@@ -83,7 +105,7 @@ impl FrameRange {
     pub fn address_range(&self) -> RangeInclusive<u64> {
         if self.is_inclusive {
             return self.start_frame.start_address.address()
-                ..=(self.end_frame.start_address.address() + FRAME_SIZE - 1);
+                ..=(self.end_frame.start_address.address() + S::SIZE - 1);
         }
 
         return self.start_frame.start_address.address()
@@ -94,7 +116,7 @@ impl FrameRange {
 #[test_case]
 fn test_frame_creation_is_successful() {
     let paddr = PhysicalAddress::new(0x18);
-    let frame = Frame::new(paddr);
+    let frame: Frame = Frame::new(paddr);
 
     assert_eq!(frame.start_address().address(), 0x00);
 }
@@ -104,8 +126,8 @@ fn test_frame_range_creation_is_successful() {
     let paddr1 = PhysicalAddress::new(0x18);
     let paddr2 = paddr1 + 3 * FRAME_SIZE;
 
-    let start_frame = Frame::new(paddr1);
-    let end_frame = Frame::new(paddr2);
+    let start_frame: Frame = Frame::new(paddr1);
+    let end_frame: Frame = Frame::new(paddr2);
 
     let frame_range = FrameRange::new(start_frame, end_frame, /*is_inclusive*/ false);
     assert_eq!(frame_range.num_frames(), 3);
@@ -122,7 +144,47 @@ fn test_frame_range_creation_is_successful() {
 #[test_case]
 fn test_frame_creation_with_in_between_address_successful() {
     let paddr = PhysicalAddress::new(2 * FRAME_SIZE - 1);
-    let frame = Frame::new(paddr);
+    let frame: Frame = Frame::new(paddr);
 
     assert_eq!(frame.start_address.address(), FRAME_SIZE);
 }
+
+#[test_case]
+fn test_huge_frame_creation_is_successful() {
+    let paddr = PhysicalAddress::new(3 * Size2MiB::SIZE + 0x1234);
+
+    let frame: Frame<Size2MiB> = Frame::new(paddr);
+    assert_eq!(frame.start_address().address(), 3 * Size2MiB::SIZE);
+    assert_eq!(frame.size(), Size2MiB::SIZE);
+
+    let frame: Frame<Size1GiB> = Frame::new(paddr);
+    assert_eq!(frame.start_address().address(), 0x00);
+    assert_eq!(frame.size(), Size1GiB::SIZE);
+}
+
+#[test_case]
+fn test_huge_frame_range_creation_is_successful() {
+    let start_frame: Frame<Size2MiB> = Frame::new(PhysicalAddress::new(Size2MiB::SIZE));
+    let end_frame: Frame<Size2MiB> = Frame::new(PhysicalAddress::new(4 * Size2MiB::SIZE));
+
+    let frame_range = FrameRange::new(start_frame, end_frame, /*is_inclusive*/ false);
+    assert_eq!(frame_range.num_frames(), 3);
+    assert_eq!(
+        frame_range.address_range(),
+        Size2MiB::SIZE..=(4 * Size2MiB::SIZE - 1)
+    );
+}
+
+#[test_case]
+fn test_single_frame_range_creation_is_successful() {
+    let frame: Frame = Frame::new(PhysicalAddress::new(FRAME_SIZE));
+
+    let frame_range = FrameRange::from_frame(frame);
+    assert_eq!(frame_range.start_frame(), frame);
+    assert_eq!(frame_range.end_frame(), frame);
+    assert_eq!(frame_range.num_frames(), 1);
+    assert_eq!(
+        frame_range.address_range(),
+        FRAME_SIZE..=(2 * FRAME_SIZE - 1)
+    );
+}
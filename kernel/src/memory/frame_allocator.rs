@@ -0,0 +1,386 @@
+use bootloader_api::info::{MemoryRegion, MemoryRegionKind};
+
+use crate::memory::frame::{Frame, FRAME_SIZE};
+use crate::memory::frame_descriptor::{
+    FrameDescriptor, FrameDescriptorTable, FrameOwner, FRAME_DESCRIPTORS_START,
+};
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page::{Page, PageRange, PAGE_SIZE};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+
+// Marks the end of the free frame list. Physical frames are 4KB aligned, so this value can never
+// be the start address of a frame.
+const FREE_LIST_END: u64 = u64::MAX;
+
+// The end of the memory addressable in real mode.
+const REAL_MODE_MEMORY_END: u64 = 0x10_0000;
+
+// A frame allocator hands out unused physical frames and takes them back once they are no longer
+// needed. Every subsystem that needs physical memory (page tables, the heap, stacks) goes through
+// this trait.
+pub trait FrameAllocator {
+    // Returns an unused frame, or None if physical memory is exhausted.
+    fn allocate_frame(&mut self) -> Option<Frame>;
+
+    // Returns a frame to the allocator. The frame must not be mapped or used anywhere else.
+    fn deallocate_frame(&mut self, frame: Frame);
+
+    // Returns an unused frame for the given use. Allocators that do not keep frame descriptors
+    // ignore the owner.
+    #[inline]
+    fn allocate_frame_for(&mut self, owner: FrameOwner) -> Option<Frame> {
+        let _ = owner;
+        self.allocate_frame()
+    }
+}
+
+// A frame allocator built from the memory map passed to the kernel by the bootloader.
+//
+// Fresh frames are handed out by walking the Usable regions of the memory map in order. Frames that
+// are deallocated are pushed onto an intrusive free list: each free frame stores the physical
+// address of the next free frame in its first 8 bytes. This lets us reuse frames without needing a
+// heap to hold the bookkeeping.
+pub struct MemoryMapFrameAllocator {
+    memory_regions: &'static [MemoryRegion],
+    paddr_offset: u64,
+
+    // Index of the memory region fresh frames are currently handed out from.
+    region_index: usize,
+
+    // Physical address of the next frame in the current region that has never been allocated.
+    next_frame_address: u64,
+
+    // Physical address of the first frame on the free list, or FREE_LIST_END.
+    free_list_head: u64,
+
+    total_frames: u64,
+    used_frames: u64,
+
+    // The metadata of every frame, once init_descriptors was called.
+    descriptors: Option<FrameDescriptorTable>,
+
+    // A frame in the first megabyte for the startup code of application processors.
+    startup_frame: Option<Frame>,
+}
+
+impl MemoryMapFrameAllocator {
+    // Creates a frame allocator from the bootloader memory map.
+    //
+    // ## Safety
+    // The caller must guarantee that all Usable regions in the memory map are really unused, and
+    // that the complete physical memory is mapped at paddr_offset.
+    #[inline]
+    pub unsafe fn init(
+        memory_regions: &'static [MemoryRegion],
+        paddr_offset: u64,
+    ) -> MemoryMapFrameAllocator {
+        let total_frames: u64 = memory_regions
+            .iter()
+            .filter_map(usable_frame_range)
+            .map(|(start, end)| (end - start) / FRAME_SIZE)
+            .sum();
+
+        MemoryMapFrameAllocator {
+            memory_regions,
+            paddr_offset,
+            region_index: 0,
+            next_frame_address: 0,
+            free_list_head: FREE_LIST_END,
+            total_frames,
+            used_frames: 0,
+            descriptors: None,
+            startup_frame: None,
+        }
+    }
+
+    // Maps a descriptor for every frame at FRAME_DESCRIPTORS_START and starts tracking the owner
+    // of every frame that is handed out. The frames holding the descriptors are owned by the
+    // kernel, all page tables reachable from paging are marked as such.
+    //
+    // This has to be called before any frame is allocated, so that no allocation goes unrecorded.
+    pub fn init_descriptors(&mut self, paging: &mut Paging) -> Result<(), MappingError> {
+        assert_eq!(
+            self.used_frames, 0,
+            "Frames were allocated before the descriptors"
+        );
+
+        // Application processors start in real mode, so their startup code needs a frame in the
+        // first megabyte. Fresh frames are handed out in ascending order, so the lowest usable frame
+        // is set aside before the descriptors take up low memory. If it lies above the first
+        // megabyte, it is handed back by rewinding to where the walk started.
+        let (region_index, next_frame_address) = (self.region_index, self.next_frame_address);
+        let startup_frame = self
+            .allocate_fresh_frame()
+            .filter(|frame| frame.start_address().address() < REAL_MODE_MEMORY_END);
+        if startup_frame.is_none() {
+            self.region_index = region_index;
+            self.next_frame_address = next_frame_address;
+        }
+
+        let size = FrameDescriptorTable::size(self.memory_regions);
+        let pages = PageRange::from_pages(
+            Page::new(VirtualAddress::new(FRAME_DESCRIPTORS_START)),
+            size.div_ceil(PAGE_SIZE),
+        );
+
+        for page in pages.iter() {
+            let frame = self
+                .allocate_frame()
+                .ok_or(MappingError::FrameAllocationFailed)?;
+            let flags =
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            paging.map_to(page, frame, flags, self)?;
+        }
+
+        let mut descriptors = unsafe {
+            FrameDescriptorTable::init(
+                self.memory_regions,
+                FRAME_DESCRIPTORS_START as *mut FrameDescriptor,
+            )
+        };
+        for page in pages.iter() {
+            let frame: Frame = Frame::new(paging.translate(page.start_address()).unwrap());
+            descriptors.allocate(frame, FrameOwner::Kernel);
+        }
+        descriptors.record_page_tables(paging);
+        if let Some(frame) = startup_frame {
+            descriptors.allocate(frame, FrameOwner::Kernel);
+            self.used_frames += 1;
+        }
+
+        self.descriptors = Some(descriptors);
+        self.startup_frame = startup_frame;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn descriptors(&self) -> Option<&FrameDescriptorTable> {
+        self.descriptors.as_ref()
+    }
+
+    #[inline]
+    pub fn descriptors_mut(&mut self) -> Option<&mut FrameDescriptorTable> {
+        self.descriptors.as_mut()
+    }
+
+    // The frame set aside for the startup code of application processors, or None if there is no
+    // usable memory in the first megabyte.
+    #[inline]
+    pub fn startup_frame(&self) -> Option<Frame> {
+        self.startup_frame
+    }
+
+    // The number of usable frames described by the memory map.
+    #[inline]
+    pub fn total_frames(&self) -> u64 {
+        self.total_frames
+    }
+
+    // The number of frames currently handed out.
+    #[inline]
+    pub fn used_frames(&self) -> u64 {
+        self.used_frames
+    }
+
+    // The number of frames that can still be allocated.
+    #[inline]
+    pub fn free_frames(&self) -> u64 {
+        self.total_frames - self.used_frames
+    }
+
+    // Returns a frame that has never been handed out before by walking the usable regions.
+    fn allocate_fresh_frame(&mut self) -> Option<Frame> {
+        while self.region_index < self.memory_regions.len() {
+            if let Some((start, end)) = usable_frame_range(&self.memory_regions[self.region_index])
+            {
+                if self.next_frame_address < start {
+                    self.next_frame_address = start;
+                }
+
+                if self.next_frame_address + FRAME_SIZE <= end {
+                    let frame = Frame::new(PhysicalAddress::new(self.next_frame_address));
+                    self.next_frame_address += FRAME_SIZE;
+                    return Some(frame);
+                }
+            }
+
+            self.region_index += 1;
+        }
+
+        None
+    }
+
+    // Returns a pointer to the free list link stored at the start of the given frame.
+    #[inline]
+    fn free_list_link(&self, frame_address: u64) -> *mut u64 {
+        VirtualAddress::new(frame_address + self.paddr_offset).address() as *mut u64
+    }
+}
+
+impl FrameAllocator for MemoryMapFrameAllocator {
+    // Frames allocated without a specific use are owned by the kernel.
+    #[inline]
+    fn allocate_frame(&mut self) -> Option<Frame> {
+        self.allocate_frame_for(FrameOwner::Kernel)
+    }
+
+    fn allocate_frame_for(&mut self, owner: FrameOwner) -> Option<Frame> {
+        let frame = if self.free_list_head != FREE_LIST_END {
+            let frame_address = self.free_list_head;
+            self.free_list_head = unsafe { self.free_list_link(frame_address).read() };
+            Frame::new(PhysicalAddress::new(frame_address))
+        } else {
+            self.allocate_fresh_frame()?
+        };
+
+        self.used_frames += 1;
+        if let Some(descriptors) = self.descriptors.as_mut() {
+            descriptors.allocate(frame, owner);
+        }
+
+        Some(frame)
+    }
+
+    fn deallocate_frame(&mut self, frame: Frame) {
+        if self.used_frames == 0 {
+            panic!("Deallocating {:?} while no frames are in use", frame);
+        }
+
+        if let Some(descriptors) = self.descriptors.as_mut() {
+            descriptors.deallocate(frame);
+        }
+
+        let frame_address = frame.start_address().address();
+        unsafe {
+            self.free_list_link(frame_address)
+                .write(self.free_list_head)
+        };
+        self.free_list_head = frame_address;
+        self.used_frames -= 1;
+    }
+}
+
+// Returns the frame aligned [start, end) range of a Usable memory region. Frame 0 is never handed
+// out, as a zero physical address is commonly used to denote a missing value.
+#[inline]
+pub(crate) fn usable_frame_range(region: &MemoryRegion) -> Option<(u64, u64)> {
+    if region.kind != MemoryRegionKind::Usable {
+        return None;
+    }
+
+    let start = ((region.start + FRAME_SIZE - 1) & !(FRAME_SIZE - 1)).max(FRAME_SIZE);
+    let end = region.end & !(FRAME_SIZE - 1);
+
+    if start >= end {
+        return None;
+    }
+
+    Some((start, end))
+}
+
+#[cfg(test)]
+const TEST_MEMORY_FRAMES: usize = 8;
+
+// Backing memory for the synthetic memory maps used in the tests below. We use a physical memory
+// offset of 0, so the "physical" addresses handed out are addresses within this buffer.
+#[cfg(test)]
+#[allow(dead_code)]
+#[repr(align(4096))]
+struct TestMemory([u8; TEST_MEMORY_FRAMES * FRAME_SIZE as usize]);
+
+#[cfg(test)]
+static mut TEST_MEMORY: TestMemory = TestMemory([0; TEST_MEMORY_FRAMES * FRAME_SIZE as usize]);
+
+#[cfg(test)]
+static mut TEST_MEMORY_REGIONS: [MemoryRegion; 3] = [MemoryRegion::empty(); 3];
+
+// Builds a memory map with two usable regions (frames 0..3 and 4..8 of TEST_MEMORY) separated by a
+// region reserved for the bootloader.
+#[cfg(test)]
+fn test_frame_allocator() -> MemoryMapFrameAllocator {
+    let base = (&raw const TEST_MEMORY) as u64;
+
+    unsafe {
+        let regions = &mut *(&raw mut TEST_MEMORY_REGIONS);
+        regions[0] = MemoryRegion {
+            start: base,
+            end: base + 3 * FRAME_SIZE,
+            kind: MemoryRegionKind::Usable,
+        };
+        regions[1] = MemoryRegion {
+            start: base + 3 * FRAME_SIZE,
+            end: base + 4 * FRAME_SIZE,
+            kind: MemoryRegionKind::Bootloader,
+        };
+        regions[2] = MemoryRegion {
+            start: base + 4 * FRAME_SIZE,
+            end: base + TEST_MEMORY_FRAMES as u64 * FRAME_SIZE,
+            kind: MemoryRegionKind::Usable,
+        };
+
+        MemoryMapFrameAllocator::init(&*(&raw const TEST_MEMORY_REGIONS), 0)
+    }
+}
+
+#[test_case]
+fn test_frame_allocator_counts_usable_frames() {
+    let allocator = test_frame_allocator();
+    assert_eq!(allocator.total_frames(), 7);
+    assert_eq!(allocator.used_frames(), 0);
+    assert_eq!(allocator.free_frames(), 7);
+}
+
+#[test_case]
+fn test_frame_allocator_skips_reserved_regions() {
+    let mut allocator = test_frame_allocator();
+    let base = (&raw const TEST_MEMORY) as u64;
+
+    for i in [0, 1, 2, 4, 5, 6, 7] {
+        let frame = allocator.allocate_frame().unwrap();
+        assert_eq!(frame.start_address().address(), base + i * FRAME_SIZE);
+    }
+
+    assert_eq!(allocator.allocate_frame(), None);
+    assert_eq!(allocator.used_frames(), 7);
+    assert_eq!(allocator.free_frames(), 0);
+}
+
+#[test_case]
+fn test_frame_allocator_reuses_deallocated_frames() {
+    let mut allocator = test_frame_allocator();
+
+    let first = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_eq!(allocator.used_frames(), 2);
+
+    allocator.deallocate_frame(first);
+    allocator.deallocate_frame(second);
+    assert_eq!(allocator.used_frames(), 0);
+
+    // Freed frames are handed out in LIFO order before any fresh frame.
+    assert_eq!(allocator.allocate_frame(), Some(second));
+    assert_eq!(allocator.allocate_frame(), Some(first));
+    assert_eq!(allocator.used_frames(), 2);
+}
+
+#[test_case]
+fn test_usable_frame_range_aligns_region() {
+    let region = MemoryRegion {
+        start: FRAME_SIZE + 0x18,
+        end: 4 * FRAME_SIZE + 0x18,
+        kind: MemoryRegionKind::Usable,
+    };
+    assert_eq!(
+        usable_frame_range(&region),
+        Some((2 * FRAME_SIZE, 4 * FRAME_SIZE))
+    );
+
+    let reserved = MemoryRegion {
+        start: 0,
+        end: 4 * FRAME_SIZE,
+        kind: MemoryRegionKind::Bootloader,
+    };
+    assert_eq!(usable_frame_range(&reserved), None);
+}
@@ -0,0 +1,355 @@
+use bitflags::bitflags;
+use bootloader_api::info::MemoryRegion;
+use core::fmt;
+use core::mem::size_of;
+
+use crate::memory::frame::{Frame, FRAME_SIZE};
+use crate::memory::frame_allocator::usable_frame_range;
+#[cfg(test)]
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page_table::{PageTable, PageTableFlags};
+use crate::memory::paging::{get_page_table_ptr, Paging};
+use crate::serial_println;
+#[cfg(test)]
+use bootloader_api::info::MemoryRegionKind;
+
+// The descriptor array lives in its own region of the upper half of the virtual address space.
+pub const FRAME_DESCRIPTORS_START: u64 = 0x_ffff_c800_0000_0000;
+
+// What a physical frame is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum FrameOwner {
+    // The frame can be handed out by the frame allocator.
+    Free,
+
+    // The frame is not managed by the frame allocator, e.g. firmware, the kernel image or memory
+    // mapped devices.
+    Reserved,
+
+    // The frame is in use by the kernel, e.g. for stacks or lazily mapped pages.
+    Kernel,
+
+    PageTable,
+    Heap,
+
+    // The frame holds the private copy of a copy-on-write page. It is freed along with the last
+    // page table entry mapping it.
+    User,
+}
+
+bitflags! {
+    #[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy)]
+    pub struct FrameFlags: u8 {
+        // The frame is mapped copy-on-write by more than one page table
+        const COPY_ON_WRITE = 1;
+    }
+}
+
+// The metadata of a single physical frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct FrameDescriptor {
+    // The number of page table entries sharing the frame. Allocated frames start out with one.
+    refcount: u32,
+    owner: FrameOwner,
+    flags: FrameFlags,
+}
+
+impl FrameDescriptor {
+    #[inline]
+    const fn new(owner: FrameOwner) -> FrameDescriptor {
+        FrameDescriptor {
+            refcount: 0,
+            owner,
+            flags: FrameFlags::empty(),
+        }
+    }
+
+    #[inline]
+    pub fn refcount(&self) -> u32 {
+        self.refcount
+    }
+
+    #[inline]
+    pub fn owner(&self) -> FrameOwner {
+        self.owner
+    }
+
+    #[inline]
+    pub fn flags(&self) -> FrameFlags {
+        self.flags
+    }
+}
+
+// A descriptor for every physical frame up to the end of the last Usable memory region, indexed by
+// frame number. Reserved regions above it, e.g. device memory, are not described.
+pub struct FrameDescriptorTable {
+    descriptors: &'static mut [FrameDescriptor],
+
+    // The number of frames in Usable regions, i.e. the RAM the kernel can use.
+    usable_frames: u64,
+}
+
+impl FrameDescriptorTable {
+    // The number of bytes needed to describe all frames of the memory map.
+    #[inline]
+    pub fn size(memory_regions: &[MemoryRegion]) -> u64 {
+        num_frames(memory_regions) * size_of::<FrameDescriptor>() as u64
+    }
+
+    // Creates the table in the given memory, which has to be at least size bytes large. Frames in
+    // Usable regions start out free, all other frames are reserved.
+    //
+    // ## Safety
+    // The memory must be valid for writes and must not be used for anything else.
+    pub unsafe fn init(
+        memory_regions: &[MemoryRegion],
+        start: *mut FrameDescriptor,
+    ) -> FrameDescriptorTable {
+        let descriptors =
+            unsafe { core::slice::from_raw_parts_mut(start, num_frames(memory_regions) as usize) };
+        descriptors.fill(FrameDescriptor::new(FrameOwner::Reserved));
+
+        let mut usable_frames = 0;
+        for (start, end) in memory_regions.iter().filter_map(usable_frame_range) {
+            descriptors[(start / FRAME_SIZE) as usize..(end / FRAME_SIZE) as usize]
+                .fill(FrameDescriptor::new(FrameOwner::Free));
+            usable_frames += (end - start) / FRAME_SIZE;
+        }
+
+        FrameDescriptorTable {
+            descriptors,
+            usable_frames,
+        }
+    }
+
+    // Returns the descriptor of the frame, or None if the frame lies beyond the memory map.
+    #[inline]
+    pub fn get(&self, frame: Frame) -> Option<&FrameDescriptor> {
+        self.descriptors.get(frame_number(frame))
+    }
+
+    // Records that the frame was handed out for the given use.
+    #[inline]
+    pub fn allocate(&mut self, frame: Frame, owner: FrameOwner) {
+        if let Some(descriptor) = self.descriptors.get_mut(frame_number(frame)) {
+            *descriptor = FrameDescriptor {
+                refcount: 1,
+                owner,
+                flags: FrameFlags::empty(),
+            };
+        }
+    }
+
+    // Records that the frame was returned to the frame allocator.
+    #[inline]
+    pub fn deallocate(&mut self, frame: Frame) {
+        if let Some(descriptor) = self.descriptors.get_mut(frame_number(frame)) {
+            if descriptor.owner == FrameOwner::Free {
+                panic!("Deallocating {:?}, which is already free", frame);
+            }
+
+            *descriptor = FrameDescriptor::new(FrameOwner::Free);
+        }
+    }
+
+    // Marks every page table reachable from the level 4 page table of paging, including the level
+    // 4 page table itself, as owned by PageTable.
+    pub fn record_page_tables(&mut self, paging: &Paging) {
+        self.record_page_table(paging.paddr_offset(), paging.level_4_page_table_frame(), 4);
+    }
+
+    fn record_page_table(&mut self, paddr_offset: u64, frame: Frame, level: u16) {
+        self.allocate(frame, FrameOwner::PageTable);
+        if level == 1 {
+            return;
+        }
+
+        let page_table: &PageTable =
+            unsafe { &*get_page_table_ptr(paddr_offset, frame.start_address()) };
+        for pte in page_table.iter() {
+            if let Some(frame) = pte.frame() {
+                if !pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+                    self.record_page_table(paddr_offset, frame, level - 1);
+                }
+            }
+        }
+    }
+
+    // Adds a copy-on-write mapping of the frame and returns the new reference count. Frames the
+    // frame allocator does not know about are treated as having one owner.
+    #[inline]
+    pub fn share(&mut self, frame: Frame) -> u32 {
+        match self.descriptors.get_mut(frame_number(frame)) {
+            Some(descriptor) => {
+                descriptor.refcount = descriptor.refcount.max(1) + 1;
+                descriptor.flags |= FrameFlags::COPY_ON_WRITE;
+                descriptor.refcount
+            }
+            None => 1,
+        }
+    }
+
+    // Removes a copy-on-write mapping of the frame and returns the new reference count. Once a
+    // single mapping remains, the frame is no longer shared.
+    #[inline]
+    pub fn unshare(&mut self, frame: Frame) -> u32 {
+        match self.descriptors.get_mut(frame_number(frame)) {
+            Some(descriptor) => {
+                descriptor.refcount = descriptor.refcount.saturating_sub(1).max(1);
+                if descriptor.refcount == 1 {
+                    descriptor.flags -= FrameFlags::COPY_ON_WRITE;
+                }
+                descriptor.refcount
+            }
+            None => 1,
+        }
+    }
+
+    // Counts the frames of every owner.
+    pub fn meminfo(&self) -> MemInfo {
+        let mut meminfo = MemInfo::default();
+        for descriptor in self.descriptors.iter() {
+            let frames = match descriptor.owner {
+                FrameOwner::Free => &mut meminfo.free,
+                FrameOwner::Reserved => &mut meminfo.reserved,
+                FrameOwner::Kernel => &mut meminfo.kernel,
+                FrameOwner::PageTable => &mut meminfo.page_tables,
+                FrameOwner::Heap => &mut meminfo.heap,
+                FrameOwner::User => &mut meminfo.user,
+            };
+            *frames += 1;
+        }
+
+        meminfo.total = self.usable_frames;
+        meminfo
+    }
+}
+
+// The number of frames of every kind of use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemInfo {
+    pub total: u64,
+    pub free: u64,
+    pub kernel: u64,
+    pub page_tables: u64,
+    pub heap: u64,
+    pub user: u64,
+    pub reserved: u64,
+}
+
+impl MemInfo {
+    // Prints the report to the serial port.
+    pub fn dump(&self) {
+        serial_println!("{}", self);
+    }
+}
+
+impl fmt::Display for MemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            ("MemTotal", self.total),
+            ("MemFree", self.free),
+            ("Kernel", self.kernel),
+            ("PageTables", self.page_tables),
+            ("Heap", self.heap),
+            ("User", self.user),
+            ("Reserved", self.reserved),
+        ];
+
+        for (i, (name, frames)) in lines.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:<12}{:>10} KiB", name, frames * FRAME_SIZE / 1024)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The number of frames up to the end of the last Usable memory region.
+#[inline]
+fn num_frames(memory_regions: &[MemoryRegion]) -> u64 {
+    let end = memory_regions
+        .iter()
+        .filter_map(usable_frame_range)
+        .map(|(_, end)| end)
+        .max()
+        .unwrap_or(0);
+
+    end / FRAME_SIZE
+}
+
+#[inline]
+fn frame_number(frame: Frame) -> usize {
+    (frame.start_address().address() / FRAME_SIZE) as usize
+}
+
+#[test_case]
+fn test_frame_descriptor_table() {
+    let regions = [
+        MemoryRegion {
+            start: 0,
+            end: 4 * FRAME_SIZE,
+            kind: MemoryRegionKind::Bootloader,
+        },
+        MemoryRegion {
+            start: 4 * FRAME_SIZE,
+            end: 12 * FRAME_SIZE,
+            kind: MemoryRegionKind::Usable,
+        },
+        // Firmware memory far above the RAM is not described.
+        MemoryRegion {
+            start: 0x_fd_0000_0000,
+            end: 0x_100_0000_0000,
+            kind: MemoryRegionKind::UnknownBios(2),
+        },
+    ];
+    assert_eq!(
+        FrameDescriptorTable::size(&regions),
+        12 * size_of::<FrameDescriptor>() as u64
+    );
+
+    let mut memory = alloc::vec![FrameDescriptor::new(FrameOwner::Free); 12];
+    let mut table = unsafe { FrameDescriptorTable::init(&regions, memory.as_mut_ptr()) };
+    let frame = |number: u64| Frame::new(PhysicalAddress::new(number * FRAME_SIZE));
+
+    assert_eq!(table.get(frame(0)).unwrap().owner(), FrameOwner::Reserved);
+    assert_eq!(table.get(frame(4)).unwrap().owner(), FrameOwner::Free);
+    assert!(table.get(frame(12)).is_none());
+
+    table.allocate(frame(4), FrameOwner::PageTable);
+    table.allocate(frame(5), FrameOwner::Heap);
+    table.allocate(frame(6), FrameOwner::Kernel);
+    assert_eq!(table.get(frame(4)).unwrap().refcount(), 1);
+    assert_eq!(
+        table.meminfo(),
+        MemInfo {
+            total: 8,
+            free: 5,
+            kernel: 1,
+            page_tables: 1,
+            heap: 1,
+            user: 0,
+            reserved: 4,
+        }
+    );
+
+    // Shared frames are counted once per mapping.
+    assert_eq!(table.share(frame(6)), 2);
+    assert_eq!(table.share(frame(6)), 3);
+    assert!(table
+        .get(frame(6))
+        .unwrap()
+        .flags()
+        .contains(FrameFlags::COPY_ON_WRITE));
+    assert_eq!(table.unshare(frame(6)), 2);
+    assert_eq!(table.unshare(frame(6)), 1);
+    assert_eq!(table.get(frame(6)).unwrap().flags(), FrameFlags::empty());
+
+    table.deallocate(frame(6));
+    assert_eq!(table.get(frame(6)).unwrap().owner(), FrameOwner::Free);
+    assert_eq!(table.meminfo().free, 6);
+}
@@ -0,0 +1,94 @@
+use core::alloc::Layout;
+
+use crate::memory::allocator::{HeapAllocator, HeapStats, Locked};
+use crate::memory::frame_allocator::FrameAllocator;
+use crate::memory::frame_descriptor::FrameOwner;
+use crate::memory::page::{Page, PageRange};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+
+// The kernel heap lives in its own region of the upper half of the virtual address space. The start
+// address is arbitrary, it only needs to be unused.
+pub const HEAP_START: u64 = 0x_ffff_c444_4444_0000;
+pub const HEAP_SIZE: u64 = 1024 * 1024; // 1 MiB
+
+// The allocator design backing the kernel heap is selected at compile time. If more than one
+// allocator feature is enabled, the simplest design wins, so that e.g.
+// `--features bump-allocator` works without disabling the default features.
+#[cfg(feature = "bump-allocator")]
+type KernelAllocator = crate::memory::allocator::bump::BumpAllocator;
+
+#[cfg(all(feature = "linked-list-allocator", not(feature = "bump-allocator")))]
+type KernelAllocator = crate::memory::allocator::linked_list::LinkedListAllocator;
+
+#[cfg(all(
+    feature = "fixed-size-block-allocator",
+    not(any(feature = "bump-allocator", feature = "linked-list-allocator"))
+))]
+type KernelAllocator = crate::memory::allocator::fixed_size_block::FixedSizeBlockAllocator;
+
+#[cfg(not(any(
+    feature = "bump-allocator",
+    feature = "linked-list-allocator",
+    feature = "fixed-size-block-allocator"
+)))]
+compile_error!(
+    "No heap allocator selected. Enable one of the bump-allocator, linked-list-allocator or \
+     fixed-size-block-allocator features."
+);
+
+#[global_allocator]
+static ALLOCATOR: Locked<KernelAllocator> = Locked::new(KernelAllocator::new());
+
+// Maps the heap region to frames from the frame allocator and hands it to the global allocator.
+// Once this returns, the alloc crate (Box, Vec, String, BTreeMap, ...) can be used.
+pub fn init_heap<A: FrameAllocator + ?Sized>(
+    paging: &mut Paging,
+    frame_allocator: &mut A,
+) -> Result<(), MappingError> {
+    let heap_start: Page = Page::new(VirtualAddress::new(HEAP_START));
+    let heap_end: Page = Page::new(VirtualAddress::new(HEAP_START + HEAP_SIZE));
+    let heap_pages = PageRange::new(heap_start, heap_end, /*is_inclusive*/ false);
+
+    for page in heap_pages.iter() {
+        let frame = frame_allocator
+            .allocate_frame_for(FrameOwner::Heap)
+            .ok_or(MappingError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        paging.map_to(page, frame, flags, frame_allocator)?;
+    }
+
+    unsafe {
+        ALLOCATOR
+            .lock()
+            .init(HEAP_START as usize, HEAP_SIZE as usize);
+    }
+
+    log::info!(
+        "Heap initialized with the {} allocator: {} KiB at {:#x}",
+        KernelAllocator::NAME,
+        HEAP_SIZE / 1024,
+        HEAP_START
+    );
+
+    Ok(())
+}
+
+// Returns a snapshot of the free memory and live allocations of the kernel heap.
+#[inline]
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+// Called when a heap allocation fails. Allocation failures are not recoverable for the kernel, so
+// we log the failed layout and panic.
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    log::error!(
+        "Heap allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    );
+    panic!("Heap allocation failed: {:?}", layout)
+}
@@ -0,0 +1,99 @@
+use crate::memory::frame::Frame;
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page::{Page, PageRange, PAGE_SIZE};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::vma::{
+    AreaKind, Backing, VirtualMemoryArea, VmaError, DYNAMIC_AREA_WINDOW, KERNEL_AREAS,
+};
+use crate::memory::{frame_allocator, paging};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MmioError {
+    // No space for the mapping could be found in the kernel address space.
+    Area(VmaError),
+
+    // The device memory could not be mapped.
+    Mapping(MappingError),
+}
+
+// Maps size bytes of device memory starting at paddr into the kernel address space and returns the
+// virtual address of paddr. The pages are not cached, as device registers have side effects on
+// every access.
+//
+// Device memory is usually not part of the memory map, so it is not covered by the physical memory
+// mapping of the bootloader.
+pub fn map_mmio(paddr: PhysicalAddress, size: u64) -> Result<VirtualAddress, MmioError> {
+    let first_frame: Frame = Frame::new(paddr);
+    let offset = paddr.address() - first_frame.start_address().address();
+    let size = (offset + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+
+    let mut areas = KERNEL_AREAS.lock();
+    let start = areas
+        .find_free_gap(
+            size,
+            PAGE_SIZE,
+            VirtualAddress::new(DYNAMIC_AREA_WINDOW.start)
+                ..VirtualAddress::new(DYNAMIC_AREA_WINDOW.end),
+        )
+        .map_err(MmioError::Area)?;
+
+    let pages = PageRange::from_pages(Page::new(start), size / PAGE_SIZE);
+
+    let mut paging = paging();
+    let mut frame_allocator = frame_allocator();
+    for (i, page) in pages.iter().enumerate() {
+        let frame: Frame = Frame::new(first_frame.start_address() + i as u64 * PAGE_SIZE);
+        if let Err(error) = paging.map_to(page, frame, flags(), &mut *frame_allocator) {
+            unmap_pages(&mut paging, pages.iter().take(i));
+            return Err(MmioError::Mapping(error));
+        }
+    }
+
+    let area = VirtualMemoryArea::new(
+        start,
+        size,
+        AreaKind::Mmio,
+        flags(),
+        Backing::Physical(first_frame.start_address()),
+    );
+    if let Err(error) = areas.insert(area) {
+        unmap_pages(&mut paging, pages.iter());
+        return Err(MmioError::Area(error));
+    }
+
+    Ok(start + offset)
+}
+
+// Undoes the mappings of map_mmio. The frames are not owned by the frame allocator, so they are not
+// freed.
+#[inline]
+fn unmap_pages(paging: &mut Paging, pages: impl Iterator<Item = Page>) {
+    for page in pages {
+        paging.unmap(page).unwrap();
+    }
+}
+
+#[inline]
+fn flags() -> PageTableFlags {
+    PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::NO_EXECUTE
+        | PageTableFlags::DISABLE_CACHING
+}
+
+#[test_case]
+fn test_map_mmio() {
+    // The local APIC registers of the bootstrap processor live in this frame on every PC.
+    let paddr = PhysicalAddress::new(0x_FEE0_0020);
+    let vaddr = map_mmio(paddr, 4).unwrap();
+
+    assert_eq!(vaddr.address() % PAGE_SIZE, 0x20);
+    assert_eq!(paging().translate(vaddr), Some(paddr));
+
+    let area = *KERNEL_AREAS.lock().find(vaddr).unwrap();
+    assert_eq!(area.kind(), AreaKind::Mmio);
+    assert_eq!(area.size(), PAGE_SIZE);
+    assert!(area.flags().contains(PageTableFlags::DISABLE_CACHING));
+}
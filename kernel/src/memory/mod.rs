@@ -1,6 +1,153 @@
+use bootloader_api::info::MemoryRegion;
+use conquer_once::spin::OnceCell;
+use spin::{Mutex, MutexGuard};
+
+use crate::memory::frame_allocator::MemoryMapFrameAllocator;
+use crate::memory::frame_descriptor::{FrameDescriptorTable, MemInfo, FRAME_DESCRIPTORS_START};
+use crate::memory::heap::{HEAP_SIZE, HEAP_START};
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page::{Page, PAGE_SIZE};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::Paging;
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::vma::{AreaKind, Backing, VirtualMemoryArea, KERNEL_AREAS};
+
+pub mod address_space;
+pub mod allocator;
+pub mod buddy;
+pub mod cow;
+pub mod dump;
+pub mod fault;
 pub mod frame;
+pub mod frame_allocator;
+pub mod frame_descriptor;
+pub mod heap;
+pub mod mmio;
 pub mod paddr;
 pub mod page;
+pub mod page_size;
 pub mod page_table;
 pub mod paging;
+pub mod protection;
+pub mod stack;
 pub mod vaddr;
+pub mod vma;
+
+// The kernel page tables and the frame allocator. Both are needed outside of the boot path, e.g.
+// by the page fault handler to back lazily allocated areas. When several memory locks are needed,
+// they have to be taken in the order KERNEL_AREAS, PAGING, FRAME_ALLOCATOR.
+static PAGING: OnceCell<Mutex<Paging>> = OnceCell::uninit();
+static FRAME_ALLOCATOR: OnceCell<Mutex<MemoryMapFrameAllocator>> = OnceCell::uninit();
+
+// Initializes paging, the physical frame allocator and the kernel heap, and registers the heap and
+// the physical memory mapping in the kernel address space.
+//
+// ## Safety
+// The caller must guarantee that all Usable regions in the memory map are really unused, and that
+// the complete physical memory is mapped at paddr_offset. This function must be called only once.
+pub unsafe fn init(memory_regions: &'static [MemoryRegion], paddr_offset: u64) {
+    let mut paging = Paging::init(paddr_offset);
+    let mut frame_allocator =
+        unsafe { MemoryMapFrameAllocator::init(memory_regions, paddr_offset) };
+
+    // Keep track of the owner of every frame, starting with the very first allocation.
+    frame_allocator
+        .init_descriptors(&mut paging)
+        .expect("Frame descriptor initialization failed");
+
+    // Give every upper half level 4 entry a page table before the first address space copies them.
+    address_space::populate_kernel_space(&paging, &mut frame_allocator)
+        .expect("Kernel address space initialization failed");
+
+    // Map the kernel heap. From here on, the alloc crate can be used.
+    heap::init_heap(&mut paging, &mut frame_allocator).expect("Heap initialization failed");
+
+    let mut areas = KERNEL_AREAS.lock();
+    areas
+        .insert(VirtualMemoryArea::new(
+            VirtualAddress::new(HEAP_START),
+            HEAP_SIZE,
+            AreaKind::Heap,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+            Backing::Mapped,
+        ))
+        .expect("Heap area overlaps with another area");
+
+    // The bootloader maps all of physical memory, up to the end of the last memory region.
+    let physical_memory_size = memory_regions
+        .iter()
+        .map(|region| region.end)
+        .max()
+        .unwrap_or(0);
+    areas
+        .insert(VirtualMemoryArea::new(
+            VirtualAddress::new(paddr_offset),
+            (physical_memory_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            AreaKind::PhysicalMemory,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Physical(PhysicalAddress::zero()),
+        ))
+        .expect("Physical memory area overlaps with another area");
+    let descriptors_size = FrameDescriptorTable::size(memory_regions);
+    areas
+        .insert(VirtualMemoryArea::new(
+            VirtualAddress::new(FRAME_DESCRIPTORS_START),
+            (descriptors_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            AreaKind::FrameDescriptors,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Mapped,
+        ))
+        .expect("Frame descriptor area overlaps with another area");
+    drop(areas);
+
+    // Enforce the permissions of the kernel image, e.g. no writes to code and no execution of data.
+    protection::enable_protection_features();
+    protection::protect_kernel_image(&mut paging).expect("Kernel image protection failed");
+
+    PAGING.init_once(|| Mutex::new(paging));
+    FRAME_ALLOCATOR.init_once(|| Mutex::new(frame_allocator));
+}
+
+// Returns the kernel page tables. Panics if memory is not initialized.
+#[inline]
+pub fn paging() -> MutexGuard<'static, Paging> {
+    PAGING.get().expect("Memory is not initialized").lock()
+}
+
+// Returns the kernel frame allocator. Panics if memory is not initialized.
+#[inline]
+pub fn frame_allocator() -> MutexGuard<'static, MemoryMapFrameAllocator> {
+    FRAME_ALLOCATOR
+        .get()
+        .expect("Memory is not initialized")
+        .lock()
+}
+
+// Returns how much physical memory is used for what.
+#[inline]
+pub fn meminfo() -> MemInfo {
+    frame_allocator()
+        .descriptors()
+        .expect("Frame descriptors are not initialized")
+        .meminfo()
+}
+
+// Records the framebuffer mapped by the bootloader in the kernel address space.
+pub fn register_frame_buffer(start: VirtualAddress, size: u64) {
+    let first_page: Page = Page::new(start);
+    let size = start.address() - first_page.start_address().address() + size;
+    let paddr = paging()
+        .translate(first_page.start_address())
+        .expect("Framebuffer is not mapped");
+
+    KERNEL_AREAS
+        .lock()
+        .insert(VirtualMemoryArea::new(
+            first_page.start_address(),
+            (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            AreaKind::FrameBuffer,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            Backing::Physical(paddr),
+        ))
+        .expect("Framebuffer area overlaps with another area");
+}
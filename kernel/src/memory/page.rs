@@ -1,24 +1,31 @@
+use core::marker::PhantomData;
 use core::ops::RangeInclusive;
 
+use crate::memory::page_size::{PageSize, Size4KiB};
+#[cfg(test)]
+use crate::memory::page_size::{Size1GiB, Size2MiB};
 use crate::memory::vaddr::VirtualAddress;
 
 // x86 uses a page size of 4KB
-pub const PAGE_SIZE: u64 = 4096;
+pub const PAGE_SIZE: u64 = Size4KiB::SIZE;
 
+// A virtual page. Pages default to 4KB, but 2MB and 1GB huge pages are supported as well.
 #[derive(Clone, Debug, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
-pub struct Page {
+pub struct Page<S: PageSize = Size4KiB> {
     start_address: VirtualAddress,
+    size: PhantomData<S>,
 }
 
-impl Page {
+impl<S: PageSize> Page<S> {
     // Returns a page whose start address is the largest that is less than
     // the address provided.
     #[inline]
-    pub fn new(vaddr: VirtualAddress) -> Page {
-        let address = VirtualAddress::new(vaddr.address() & !(PAGE_SIZE - 1));
+    pub fn new(vaddr: VirtualAddress) -> Page<S> {
+        let address = VirtualAddress::new(vaddr.address() & !(S::SIZE - 1));
         Page {
             start_address: address,
+            size: PhantomData,
         }
     }
 
@@ -26,20 +33,25 @@ impl Page {
     pub fn start_address(&self) -> VirtualAddress {
         self.start_address
     }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        S::SIZE
+    }
 }
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 #[repr(C)]
-pub struct PageRange {
-    start_page: Page,
-    end_page: Page,
+pub struct PageRange<S: PageSize = Size4KiB> {
+    start_page: Page<S>,
+    end_page: Page<S>,
     is_inclusive: bool,
 }
 
-impl PageRange {
+impl<S: PageSize> PageRange<S> {
     #[inline]
-    pub fn new(start_page: Page, end_page: Page, is_inclusive: bool) -> PageRange {
-        if (start_page.start_address.address() + PAGE_SIZE) >= end_page.start_address.address() {
+    pub fn new(start_page: Page<S>, end_page: Page<S>, is_inclusive: bool) -> PageRange<S> {
+        if (start_page.start_address.address() + S::SIZE) >= end_page.start_address.address() {
             panic!("Start Page overlaps with end frame");
         }
 
@@ -50,13 +62,27 @@ impl PageRange {
         }
     }
 
+    // The num_pages pages starting with the given page. Unlike new, this allows a single page.
+    #[inline]
+    pub fn from_pages(start_page: Page<S>, num_pages: u64) -> PageRange<S> {
+        if num_pages == 0 {
+            panic!("Page range without pages");
+        }
+
+        PageRange {
+            start_page,
+            end_page: Page::new(start_page.start_address + (num_pages - 1) * S::SIZE),
+            is_inclusive: true,
+        }
+    }
+
     #[inline]
-    pub fn start_page(&self) -> Page {
+    pub fn start_page(&self) -> Page<S> {
         self.start_page
     }
 
     #[inline]
-    pub fn end_page(&self) -> Page {
+    pub fn end_page(&self) -> Page<S> {
         self.end_page
     }
 
@@ -69,7 +95,7 @@ impl PageRange {
     pub fn num_pages(&self) -> u64 {
         let num_pages = (self.end_page.start_address.address()
             - self.start_page.start_address.address())
-            / PAGE_SIZE;
+            / S::SIZE;
 
         if self.is_inclusive {
             return num_pages + 1;
@@ -82,18 +108,25 @@ impl PageRange {
     pub fn address_range(&self) -> RangeInclusive<u64> {
         if self.is_inclusive {
             return self.start_page.start_address.address()
-                ..=(self.end_page.start_address.address() + PAGE_SIZE - 1);
+                ..=(self.end_page.start_address.address() + S::SIZE - 1);
         }
 
         return self.start_page.start_address.address()
             ..=(self.end_page.start_address.address() - 1);
     }
+
+    // Returns an iterator over all pages in the range.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = Page<S>> {
+        let start_address = self.start_page.start_address;
+        (0..self.num_pages()).map(move |i| Page::new(start_address + i * S::SIZE))
+    }
 }
 
 #[test_case]
 fn test_page_creation_is_successful() {
     let vaddr = VirtualAddress::new(0x18);
-    let page = Page::new(vaddr);
+    let page: Page = Page::new(vaddr);
 
     assert_eq!(page.start_address.address(), 0x00);
 }
@@ -103,8 +136,8 @@ fn test_page_range_creation_is_successful() {
     let vaddr1 = VirtualAddress::new(0x18);
     let vaddr2 = vaddr1 + 3 * PAGE_SIZE;
 
-    let start_page = Page::new(vaddr1);
-    let end_page = Page::new(vaddr2);
+    let start_page: Page = Page::new(vaddr1);
+    let end_page: Page = Page::new(vaddr2);
 
     let page_range = PageRange::new(start_page, end_page, /*is_inclusive*/ false);
     assert_eq!(page_range.num_pages(), 3);
@@ -118,10 +151,60 @@ fn test_page_range_creation_is_successful() {
     );
 }
 
+#[test_case]
+fn test_page_range_iterator() {
+    let start_page: Page = Page::new(VirtualAddress::new(PAGE_SIZE));
+    let end_page: Page = Page::new(VirtualAddress::new(4 * PAGE_SIZE));
+
+    let page_range = PageRange::new(start_page, end_page, /*is_inclusive*/ false);
+    let mut expected_address = PAGE_SIZE;
+    for page in page_range.iter() {
+        assert_eq!(page.start_address().address(), expected_address);
+        expected_address += PAGE_SIZE;
+    }
+    assert_eq!(expected_address, 4 * PAGE_SIZE);
+
+    let inclusive_page_range = PageRange::new(start_page, end_page, /*is_inclusive*/ true);
+    assert_eq!(inclusive_page_range.iter().count(), 4);
+}
+
 #[test_case]
 fn test_page_creation_with_in_between_address_successful() {
     let vaddr = VirtualAddress::new(2 * PAGE_SIZE - 1);
-    let frame = Page::new(vaddr);
+    let frame: Page = Page::new(vaddr);
 
     assert_eq!(frame.start_address.address(), PAGE_SIZE);
 }
+
+#[test_case]
+fn test_huge_page_creation_is_successful() {
+    let vaddr = VirtualAddress::new(Size1GiB::SIZE + 5 * Size2MiB::SIZE + 0x1234);
+
+    let page: Page<Size2MiB> = Page::new(vaddr);
+    assert_eq!(
+        page.start_address().address(),
+        Size1GiB::SIZE + 5 * Size2MiB::SIZE
+    );
+    assert_eq!(page.size(), Size2MiB::SIZE);
+
+    let page: Page<Size1GiB> = Page::new(vaddr);
+    assert_eq!(page.start_address().address(), Size1GiB::SIZE);
+    assert_eq!(page.size(), Size1GiB::SIZE);
+}
+
+#[test_case]
+fn test_page_range_from_pages_is_successful() {
+    let start_page: Page = Page::new(VirtualAddress::new(PAGE_SIZE));
+
+    let page_range = PageRange::from_pages(start_page, 1);
+    assert_eq!(page_range.num_pages(), 1);
+    assert_eq!(page_range.iter().count(), 1);
+    assert_eq!(page_range.address_range(), PAGE_SIZE..=(2 * PAGE_SIZE - 1));
+
+    let page_range = PageRange::from_pages(start_page, 3);
+    assert_eq!(page_range.num_pages(), 3);
+    assert_eq!(
+        page_range.end_page().start_address().address(),
+        3 * PAGE_SIZE
+    );
+}
@@ -0,0 +1,49 @@
+use core::fmt;
+use core::hash::Hash;
+
+// x86_64 supports three page sizes. A 4KB page is mapped by a level 1 page table entry. Huge pages
+// are mapped by setting the HUGE_PAGE flag on a level 2 (2MB) or a level 3 (1GB) entry, which
+// stops the page table walk early.
+pub trait PageSize: Copy + Eq + Ord + Hash + fmt::Debug {
+    // The size of the page in bytes.
+    const SIZE: u64;
+
+    // The page table level whose entries map pages of this size.
+    const LEVEL: u16;
+
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size4KiB {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size2MiB {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Size1GiB {}
+
+impl PageSize for Size4KiB {
+    const SIZE: u64 = 4096;
+    const LEVEL: u16 = 1;
+    const NAME: &'static str = "4KiB";
+}
+
+impl PageSize for Size2MiB {
+    const SIZE: u64 = Size4KiB::SIZE * 512;
+    const LEVEL: u16 = 2;
+    const NAME: &'static str = "2MiB";
+}
+
+impl PageSize for Size1GiB {
+    const SIZE: u64 = Size2MiB::SIZE * 512;
+    const LEVEL: u16 = 3;
+    const NAME: &'static str = "1GiB";
+}
+
+#[test_case]
+fn test_page_sizes() {
+    assert_eq!(Size4KiB::SIZE, 0x1000);
+    assert_eq!(Size2MiB::SIZE, 0x20_0000);
+    assert_eq!(Size1GiB::SIZE, 0x4000_0000);
+}
@@ -4,6 +4,7 @@ use core::ops::{Index, IndexMut};
 
 use crate::memory::frame::Frame;
 use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page_size::PageSize;
 
 pub const PAGE_TABLE_INDEX_LENGTH: u16 = 9; // Each page table index is 9 bits long
 pub const PAGE_TABLE_OFFSET_LENGTH: u16 = 12; // The page table offset is 12 bits long
@@ -59,6 +60,10 @@ bitflags! {
         const GLOBAL = 1 << 8;
 
         // Bits 9 - 11 can be freely used by the OS
+
+        // Marks a read-only mapping of a shared frame that gets copied on the first write
+        const COPY_ON_WRITE = 1 << 9;
+
         // Bits 12 - 51 are used to represent the frame physical address
         // Bits 52 - 62 can be freely used by the OS
         // This is possible because we always point to a 4096-byte aligned address.
@@ -102,6 +107,18 @@ impl PageTableEntry {
         PageTableFlags::from_bits_retain(self.entry & !PTE_PADDR_MASK)
     }
 
+    // Points the entry to the given frame with the given flags.
+    #[inline]
+    pub fn set_frame<S: PageSize>(&mut self, frame: Frame<S>, flags: PageTableFlags) {
+        self.entry = frame.start_address().address() | flags.bits();
+    }
+
+    // Replaces the flags of the entry while keeping the frame it points to.
+    #[inline]
+    pub fn set_flags(&mut self, flags: PageTableFlags) {
+        self.entry = self.paddr().address() | flags.bits();
+    }
+
     #[inline]
     pub fn frame(&self) -> Option<Frame> {
         if !self.flags().contains(PageTableFlags::PRESENT) {
@@ -134,6 +151,14 @@ impl PageTable {
     pub fn iter(&self) -> impl Iterator<Item = &PageTableEntry> {
         (0..PTE_COUNT).map(move |i| &self.0[i])
     }
+
+    // Marks every entry of the page table as unused.
+    #[inline]
+    pub fn zero(&mut self) {
+        for pte in self.0.iter_mut() {
+            pte.set_unused();
+        }
+    }
 }
 
 impl Index<usize> for PageTable {
@@ -167,3 +192,37 @@ fn test_page_table_iterator() {
 
     assert_eq!(test_pti, PTE_COUNT as usize);
 }
+
+#[test_case]
+fn test_page_table_entry_set_frame_and_flags() {
+    let frame: Frame = Frame::new(PhysicalAddress::new(0x5000));
+    let mut pte = PageTableEntry::new();
+
+    pte.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+    assert_eq!(pte.frame(), Some(frame));
+    assert_eq!(
+        pte.flags(),
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+    );
+
+    pte.set_flags(PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE);
+    assert_eq!(pte.frame(), Some(frame));
+    assert_eq!(
+        pte.flags(),
+        PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE
+    );
+
+    pte.set_flags(PageTableFlags::empty());
+    assert_eq!(pte.frame(), None);
+}
+
+#[test_case]
+fn test_page_table_zero() {
+    let mut page_table = PageTable::new();
+    let frame: Frame = Frame::new(PhysicalAddress::new(0x5000));
+    page_table[42].set_frame(frame, PageTableFlags::PRESENT);
+    assert!(!page_table[42].is_unused());
+
+    page_table.zero();
+    assert!(page_table.iter().all(|pte| pte.is_unused()));
+}
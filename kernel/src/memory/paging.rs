@@ -1,13 +1,68 @@
+use core::arch::asm;
 use core::ops::RangeInclusive;
+use core::sync::atomic::{AtomicU64, Ordering};
 
+use crate::memory::frame_allocator::FrameAllocator;
+use crate::memory::frame_descriptor::FrameOwner;
+use crate::memory::page_size::Size1GiB;
 use crate::memory::page_table::{PAGE_TABLE_INDEX_LENGTH, PAGE_TABLE_OFFSET_LENGTH};
 use crate::memory::{
-    frame::Frame, paddr::PhysicalAddress, page_table::PageTable, page_table::PageTableEntry,
-    page_table::PageTableFlags, vaddr::VirtualAddress,
+    frame::Frame, paddr::PhysicalAddress, page::Page, page_size::PageSize, page_table::PageTable,
+    page_table::PageTableEntry, page_table::PageTableFlags, vaddr::VirtualAddress,
 };
 use crate::registers::control::CR3;
+use crate::registers::cpuid::{self, CpuFeatures};
 const PAGE_TABLE_LEVELS: RangeInclusive<u16> = 1..=4;
 
+// Invalidates the TLB entry of the page containing the given virtual address. The CPU caches
+// translations in the TLB, so every change to an existing mapping needs to be followed by a flush.
+#[inline]
+pub unsafe fn invlpg(vaddr: VirtualAddress) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) vaddr.address(), options(nostack, preserves_flags));
+    }
+}
+
+// Counts the changes to existing mappings. With PCIDs enabled, invlpg only flushes the TLB entries
+// of the active PCID, while other address spaces may still have the old mapping cached under
+// theirs. Address spaces compare the count on activation to find out whether to flush, see
+// AddressSpace::activate.
+static TLB_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+pub fn tlb_generation() -> u64 {
+    TLB_GENERATION.load(Ordering::Acquire)
+}
+
+// Flushes the page from the TLB after an existing mapping changed, and marks the TLB entries cached
+// under other PCIDs as possibly stale.
+#[inline]
+unsafe fn invalidate(vaddr: VirtualAddress) {
+    TLB_GENERATION.fetch_add(1, Ordering::AcqRel);
+    unsafe { invlpg(vaddr) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MappingError {
+    // A new page table was needed, but the frame allocator ran out of frames.
+    FrameAllocationFailed,
+
+    // The page is already mapped to the contained frame.
+    PageAlreadyMapped(Frame),
+
+    // The page is not mapped.
+    PageNotMapped,
+
+    // A higher level entry on the way to the page maps a huge page.
+    ParentEntryHugePage,
+
+    // The entry for a huge page points to a page table of smaller pages instead.
+    PageSizeMismatch,
+
+    // The CPU does not support pages of this size.
+    PageSizeNotSupported,
+}
+
 #[derive(Copy, Debug, Eq, PartialEq, Hash, Clone)]
 pub struct Paging {
     paddr_offset: u64,
@@ -24,6 +79,27 @@ impl Paging {
         }
     }
 
+    // Creates a Paging instance for the level 4 page table in the given frame, which does not need
+    // to be the active one.
+    #[inline]
+    pub fn new(paddr_offset: u64, level_4_page_table_frame: Frame) -> Paging {
+        Paging {
+            paddr_offset,
+            level_4_page_table_frame,
+        }
+    }
+
+    #[inline]
+    pub fn level_4_page_table_frame(&self) -> Frame {
+        self.level_4_page_table_frame
+    }
+
+    // The offset at which the complete physical memory is mapped.
+    #[inline]
+    pub fn paddr_offset(&self) -> u64 {
+        self.paddr_offset
+    }
+
     #[inline]
     pub fn translate(&self, vaddr: VirtualAddress) -> Option<PhysicalAddress> {
         let mut page_table_frame: Frame = self.level_4_page_table_frame;
@@ -59,6 +135,186 @@ impl Paging {
             page_table_frame.start_address().address() + vaddr.page_table_offset() as u64,
         ))
     }
+
+    // Maps the page to the frame with the given flags. The flags need to contain PRESENT for the
+    // mapping to be usable. Missing intermediate page tables are allocated from the frame
+    // allocator.
+    //
+    // Huge pages (2MB and 1GB) are mapped by a level 2 or level 3 entry with the HUGE_PAGE flag
+    // set, which is added automatically. 1GB pages fail with PageSizeNotSupported if the CPU does
+    // not support them.
+    #[inline]
+    pub fn map_to<S: PageSize, A: FrameAllocator + ?Sized>(
+        &mut self,
+        page: Page<S>,
+        frame: Frame<S>,
+        flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<(), MappingError> {
+        let vaddr = page.start_address();
+        if S::LEVEL == Size1GiB::LEVEL && !cpuid::has(CpuFeatures::HUGE_PAGE_1G) {
+            return Err(MappingError::PageSizeNotSupported);
+        }
+
+        // Intermediate entries need to be at least as permissive as the final mapping, as the CPU
+        // combines the flags of all levels.
+        let mut parent_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        if flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+            parent_flags |= PageTableFlags::USER_ACCESSIBLE;
+        }
+
+        let mut page_table_frame: Frame = self.level_4_page_table_frame;
+        for level in ((S::LEVEL + 1)..=4).rev() {
+            let page_table: &mut PageTable = unsafe {
+                &mut *get_page_table_ptr(self.paddr_offset, page_table_frame.start_address())
+            };
+            let pte: &mut PageTableEntry = &mut page_table[vaddr.page_table_index(level) as usize];
+
+            page_table_frame = self.create_next_page_table(pte, parent_flags, frame_allocator)?;
+        }
+
+        let page_table: &mut PageTable = unsafe {
+            &mut *get_page_table_ptr(self.paddr_offset, page_table_frame.start_address())
+        };
+        let pte: &mut PageTableEntry = &mut page_table[vaddr.page_table_index(S::LEVEL) as usize];
+        if let Some(mapped_frame) = pte.frame() {
+            if is_huge::<S>() && !pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+                return Err(MappingError::PageSizeMismatch);
+            }
+
+            return Err(MappingError::PageAlreadyMapped(mapped_frame));
+        }
+
+        pte.set_frame(frame, with_huge_flag::<S>(flags));
+        unsafe { invlpg(vaddr) };
+
+        Ok(())
+    }
+
+    // Removes the mapping of the page and returns the frame it was mapped to. Page tables that
+    // become empty are not freed.
+    #[inline]
+    pub fn unmap<S: PageSize>(&mut self, page: Page<S>) -> Result<Frame<S>, MappingError> {
+        let pte: &mut PageTableEntry = unsafe { &mut *self.leaf_entry(page)? };
+        let frame: Frame<S> = Frame::new(pte.paddr());
+
+        pte.set_unused();
+        unsafe { invalidate(page.start_address()) };
+
+        Ok(frame)
+    }
+
+    // Replaces the flags of an existing mapping.
+    #[inline]
+    pub fn update_flags<S: PageSize>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableFlags,
+    ) -> Result<(), MappingError> {
+        let pte: &mut PageTableEntry = unsafe { &mut *self.leaf_entry(page)? };
+
+        pte.set_flags(with_huge_flag::<S>(flags));
+        unsafe { invalidate(page.start_address()) };
+
+        Ok(())
+    }
+
+    // Returns the flags the page is currently mapped with. For huge pages, the HUGE_PAGE flag is
+    // not part of the returned flags.
+    #[inline]
+    pub fn page_flags<S: PageSize>(&self, page: Page<S>) -> Option<PageTableFlags> {
+        let pte: &PageTableEntry = unsafe { &*self.leaf_entry(page).ok()? };
+
+        if is_huge::<S>() {
+            return Some(pte.flags() - PageTableFlags::HUGE_PAGE);
+        }
+
+        Some(pte.flags())
+    }
+
+    // Returns the frame of the page table the entry points to. If the entry is unused, a new page
+    // table is allocated and the entry is pointed to it.
+    #[inline]
+    fn create_next_page_table<A: FrameAllocator + ?Sized>(
+        &self,
+        pte: &mut PageTableEntry,
+        parent_flags: PageTableFlags,
+        frame_allocator: &mut A,
+    ) -> Result<Frame, MappingError> {
+        if pte.is_unused() {
+            let frame = frame_allocator
+                .allocate_frame_for(FrameOwner::PageTable)
+                .ok_or(MappingError::FrameAllocationFailed)?;
+
+            let page_table: &mut PageTable =
+                unsafe { &mut *get_page_table_ptr(self.paddr_offset, frame.start_address()) };
+            page_table.zero();
+
+            pte.set_frame(frame, parent_flags);
+            return Ok(frame);
+        }
+
+        if pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(MappingError::ParentEntryHugePage);
+        }
+
+        if !pte.flags().contains(parent_flags) {
+            pte.set_flags(pte.flags() | parent_flags);
+        }
+
+        Ok(Frame::new(pte.paddr()))
+    }
+
+    // Walks the page tables down to the entry mapping the page without allocating anything. The
+    // entry is guaranteed to be present and to map a page of the requested size.
+    #[inline]
+    fn leaf_entry<S: PageSize>(&self, page: Page<S>) -> Result<*mut PageTableEntry, MappingError> {
+        let vaddr = page.start_address();
+        let mut page_table_frame: Frame = self.level_4_page_table_frame;
+
+        for level in ((S::LEVEL + 1)..=4).rev() {
+            let page_table: &PageTable = unsafe {
+                &*get_page_table_ptr(self.paddr_offset, page_table_frame.start_address())
+            };
+            let pte: &PageTableEntry = &page_table[vaddr.page_table_index(level) as usize];
+
+            page_table_frame = pte.frame().ok_or(MappingError::PageNotMapped)?;
+            if pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+                return Err(MappingError::ParentEntryHugePage);
+            }
+        }
+
+        let page_table: &mut PageTable = unsafe {
+            &mut *get_page_table_ptr(self.paddr_offset, page_table_frame.start_address())
+        };
+        let pte: &mut PageTableEntry = &mut page_table[vaddr.page_table_index(S::LEVEL) as usize];
+
+        if pte.frame().is_none() {
+            return Err(MappingError::PageNotMapped);
+        }
+
+        if is_huge::<S>() && !pte.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Err(MappingError::PageSizeMismatch);
+        }
+
+        Ok(pte as *mut PageTableEntry)
+    }
+}
+
+// Level 1 entries always map 4KB pages. On level 1, bit 7 selects the page attribute table instead
+// of marking a huge page.
+#[inline]
+fn is_huge<S: PageSize>() -> bool {
+    S::LEVEL > 1
+}
+
+#[inline]
+fn with_huge_flag<S: PageSize>(flags: PageTableFlags) -> PageTableFlags {
+    if is_huge::<S>() {
+        return flags | PageTableFlags::HUGE_PAGE;
+    }
+
+    flags
 }
 
 // The kernel runs on paging. This means all the addresses obtained from registers like CR3 are
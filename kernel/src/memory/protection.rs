@@ -0,0 +1,211 @@
+use crate::memory::page::{Page, PageRange, PAGE_SIZE};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+use crate::registers::control::{CR0Flags, CR3Flags, CR4Flags, CR0, CR3, CR4};
+use crate::registers::cpuid::{self, CpuFeatures};
+use crate::registers::model_specific::{Efer, EferFlags};
+
+#[cfg(test)]
+use crate::memory::paging;
+
+// Program header types and flags of the ELF specification.
+const PT_LOAD: u32 = 1;
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+const PF_X: u32 = 1;
+const PF_W: u32 = 1 << 1;
+
+// The linker places the ELF header at the start of the first loadable segment and defines this
+// symbol at its address. As the kernel is loaded by the bootloader, the header is mapped as well.
+extern "C" {
+    static __ehdr_start: u8;
+}
+
+// The parts of an ELF64 program header needed to protect the kernel image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+// Enables the CPU features that enforce page protections in the kernel itself:
+// - CR0.WP makes read-only pages read-only for the kernel, not only for user mode.
+// - EFER.NXE makes the NO_EXECUTE flag usable.
+// - CR4.SMEP and CR4.SMAP stop the kernel from executing or accessing user accessible pages.
+//
+// CR4.PCIDE is set as well, so that address spaces keep their TLB entries while they are not active,
+// see enable_pcids. Features the CPU does not support are skipped.
+pub fn enable_protection_features() {
+    unsafe { CR0::update(|flags| flags.insert(CR0Flags::WRITE_PROTECT)) };
+
+    if cpuid::has(CpuFeatures::NO_EXECUTE) {
+        unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+    }
+
+    let mut cr4_flags = CR4Flags::empty();
+    if cpuid::has(CpuFeatures::SMEP) {
+        cr4_flags |= CR4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if cpuid::has(CpuFeatures::SMAP) {
+        cr4_flags |= CR4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    unsafe { CR4::update(|flags| flags.insert(cr4_flags)) };
+    enable_pcids();
+
+    log::info!(
+        "Protection features: WP {}, NXE {}, SMEP {}, SMAP {}, PCID {}",
+        CR0::read().contains(CR0Flags::WRITE_PROTECT),
+        Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE),
+        CR4::read().contains(CR4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+        CR4::read().contains(CR4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        CR4::read().contains(CR4Flags::PCID)
+    );
+}
+
+// Enables PCIDs on the calling CPU if it supports them. Application processors call this once they
+// are in long mode, as CR4.PCIDE can't be set before.
+pub fn enable_pcids() {
+    // Setting CR4.PCIDE faults while the lower bits of CR3 are set, as they become the PCID.
+    if !cpuid::has(CpuFeatures::PCID) || CR3::read().1 != CR3Flags::empty() {
+        return;
+    }
+
+    unsafe { CR4::update(|flags| flags.insert(CR4Flags::PCID)) };
+}
+
+// Remaps the segments of the kernel image with the permissions from its program headers: code is
+// read-only and executable, read-only data is read-only and not executable, and data and bss are
+// writable and not executable. Data that is only written while relocating the kernel becomes
+// read-only as well.
+//
+// The linker puts segments with different permissions on different pages, so the permissions of
+// every page are well defined.
+pub fn protect_kernel_image(paging: &mut Paging) -> Result<(), MappingError> {
+    let program_headers = kernel_program_headers();
+
+    // The kernel may be loaded at any address. The ELF header is part of the segment with file
+    // offset 0, which gives the distance between link and load addresses.
+    let ehdr_address = (&raw const __ehdr_start) as u64;
+    let load_bias = program_headers
+        .iter()
+        .find(|header| header.p_type == PT_LOAD && header.p_offset == 0)
+        .map_or(0, |header| ehdr_address.wrapping_sub(header.p_vaddr));
+
+    for header in program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+    {
+        let start = load_bias.wrapping_add(header.p_vaddr);
+        let pages = page_range(start & !(PAGE_SIZE - 1), start + header.p_memsz);
+
+        let (set, clear) = if header.p_flags & PF_X != 0 {
+            (
+                PageTableFlags::empty(),
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            )
+        } else if header.p_flags & PF_W != 0 {
+            (
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+                PageTableFlags::empty(),
+            )
+        } else {
+            (PageTableFlags::NO_EXECUTE, PageTableFlags::WRITABLE)
+        };
+
+        update_page_flags(paging, pages, set, clear)?;
+    }
+
+    // Only pages that are completely part of the relocation read-only area can be protected.
+    for header in program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_GNU_RELRO)
+    {
+        let start = load_bias.wrapping_add(header.p_vaddr);
+        let pages = page_range(
+            (start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1),
+            (start + header.p_memsz) & !(PAGE_SIZE - 1),
+        );
+
+        update_page_flags(
+            paging,
+            pages,
+            PageTableFlags::empty(),
+            PageTableFlags::WRITABLE,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Returns the program headers of the kernel ELF image.
+fn kernel_program_headers() -> &'static [ProgramHeader] {
+    let ehdr = (&raw const __ehdr_start) as *const u8;
+
+    // e_phoff is at offset 0x20 and e_phnum at offset 0x38 of the ELF64 header.
+    unsafe {
+        let phoff = (ehdr.add(0x20) as *const u64).read_unaligned();
+        let phnum = (ehdr.add(0x38) as *const u16).read_unaligned();
+        core::slice::from_raw_parts(
+            ehdr.add(phoff as usize) as *const ProgramHeader,
+            phnum as usize,
+        )
+    }
+}
+
+// Returns the pages in [start, end), which may be empty.
+#[inline]
+fn page_range(start: u64, end: u64) -> Option<PageRange> {
+    if start >= end {
+        return None;
+    }
+
+    let first_page: Page = Page::new(VirtualAddress::new(start));
+    let num_pages = (end - first_page.start_address().address()).div_ceil(PAGE_SIZE);
+    Some(PageRange::from_pages(first_page, num_pages))
+}
+
+#[inline]
+fn update_page_flags(
+    paging: &mut Paging,
+    pages: Option<PageRange>,
+    set: PageTableFlags,
+    clear: PageTableFlags,
+) -> Result<(), MappingError> {
+    for page in pages.iter().flat_map(|pages| pages.iter()) {
+        let flags = paging.page_flags(page).ok_or(MappingError::PageNotMapped)?;
+        paging.update_flags(page, (flags - clear) | set)?;
+    }
+
+    Ok(())
+}
+
+#[test_case]
+fn test_kernel_image_is_protected() {
+    let code: Page = Page::new(VirtualAddress::new(
+        protect_kernel_image as *const () as u64,
+    ));
+    let code_flags = paging().page_flags(code).unwrap();
+    assert!(!code_flags.contains(PageTableFlags::WRITABLE));
+    assert!(!code_flags.contains(PageTableFlags::NO_EXECUTE));
+
+    static READ_ONLY: u64 = 42;
+    let read_only: Page = Page::new(VirtualAddress::from_ptr(&READ_ONLY));
+    let read_only_flags = paging().page_flags(read_only).unwrap();
+    assert!(!read_only_flags.contains(PageTableFlags::WRITABLE));
+    assert!(read_only_flags.contains(PageTableFlags::NO_EXECUTE));
+
+    static mut WRITABLE: u64 = 0;
+    let writable: Page = Page::new(VirtualAddress::from_ptr(&raw const WRITABLE));
+    let writable_flags = paging().page_flags(writable).unwrap();
+    assert!(writable_flags.contains(PageTableFlags::WRITABLE));
+    assert!(writable_flags.contains(PageTableFlags::NO_EXECUTE));
+
+    assert!(CR0::read().contains(CR0Flags::WRITE_PROTECT));
+}
@@ -0,0 +1,195 @@
+use crate::memory::frame_allocator::{FrameAllocator, MemoryMapFrameAllocator};
+use crate::memory::page::{Page, PageRange, PAGE_SIZE};
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::{MappingError, Paging};
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::vma::{
+    AreaKind, Backing, VirtualMemoryArea, VmaError, VmaTable, DYNAMIC_AREA_WINDOW, KERNEL_AREAS,
+};
+use crate::memory::{frame_allocator, paging};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackError {
+    // No space for the stack could be found in the kernel address space.
+    Area(VmaError),
+
+    // The stack pages could not be mapped.
+    Mapping(MappingError),
+}
+
+// A kernel stack with an unmapped guard page below it.
+//
+// Stacks grow downwards, so a stack overflow runs into the guard page and causes a page fault,
+// instead of silently overwriting whatever memory lies below the stack.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct KernelStack {
+    guard_page: Page,
+    num_pages: u64,
+}
+
+impl KernelStack {
+    // Allocates a stack of num_pages mapped pages, placed in a free gap of the kernel address space
+    // with a guard page below it.
+    pub fn allocate(num_pages: u64) -> Result<KernelStack, StackError> {
+        let mut areas = KERNEL_AREAS.lock();
+
+        let start = areas
+            .find_free_gap(
+                (num_pages + 1) * PAGE_SIZE,
+                PAGE_SIZE,
+                VirtualAddress::new(DYNAMIC_AREA_WINDOW.start)
+                    ..VirtualAddress::new(DYNAMIC_AREA_WINDOW.end),
+            )
+            .map_err(StackError::Area)?;
+
+        let stack = KernelStack {
+            guard_page: Page::new(start),
+            num_pages,
+        };
+
+        let mut paging = paging();
+        let mut frame_allocator = frame_allocator();
+        for (i, page) in stack.pages().iter().enumerate() {
+            let mapped = match frame_allocator.allocate_frame() {
+                Some(frame) => paging
+                    .map_to(page, frame, Self::flags(), &mut *frame_allocator)
+                    .map_err(|error| (error, Some(frame))),
+                None => Err((MappingError::FrameAllocationFailed, None)),
+            };
+
+            // Undo the mappings that were already created.
+            if let Err((error, frame)) = mapped {
+                if let Some(frame) = frame {
+                    frame_allocator.deallocate_frame(frame);
+                }
+                unmap_pages(
+                    &mut paging,
+                    &mut frame_allocator,
+                    stack.pages().iter().take(i),
+                );
+                return Err(StackError::Mapping(error));
+            }
+        }
+
+        if let Err(error) = stack.insert_areas(&mut areas) {
+            unmap_pages(&mut paging, &mut frame_allocator, stack.pages().iter());
+            return Err(StackError::Area(error));
+        }
+
+        Ok(stack)
+    }
+
+    // Unmaps the stack and returns its frames to the frame allocator.
+    //
+    // ## Safety
+    // The stack must not be in use anymore, e.g. by a TSS entry or a suspended task.
+    pub unsafe fn free(self) {
+        let mut areas = KERNEL_AREAS.lock();
+        let mut paging = paging();
+        let mut frame_allocator = frame_allocator();
+
+        unmap_pages(&mut paging, &mut frame_allocator, self.pages().iter());
+
+        areas.remove(self.guard_page.start_address()).unwrap();
+        areas.remove(self.bottom()).unwrap();
+    }
+
+    // The initial stack pointer. It is 16 byte aligned, as the x86_64 ABI requires.
+    #[inline]
+    pub fn top(&self) -> VirtualAddress {
+        self.bottom() + self.num_pages * PAGE_SIZE
+    }
+
+    // The lowest address of the stack.
+    #[inline]
+    pub fn bottom(&self) -> VirtualAddress {
+        self.guard_page.start_address() + PAGE_SIZE
+    }
+
+    #[inline]
+    pub fn guard_page(&self) -> Page {
+        self.guard_page
+    }
+
+    // The mapped pages of the stack, without the guard page.
+    #[inline]
+    pub fn pages(&self) -> PageRange {
+        PageRange::from_pages(
+            Page::new(self.bottom()),
+            (self.top().address() - self.bottom().address()) / PAGE_SIZE,
+        )
+    }
+
+    #[inline]
+    fn flags() -> PageTableFlags {
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+    }
+
+    // Records the guard page and the stack in the areas. Either both are recorded or neither is.
+    fn insert_areas(&self, areas: &mut VmaTable) -> Result<(), VmaError> {
+        areas.insert(VirtualMemoryArea::new(
+            self.guard_page.start_address(),
+            PAGE_SIZE,
+            AreaKind::GuardPage,
+            PageTableFlags::empty(),
+            Backing::Unmapped,
+        ))?;
+
+        let inserted = areas.insert(VirtualMemoryArea::new(
+            self.bottom(),
+            self.num_pages * PAGE_SIZE,
+            AreaKind::Stack,
+            Self::flags(),
+            Backing::Mapped,
+        ));
+        if inserted.is_err() {
+            areas.remove(self.guard_page.start_address()).unwrap();
+        }
+
+        inserted
+    }
+}
+
+// Unmaps the stack pages and returns their frames to the frame allocator.
+fn unmap_pages(
+    paging: &mut Paging,
+    frame_allocator: &mut MemoryMapFrameAllocator,
+    pages: impl Iterator<Item = Page>,
+) {
+    for page in pages {
+        let frame = paging.unmap(page).expect("Kernel stack page is not mapped");
+        frame_allocator.deallocate_frame(frame);
+    }
+}
+
+#[test_case]
+fn test_kernel_stack_has_guard_page() {
+    let stack = KernelStack::allocate(4).unwrap();
+    assert_eq!(
+        stack.top() - stack.bottom(),
+        VirtualAddress::new(4 * PAGE_SIZE)
+    );
+    assert_eq!(stack.top().address() % 16, 0);
+
+    // The stack pages are mapped, the guard page below them is not.
+    assert!(paging().translate(stack.bottom()).is_some());
+    assert!(paging().translate(stack.top() - 1u64).is_some());
+    assert!(paging()
+        .translate(stack.guard_page().start_address())
+        .is_none());
+
+    let areas = KERNEL_AREAS.lock();
+    let guard = areas.find(stack.guard_page().start_address()).unwrap();
+    assert_eq!(guard.kind(), AreaKind::GuardPage);
+    assert_eq!(areas.find(stack.bottom()).unwrap().kind(), AreaKind::Stack);
+    drop(areas);
+
+    // Writing to the whole stack works.
+    let bottom = stack.bottom().address() as *mut u8;
+    unsafe { bottom.write_bytes(0x42, (4 * PAGE_SIZE) as usize) };
+
+    let bottom = stack.bottom();
+    unsafe { stack.free() };
+    assert!(paging().translate(bottom).is_none());
+    assert!(KERNEL_AREAS.lock().find(bottom).is_none());
+}
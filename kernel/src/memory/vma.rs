@@ -0,0 +1,394 @@
+use alloc::collections::BTreeMap;
+use core::fmt;
+use core::ops::Range;
+use spin::Mutex;
+
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::page::PAGE_SIZE;
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::vaddr::VirtualAddress;
+
+// The areas of the kernel address space. The page fault handler consults this table to decide
+// whether a fault can be resolved, and new areas are placed in the gaps between the known ones.
+pub static KERNEL_AREAS: Mutex<VmaTable> = Mutex::new(VmaTable::new());
+
+// The part of the address space areas without a fixed address are placed in. The bootloader puts
+// its mappings (kernel, physical memory, framebuffer, boot info) in the lowest unused level 4
+// entries of the upper half, so this window stays free.
+pub const DYNAMIC_AREA_WINDOW: Range<u64> = 0x_ffff_e000_0000_0000..0x_ffff_f000_0000_0000;
+
+// What an area is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AreaKind {
+    Heap,
+    Stack,
+    Mmio,
+
+    // The mapping of the complete physical memory at the physical memory offset.
+    PhysicalMemory,
+
+    FrameBuffer,
+
+    // The descriptor of every physical frame, see frame_descriptor.
+    FrameDescriptors,
+
+    // A page that is kept unmapped so that running off the end of the area next to it faults.
+    GuardPage,
+
+    // Memory that is not tied to a specific subsystem.
+    Anonymous,
+}
+
+// How the pages of an area get their physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backing {
+    // All pages are mapped to frames from the frame allocator when the area is registered.
+    Mapped,
+
+    // Pages are mapped to a fresh zeroed frame by the page fault handler on first access.
+    Lazy,
+
+    // The area maps a fixed, contiguous range of physical memory starting at the contained
+    // address, e.g. device registers or the framebuffer.
+    Physical(PhysicalAddress),
+
+    // The pages are never mapped, every access faults.
+    Unmapped,
+}
+
+// A page aligned range of virtual memory whose pages share the same flags and backing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VirtualMemoryArea {
+    start: VirtualAddress,
+    size: u64,
+    kind: AreaKind,
+    flags: PageTableFlags,
+    backing: Backing,
+}
+
+impl VirtualMemoryArea {
+    #[inline]
+    pub fn new(
+        start: VirtualAddress,
+        size: u64,
+        kind: AreaKind,
+        flags: PageTableFlags,
+        backing: Backing,
+    ) -> VirtualMemoryArea {
+        VirtualMemoryArea {
+            start,
+            size,
+            kind,
+            flags,
+            backing,
+        }
+    }
+
+    #[inline]
+    pub fn start(&self) -> VirtualAddress {
+        self.start
+    }
+
+    // The first address after the area.
+    #[inline]
+    pub fn end(&self) -> VirtualAddress {
+        self.start + self.size
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn kind(&self) -> AreaKind {
+        self.kind
+    }
+
+    // The flags used to map the pages of the area.
+    #[inline]
+    pub fn flags(&self) -> PageTableFlags {
+        self.flags
+    }
+
+    #[inline]
+    pub fn backing(&self) -> Backing {
+        self.backing
+    }
+
+    #[inline]
+    pub fn contains(&self, vaddr: VirtualAddress) -> bool {
+        self.start <= vaddr && vaddr < self.end()
+    }
+
+    #[inline]
+    fn overlaps(&self, other: &VirtualMemoryArea) -> bool {
+        self.start < other.end() && other.start < self.end()
+    }
+}
+
+// Formats an area as a single line of the address space layout, e.g.
+// 0x444444440000-0x444444540000 rw- 1024 KiB Heap (Mapped)
+impl fmt::Display for VirtualMemoryArea {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permission = |flag: PageTableFlags, set: bool, c: char| {
+            if self.flags.contains(flag) == set {
+                c
+            } else {
+                '-'
+            }
+        };
+
+        write!(
+            f,
+            "{:#014x}-{:#014x} {}{}{}{} {:>8} KiB {:?} ({:?})",
+            self.start.address(),
+            self.end().address(),
+            permission(PageTableFlags::PRESENT, true, 'r'),
+            permission(PageTableFlags::WRITABLE, true, 'w'),
+            permission(PageTableFlags::NO_EXECUTE, false, 'x'),
+            permission(PageTableFlags::USER_ACCESSIBLE, true, 'u'),
+            self.size / 1024,
+            self.kind,
+            self.backing
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VmaError {
+    // The start address or the size of the area is not page aligned, or the area is empty.
+    InvalidArea,
+
+    // The area overlaps with the contained area.
+    Overlap(VirtualMemoryArea),
+
+    // No area starts at the given address.
+    AreaNotFound,
+
+    // There is no free gap of the requested size and alignment.
+    NoFreeGap,
+}
+
+// A set of non overlapping areas, sorted by their start address.
+pub struct VmaTable {
+    areas: BTreeMap<VirtualAddress, VirtualMemoryArea>,
+}
+
+impl VmaTable {
+    #[inline]
+    pub const fn new() -> VmaTable {
+        VmaTable {
+            areas: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, area: VirtualMemoryArea) -> Result<(), VmaError> {
+        if area.size == 0 || area.start.address() % PAGE_SIZE != 0 || area.size % PAGE_SIZE != 0 {
+            return Err(VmaError::InvalidArea);
+        }
+
+        // Areas don't overlap, so only the closest areas below and above can overlap the new one.
+        let below = self.areas.range(..area.start).next_back();
+        let above = self.areas.range(area.start..).next();
+        for (_, other) in below.into_iter().chain(above) {
+            if area.overlaps(other) {
+                return Err(VmaError::Overlap(*other));
+            }
+        }
+
+        self.areas.insert(area.start, area);
+        Ok(())
+    }
+
+    // Removes the area starting at the given address. The pages of the area are not unmapped.
+    #[inline]
+    pub fn remove(&mut self, start: VirtualAddress) -> Result<VirtualMemoryArea, VmaError> {
+        self.areas.remove(&start).ok_or(VmaError::AreaNotFound)
+    }
+
+    // Returns the area containing the given address.
+    #[inline]
+    pub fn find(&self, vaddr: VirtualAddress) -> Option<&VirtualMemoryArea> {
+        self.areas
+            .range(..=vaddr)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.contains(vaddr))
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &VirtualMemoryArea> {
+        self.areas.values()
+    }
+
+    // Returns the lowest address in the window where size bytes aligned to align are not used by
+    // any area. The size needs to be a multiple of the page size, and the alignment a power of 2
+    // that is at least the page size.
+    pub fn find_free_gap(
+        &self,
+        size: u64,
+        align: u64,
+        window: Range<VirtualAddress>,
+    ) -> Result<VirtualAddress, VmaError> {
+        if size == 0 || size % PAGE_SIZE != 0 || !align.is_power_of_two() || align < PAGE_SIZE {
+            return Err(VmaError::InvalidArea);
+        }
+
+        let mut candidate = align_up(window.start.address(), align);
+
+        // Areas are sorted, so we can move the candidate past every area it collides with.
+        for area in self.areas.values() {
+            if area.end().address() <= candidate {
+                continue;
+            }
+
+            if area.start.address() >= candidate + size {
+                break;
+            }
+
+            candidate = align_up(area.end().address(), align);
+        }
+
+        if candidate + size > window.end.address() {
+            return Err(VmaError::NoFreeGap);
+        }
+
+        Ok(VirtualAddress::new(candidate))
+    }
+
+    // Logs the layout of the address space, one area per line.
+    pub fn dump(&self) {
+        log::info!("Address space layout ({} areas):", self.areas.len());
+        for area in self.areas.values() {
+            log::info!("  {}", area);
+        }
+    }
+}
+
+#[inline]
+fn align_up(address: u64, align: u64) -> u64 {
+    (address + align - 1) & !(align - 1)
+}
+
+#[cfg(test)]
+fn test_area(start: u64, num_pages: u64, backing: Backing) -> VirtualMemoryArea {
+    VirtualMemoryArea::new(
+        VirtualAddress::new(start),
+        num_pages * PAGE_SIZE,
+        AreaKind::Anonymous,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        backing,
+    )
+}
+
+#[test_case]
+fn test_vma_table_finds_areas() {
+    let mut table = VmaTable::new();
+    let first = test_area(0x10_0000, 4, Backing::Lazy);
+    let second = test_area(0x20_0000, 1, Backing::Mapped);
+    table.insert(second).unwrap();
+    table.insert(first).unwrap();
+
+    assert_eq!(table.find(VirtualAddress::new(0x10_0000)), Some(&first));
+    assert_eq!(table.find(VirtualAddress::new(0x10_3fff)), Some(&first));
+    assert_eq!(table.find(VirtualAddress::new(0x10_4000)), None);
+    assert_eq!(table.find(VirtualAddress::new(0x20_0042)), Some(&second));
+    assert_eq!(table.find(VirtualAddress::new(0x0fff)), None);
+
+    assert_eq!(table.remove(first.start()), Ok(first));
+    assert_eq!(table.find(VirtualAddress::new(0x10_0000)), None);
+    assert_eq!(table.remove(first.start()), Err(VmaError::AreaNotFound));
+}
+
+#[test_case]
+fn test_vma_table_rejects_invalid_areas() {
+    let mut table = VmaTable::new();
+    let area = test_area(0x10_0000, 4, Backing::Lazy);
+    table.insert(area).unwrap();
+
+    assert_eq!(
+        table.insert(test_area(0x10_3000, 2, Backing::Lazy)),
+        Err(VmaError::Overlap(area))
+    );
+    assert_eq!(
+        table.insert(test_area(0x0f_f000, 2, Backing::Lazy)),
+        Err(VmaError::Overlap(area))
+    );
+    assert_eq!(
+        table.insert(test_area(0x20_0000, 0, Backing::Lazy)),
+        Err(VmaError::InvalidArea)
+    );
+    assert_eq!(
+        table.insert(test_area(0x20_0010, 1, Backing::Lazy)),
+        Err(VmaError::InvalidArea)
+    );
+
+    // Areas may touch each other.
+    table
+        .insert(test_area(0x10_4000, 1, Backing::Mapped))
+        .unwrap();
+    table
+        .insert(test_area(0x0f_f000, 1, Backing::Mapped))
+        .unwrap();
+    assert_eq!(table.iter().count(), 3);
+}
+
+#[test_case]
+fn test_vma_table_finds_free_gaps() {
+    let mut table = VmaTable::new();
+    table
+        .insert(test_area(0x10_0000, 1, Backing::Lazy))
+        .unwrap();
+    table
+        .insert(test_area(0x10_3000, 2, Backing::Lazy))
+        .unwrap();
+    table
+        .insert(test_area(0x10_8000, 1, Backing::Lazy))
+        .unwrap();
+
+    let window = VirtualAddress::new(0x10_0000)..VirtualAddress::new(0x12_0000);
+    let gap = |size, align| table.find_free_gap(size, align, window.clone());
+
+    // The first gap between the areas that is large enough is used.
+    assert_eq!(
+        gap(PAGE_SIZE, PAGE_SIZE),
+        Ok(VirtualAddress::new(0x10_1000))
+    );
+    assert_eq!(
+        gap(2 * PAGE_SIZE, PAGE_SIZE),
+        Ok(VirtualAddress::new(0x10_1000))
+    );
+    assert_eq!(
+        gap(3 * PAGE_SIZE, PAGE_SIZE),
+        Ok(VirtualAddress::new(0x10_5000))
+    );
+
+    // Gaps are aligned.
+    assert_eq!(
+        gap(PAGE_SIZE, 4 * PAGE_SIZE),
+        Ok(VirtualAddress::new(0x10_c000))
+    );
+    assert_eq!(gap(PAGE_SIZE, 0x8000), Ok(VirtualAddress::new(0x11_0000)));
+
+    assert_eq!(gap(0x10_0000, PAGE_SIZE), Err(VmaError::NoFreeGap));
+    assert_eq!(gap(PAGE_SIZE, 0x10), Err(VmaError::InvalidArea));
+    assert_eq!(gap(0x10, PAGE_SIZE), Err(VmaError::InvalidArea));
+}
+
+#[test_case]
+fn test_vma_display() {
+    use alloc::format;
+
+    let area = VirtualMemoryArea::new(
+        VirtualAddress::new(0x4444_4444_0000),
+        1024 * 1024,
+        AreaKind::Heap,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        Backing::Mapped,
+    );
+    assert_eq!(
+        format!("{}", area),
+        "0x444444440000-0x444444540000 rw--     1024 KiB Heap (Mapped)"
+    );
+}
@@ -0,0 +1,217 @@
+// Powering off and resetting the machine. ACPI is used when the firmware provides the tables, with
+// the legacy mechanisms of the PC as fallback. More info can be found at
+// https://wiki.osdev.org/Shutdown and https://wiki.osdev.org/Reboot.
+
+use core::hint::spin_loop;
+use x86_64::instructions::port::Port;
+
+use crate::acpi;
+use crate::acpi::fadt::{Fadt, FadtFlags};
+use crate::acpi::pm_timer;
+use crate::acpi::sdt::GenericAddress;
+use crate::acpi::AcpiError;
+use crate::hlt;
+use crate::interrupts::dtp::DescriptorTablePointer;
+use crate::interrupts::idt::lidt;
+use crate::interrupts::instructions::disable_interrupts;
+use crate::memory::vaddr::VirtualAddress;
+
+// The PM1 control register fields. SCI_EN tells that the machine is in ACPI mode. Writing SLP_TYP
+// together with SLP_EN enters the sleep state.
+const PM1_SCI_ENABLE: u64 = 1;
+const PM1_SLEEP_TYPE_SHIFT: u64 = 10;
+const PM1_SLEEP_TYPE_MASK: u64 = 0b111 << PM1_SLEEP_TYPE_SHIFT;
+const PM1_SLEEP_ENABLE: u64 = 1 << 13;
+
+// The sleep control register of hardware reduced machines has the same fields at other positions.
+const SLEEP_CONTROL_TYPE_SHIFT: u64 = 2;
+const SLEEP_CONTROL_TYPE_MASK: u64 = 0b111 << SLEEP_CONTROL_TYPE_SHIFT;
+const SLEEP_CONTROL_ENABLE: u64 = 1 << 5;
+
+// Writing the pulse command to the keyboard controller pulls the reset line of the CPU.
+const KEYBOARD_CONTROLLER_PORT: u16 = 0x64;
+const KEYBOARD_CONTROLLER_INPUT_FULL: u8 = 1 << 1;
+const KEYBOARD_CONTROLLER_PULSE_RESET: u8 = 0xFE;
+
+// How often the keyboard controller is polled before the command is sent anyway.
+const KEYBOARD_CONTROLLER_RETRIES: u64 = 100_000;
+
+// How long the hardware gets to act on a request before the next mechanism is tried.
+const POWER_OFF_DELAY_MS: u64 = 100;
+const ACPI_MODE_TIMEOUT_MS: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerError {
+    // The firmware did not provide the ACPI tables or registers needed.
+    NotSupported,
+
+    // The DSDT does not define the sleep types of the soft off state.
+    NoSoftOffState,
+
+    // The firmware did not switch to ACPI mode.
+    AcpiModeTimeout,
+
+    // A power management register could not be accessed.
+    Register(AcpiError),
+
+    // The machine is still running after entering the sleep state.
+    NoEffect,
+}
+
+// Powers the machine off by entering the ACPI soft off state S5. Halts if this fails.
+pub fn shutdown() -> ! {
+    log::info!("Shutting down");
+
+    let error = try_shutdown();
+    log::info!("ACPI shutdown failed: {:?}", error);
+    log::info!("The machine could not be powered off, halting");
+    hlt();
+}
+
+// Powers the machine off like shutdown, but returns why it failed instead of halting. Interrupts
+// stay disabled.
+pub fn try_shutdown() -> PowerError {
+    disable_interrupts();
+
+    match unsafe { enter_soft_off() } {
+        Ok(()) => PowerError::NoEffect,
+        Err(error) => error,
+    }
+}
+
+// Resets the machine. The ACPI reset register is tried first, then the keyboard controller, and a
+// triple fault as last resort.
+//
+// No locks are taken, so this can be called from the panic handler. The ACPI registers were mapped
+// when the tables were parsed.
+pub fn reboot() -> ! {
+    log::info!("Rebooting");
+    disable_interrupts();
+
+    if let Some(fadt) = acpi::fadt() {
+        if let Some(reset_register) = fadt.reset_register {
+            match unsafe { reset_register.write(fadt.reset_value as u64) } {
+                Ok(()) => delay(POWER_OFF_DELAY_MS),
+                Err(error) => log::info!("ACPI reset failed: {:?}", error),
+            }
+        }
+    }
+
+    unsafe { pulse_reset_line() };
+    delay(POWER_OFF_DELAY_MS);
+
+    // Without an IDT, the CPU can't deliver the exception, nor the resulting double fault, and
+    // resets.
+    log::info!("Rebooting by triple fault");
+    unsafe {
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtualAddress::zero(),
+        });
+        core::arch::asm!("int3", options(nomem, nostack));
+    }
+
+    hlt();
+}
+
+// Resets the machine after waiting for delay_ms milliseconds, e.g. to keep a panic message readable.
+pub fn reboot_after(delay_ms: u64) -> ! {
+    log::info!("Rebooting in {} ms", delay_ms);
+    delay(delay_ms);
+    reboot();
+}
+
+// Enters the sleep state S5 through the PM1 control registers, or the sleep control register on
+// hardware reduced machines.
+//
+// ## Safety
+// The machine is powered off on success.
+unsafe fn enter_soft_off() -> Result<(), PowerError> {
+    let tables = acpi::tables().ok_or(PowerError::NotSupported)?;
+    let fadt = tables.fadt.as_ref().ok_or(PowerError::NotSupported)?;
+    let s5 = tables.s5.ok_or(PowerError::NoSoftOffState)?;
+
+    if fadt.flags.contains(FadtFlags::HARDWARE_REDUCED_ACPI) {
+        let sleep_control = fadt
+            .sleep_control_register
+            .ok_or(PowerError::NotSupported)?;
+        let value = ((s5.pm1a as u64) << SLEEP_CONTROL_TYPE_SHIFT) & SLEEP_CONTROL_TYPE_MASK;
+        unsafe { sleep_control.write(value | SLEEP_CONTROL_ENABLE) }
+            .map_err(PowerError::Register)?;
+    } else {
+        let pm1a_control = fadt.pm1a_control_block.ok_or(PowerError::NotSupported)?;
+        unsafe { enable_acpi_mode(fadt, &pm1a_control) }?;
+
+        // The PM1b block is optional. Both need to be written for the sleep state to be entered.
+        unsafe { write_sleep_type(&pm1a_control, s5.pm1a) }?;
+        if let Some(pm1b_control) = fadt.pm1b_control_block {
+            unsafe { write_sleep_type(&pm1b_control, s5.pm1b) }?;
+        }
+    }
+
+    delay(POWER_OFF_DELAY_MS);
+    Err(PowerError::NoEffect)
+}
+
+// Firmware that boots in legacy mode owns the power management registers until it is asked to
+// switch to ACPI mode through the SMI command port.
+//
+// ## Safety
+// pm1a_control needs to be the PM1a control block of the FADT.
+unsafe fn enable_acpi_mode(fadt: &Fadt, pm1a_control: &GenericAddress) -> Result<(), PowerError> {
+    let is_acpi_mode = || {
+        unsafe { pm1a_control.read() }
+            .map(|value| value & PM1_SCI_ENABLE != 0)
+            .map_err(PowerError::Register)
+    };
+
+    // Machines without an SMI command port are always in ACPI mode.
+    if fadt.smi_command_port == 0 || fadt.acpi_enable == 0 || is_acpi_mode()? {
+        return Ok(());
+    }
+
+    unsafe { Port::<u8>::new(fadt.smi_command_port as u16).write(fadt.acpi_enable) };
+    for _ in 0..ACPI_MODE_TIMEOUT_MS {
+        if is_acpi_mode()? {
+            return Ok(());
+        }
+        delay(1);
+    }
+
+    Err(PowerError::AcpiModeTimeout)
+}
+
+// Writes the sleep type to a PM1 control register, keeping its other fields.
+//
+// ## Safety
+// The machine enters the sleep state.
+unsafe fn write_sleep_type(control: &GenericAddress, sleep_type: u8) -> Result<(), PowerError> {
+    let value = unsafe { control.read() }.map_err(PowerError::Register)?;
+    let value = (value & !(PM1_SLEEP_TYPE_MASK | PM1_SLEEP_ENABLE))
+        | (((sleep_type as u64) << PM1_SLEEP_TYPE_SHIFT) & PM1_SLEEP_TYPE_MASK)
+        | PM1_SLEEP_ENABLE;
+    unsafe { control.write(value) }.map_err(PowerError::Register)
+}
+
+// Asks the keyboard controller to pulse the reset line, once it accepts commands.
+//
+// ## Safety
+// The machine is reset.
+unsafe fn pulse_reset_line() {
+    let mut port: Port<u8> = Port::new(KEYBOARD_CONTROLLER_PORT);
+    for _ in 0..KEYBOARD_CONTROLLER_RETRIES {
+        if unsafe { port.read() } & KEYBOARD_CONTROLLER_INPUT_FULL == 0 {
+            break;
+        }
+        spin_loop();
+    }
+
+    unsafe { port.write(KEYBOARD_CONTROLLER_PULSE_RESET) };
+}
+
+// Waits for about ms milliseconds. Interrupts are disabled on every path that ends here, so the ACPI
+// PM timer is polled.
+#[inline]
+fn delay(ms: u64) {
+    pm_timer::delay_us(ms * 1000);
+}
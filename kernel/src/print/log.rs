@@ -4,6 +4,7 @@ use core::fmt::Write;
 use spinning_top::Spinlock;
 
 use crate::print::writer::Writer;
+use crate::time;
 
 // The global logger instance used for the `log` crate.
 pub static LOGGER: OnceCell<KernelLogger> = OnceCell::uninit();
@@ -91,7 +92,19 @@ impl log::Log for KernelLogger {
         if let Some(framebuffer) = &self.framebuffer {
             run_without_interrupts(|| {
                 let mut framebuffer = framebuffer.lock();
-                writeln!(framebuffer, "{:5}: {}", record.level(), record.args()).unwrap();
+
+                // Lines carry the calendar time once the RTC was read.
+                match time::date_time() {
+                    Some(date_time) => writeln!(
+                        framebuffer,
+                        "{} {:5}: {}",
+                        date_time,
+                        record.level(),
+                        record.args()
+                    ),
+                    None => writeln!(framebuffer, "{:5}: {}", record.level(), record.args()),
+                }
+                .unwrap();
             });
         }
     }
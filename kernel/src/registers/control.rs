@@ -5,6 +5,80 @@ use crate::memory::vaddr::VirtualAddress;
 use bitflags::bitflags;
 use core::arch::asm;
 
+// CR0 contains system control flags that control the operating mode and states of the processor.
+#[derive(Debug)]
+pub struct CR0;
+
+// https://wiki.osdev.org/CPU_Registers_x86-64#CR0
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct CR0Flags: u64 {
+        const PROTECTED_MODE_ENABLE = 1;
+        const MONITOR_COPROCESSOR = 1 << 1;
+        const EMULATE_COPROCESSOR = 1 << 2;
+        const TASK_SWITCHED = 1 << 3;
+        const EXTENSION_TYPE = 1 << 4;
+        const NUMERIC_ERROR = 1 << 5;
+
+        // Makes read-only pages read-only for the kernel as well
+        const WRITE_PROTECT = 1 << 16;
+
+        const ALIGNMENT_MASK = 1 << 18;
+        const NOT_WRITE_THROUGH = 1 << 29;
+        const CACHE_DISABLE = 1 << 30;
+        const PAGING = 1 << 31;
+    }
+}
+
+impl CR0 {
+    #[inline]
+    pub fn read() -> CR0Flags {
+        CR0Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let mut value: u64 = 0;
+        unsafe {
+            asm!(
+                "mov {}, cr0",
+                out(reg) value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        value
+    }
+
+    // Writes the flags while keeping the reserved bits.
+    //
+    // ## Safety
+    // Flags like PAGING or PROTECTED_MODE_ENABLE change how the CPU executes the kernel. The
+    // caller has to make sure the new flags do not break memory safety.
+    #[inline]
+    pub unsafe fn write(flags: CR0Flags) {
+        let value = (Self::read_raw() & !CR0Flags::all().bits()) | flags.bits();
+        unsafe {
+            asm!(
+                "mov cr0, {}",
+                in(reg) value,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+
+    // Updates the flags with the given closure.
+    //
+    // ## Safety
+    // Same as write.
+    #[inline]
+    pub unsafe fn update<F: FnOnce(&mut CR0Flags)>(f: F) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        unsafe { Self::write(flags) };
+    }
+}
+
 // This register holds the value called Page Fault Linear Address (PFLA).
 // When a page fault occurs, the address the program attempted to access is stored in the CR2 register.
 #[derive(Debug)]
@@ -41,6 +115,37 @@ bitflags! {
     }
 }
 
+// A process context identifier. With CR4.PCIDE set, the lower 12 bits of CR3 hold the PCID of the
+// active address space instead of the cache flags. TLB entries are tagged with the PCID, so they
+// survive switching between address spaces.
+#[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+pub struct Pcid(u16);
+
+impl Pcid {
+    pub const MAX: u16 = 0x0FFF;
+
+    // Returns None if the value does not fit in 12 bits.
+    #[inline]
+    pub const fn new(pcid: u16) -> Option<Pcid> {
+        if pcid > Self::MAX {
+            return None;
+        }
+
+        Some(Pcid(pcid))
+    }
+
+    #[inline]
+    pub const fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+const CR3_PDBR_MASK: u64 = 0x_000F_FFFF_FFFF_F000;
+const CR3_FLAGS_MASK: u64 = 0x_0000_0FFF;
+
+// Setting bit 63 when writing CR3 with PCIDs enabled keeps the TLB entries of the new PCID.
+const CR3_NO_FLUSH: u64 = 1 << 63;
+
 impl CR3 {
     #[inline]
     pub fn read() -> (Frame, CR3Flags) {
@@ -53,12 +158,244 @@ impl CR3 {
             );
         }
 
-        let pdbr_mask: u64 = 0x_000F_FFFF_FFFF_F000;
-        let cr3_flags_mask: u64 = 0x_0000_0FFF;
-
-        let frame: Frame = Frame::new(PhysicalAddress::new(value & pdbr_mask));
-        let flags: CR3Flags = CR3Flags::from_bits_truncate(value & cr3_flags_mask);
+        let frame: Frame = Frame::new(PhysicalAddress::new(value & CR3_PDBR_MASK));
+        let flags: CR3Flags = CR3Flags::from_bits_truncate(value & CR3_FLAGS_MASK);
 
         (frame, flags)
     }
+
+    // Reads CR3 when PCIDs are enabled, in which case the lower 12 bits hold the active PCID.
+    #[inline]
+    pub fn read_pcid() -> (Frame, Pcid) {
+        let mut value: u64 = 0;
+        unsafe {
+            asm!(
+                "mov {}, cr3",
+                out(reg) value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        let frame: Frame = Frame::new(PhysicalAddress::new(value & CR3_PDBR_MASK));
+        let pcid: Pcid = Pcid((value & CR3_FLAGS_MASK) as u16);
+
+        (frame, pcid)
+    }
+
+    // Switches to the level 4 page table in the given frame. This flushes all non-global TLB
+    // entries.
+    //
+    // ## Safety
+    // The page table has to map the currently executing code, the stack and all data the kernel
+    // accesses afterwards, otherwise the CPU faults right away.
+    #[inline]
+    pub unsafe fn write(frame: Frame, flags: CR3Flags) {
+        let value: u64 = frame.start_address().address() | flags.bits();
+        unsafe {
+            asm!(
+                "mov cr3, {}",
+                in(reg) value,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+
+    // Switches to the level 4 page table in the given frame and tags new TLB entries with the
+    // PCID. Unless flush is set, TLB entries cached for the PCID earlier stay valid.
+    //
+    // ## Safety
+    // Same as write. In addition, CR4.PCIDE needs to be set, otherwise this causes a general
+    // protection fault.
+    #[inline]
+    pub unsafe fn write_pcid(frame: Frame, pcid: Pcid, flush: bool) {
+        let mut value: u64 = frame.start_address().address() | pcid.value() as u64;
+        if !flush {
+            value |= CR3_NO_FLUSH;
+        }
+
+        unsafe {
+            asm!(
+                "mov cr3, {}",
+                in(reg) value,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+}
+
+// CR4 contains flags that enable architectural extensions of the processor.
+#[derive(Debug)]
+pub struct CR4;
+
+// https://wiki.osdev.org/CPU_Registers_x86-64#CR4
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct CR4Flags: u64 {
+        const VIRTUAL_8086_MODE_EXTENSIONS = 1;
+        const PROTECTED_MODE_VIRTUAL_INTERRUPTS = 1 << 1;
+        const TIMESTAMP_DISABLE = 1 << 2;
+        const DEBUGGING_EXTENSIONS = 1 << 3;
+        const PAGE_SIZE_EXTENSION = 1 << 4;
+        const PHYSICAL_ADDRESS_EXTENSION = 1 << 5;
+        const MACHINE_CHECK_EXCEPTION = 1 << 6;
+
+        // Enables global pages, which are kept in the TLB when CR3 is written
+        const PAGE_GLOBAL = 1 << 7;
+
+        const PERFORMANCE_MONITOR_COUNTER = 1 << 8;
+        const OSFXSR = 1 << 9;
+        const OSXMMEXCPT_ENABLE = 1 << 10;
+        const USER_MODE_INSTRUCTION_PREVENTION = 1 << 11;
+        const L5_PAGING = 1 << 12;
+        const VIRTUAL_MACHINE_EXTENSIONS = 1 << 13;
+        const SAFER_MODE_EXTENSIONS = 1 << 14;
+        const FSGSBASE = 1 << 16;
+
+        // Enables process context identifiers in the lower 12 bits of CR3
+        const PCID = 1 << 17;
+
+        const OSXSAVE = 1 << 18;
+        const KEY_LOCKER = 1 << 19;
+
+        // Faults when the kernel executes code from user accessible pages
+        const SUPERVISOR_MODE_EXECUTION_PROTECTION = 1 << 20;
+
+        // Faults when the kernel accesses user accessible pages, unless RFLAGS.AC is set
+        const SUPERVISOR_MODE_ACCESS_PREVENTION = 1 << 21;
+
+        const PROTECTION_KEY_USER = 1 << 22;
+        const CONTROL_FLOW_ENFORCEMENT = 1 << 23;
+        const PROTECTION_KEY_SUPERVISOR = 1 << 24;
+    }
+}
+
+impl CR4 {
+    #[inline]
+    pub fn read() -> CR4Flags {
+        CR4Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let mut value: u64 = 0;
+        unsafe {
+            asm!(
+                "mov {}, cr4",
+                out(reg) value,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        value
+    }
+
+    // Writes the flags while keeping the reserved bits. Setting a flag the CPU does not support
+    // causes a general protection fault.
+    //
+    // ## Safety
+    // The caller has to make sure the new flags do not break memory safety.
+    #[inline]
+    pub unsafe fn write(flags: CR4Flags) {
+        let value = (Self::read_raw() & !CR4Flags::all().bits()) | flags.bits();
+        unsafe {
+            asm!(
+                "mov cr4, {}",
+                in(reg) value,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+
+    // Updates the flags with the given closure.
+    //
+    // ## Safety
+    // Same as write.
+    #[inline]
+    pub unsafe fn update<F: FnOnce(&mut CR4Flags)>(f: F) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        unsafe { Self::write(flags) };
+    }
+}
+
+// XCR0 is an extended control register that selects the state components saved and restored by
+// the xsave and xrstor instructions. It can only be accessed once CR4.OSXSAVE is set.
+#[derive(Debug)]
+pub struct XCR0;
+
+// https://wiki.osdev.org/CPU_Registers_x86-64#XCR0
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct XCR0Flags: u64 {
+        // Has to be set at all times
+        const X87 = 1;
+
+        const SSE = 1 << 1;
+
+        // Requires SSE
+        const AVX = 1 << 2;
+
+        const BNDREG = 1 << 3;
+        const BNDCSR = 1 << 4;
+
+        // OPMASK, ZMM_HI256 and HI16_ZMM enable AVX-512 and have to be set together with AVX
+        const OPMASK = 1 << 5;
+        const ZMM_HI256 = 1 << 6;
+        const HI16_ZMM = 1 << 7;
+
+        const PKRU = 1 << 9;
+    }
+}
+
+impl XCR0 {
+    #[inline]
+    pub fn read() -> XCR0Flags {
+        XCR0Flags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let (high, low): (u32, u32);
+        unsafe {
+            asm!(
+                "xgetbv",
+                in("ecx") 0,
+                out("eax") low,
+                out("edx") high,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        ((high as u64) << 32) | (low as u64)
+    }
+
+    // Writes the flags while keeping the reserved bits. Invalid combinations of flags, e.g. AVX
+    // without SSE, cause a general protection fault.
+    //
+    // ## Safety
+    // Disabling a state component that is in use loses its register contents.
+    #[inline]
+    pub unsafe fn write(flags: XCR0Flags) {
+        let value = (Self::read_raw() & !XCR0Flags::all().bits()) | flags.bits();
+        unsafe {
+            asm!(
+                "xsetbv",
+                in("ecx") 0,
+                in("eax") value as u32,
+                in("edx") (value >> 32) as u32,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+    }
+
+    // Updates the flags with the given closure.
+    //
+    // ## Safety
+    // Same as write.
+    #[inline]
+    pub unsafe fn update<F: FnOnce(&mut XCR0Flags)>(f: F) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        unsafe { Self::write(flags) };
+    }
 }
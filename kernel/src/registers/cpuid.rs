@@ -0,0 +1,401 @@
+use bitflags::bitflags;
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+use core::fmt;
+use lazy_static::lazy_static;
+
+// The deterministic cache parameters leaf, reported by Intel CPUs.
+const INTEL_CACHE_LEAF: u32 = 0x4;
+
+// The cache topology leaf, reported by AMD CPUs with topology extensions.
+const AMD_CACHE_LEAF: u32 = 0x8000_001D;
+
+// The number of cache levels and types kept in CpuInfo.
+const MAX_CACHES: usize = 8;
+
+// Executes the CPUID instruction for the given leaf and subleaf. CPUID is available on every
+// x86_64 processor.
+#[inline]
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    __cpuid_count(leaf, subleaf)
+}
+
+// Returns true if the CPU supports the given leaf.
+#[inline]
+fn has_leaf(leaf: u32) -> bool {
+    // The highest supported leaf of the standard and extended ranges is reported by their first
+    // leaf.
+    cpuid(leaf & 0x8000_0000, 0).eax >= leaf
+}
+
+// Processor features the kernel cares about. The bits do not correspond to CPUID bits, as the
+// features are spread across several leaves.
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct CpuFeatures: u64 {
+        const TSC = 1;
+        const PAE = 1 << 1;
+        const APIC = 1 << 2;
+
+        // Global pages in page table entries
+        const PGE = 1 << 3;
+
+        const SSE = 1 << 4;
+        const SSE2 = 1 << 5;
+        const PCID = 1 << 6;
+        const X2APIC = 1 << 7;
+
+        // The local APIC timer can fire at an absolute TSC value
+        const TSC_DEADLINE = 1 << 8;
+
+        const XSAVE = 1 << 9;
+        const AVX = 1 << 10;
+        const RDRAND = 1 << 11;
+
+        // The kernel runs in a virtual machine
+        const HYPERVISOR = 1 << 12;
+
+        const FSGSBASE = 1 << 13;
+        const SMEP = 1 << 14;
+        const INVPCID = 1 << 15;
+        const SMAP = 1 << 16;
+        const NO_EXECUTE = 1 << 17;
+        const HUGE_PAGE_1G = 1 << 18;
+        const RDTSCP = 1 << 19;
+
+        // The TSC runs at a constant rate in all power states
+        const INVARIANT_TSC = 1 << 20;
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, _)) in self.iter_names().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The CPUID leaf, register and bit of every feature.
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+const FEATURE_BITS: [(CpuFeatures, u32, Register, u32); 21] = [
+    (CpuFeatures::TSC, 0x1, Register::Edx, 4),
+    (CpuFeatures::PAE, 0x1, Register::Edx, 6),
+    (CpuFeatures::APIC, 0x1, Register::Edx, 9),
+    (CpuFeatures::PGE, 0x1, Register::Edx, 13),
+    (CpuFeatures::SSE, 0x1, Register::Edx, 25),
+    (CpuFeatures::SSE2, 0x1, Register::Edx, 26),
+    (CpuFeatures::PCID, 0x1, Register::Ecx, 17),
+    (CpuFeatures::X2APIC, 0x1, Register::Ecx, 21),
+    (CpuFeatures::TSC_DEADLINE, 0x1, Register::Ecx, 24),
+    (CpuFeatures::XSAVE, 0x1, Register::Ecx, 26),
+    (CpuFeatures::AVX, 0x1, Register::Ecx, 28),
+    (CpuFeatures::RDRAND, 0x1, Register::Ecx, 30),
+    (CpuFeatures::HYPERVISOR, 0x1, Register::Ecx, 31),
+    (CpuFeatures::FSGSBASE, 0x7, Register::Ebx, 0),
+    (CpuFeatures::SMEP, 0x7, Register::Ebx, 7),
+    (CpuFeatures::INVPCID, 0x7, Register::Ebx, 10),
+    (CpuFeatures::SMAP, 0x7, Register::Ebx, 20),
+    (CpuFeatures::NO_EXECUTE, 0x8000_0001, Register::Edx, 20),
+    (CpuFeatures::HUGE_PAGE_1G, 0x8000_0001, Register::Edx, 26),
+    (CpuFeatures::RDTSCP, 0x8000_0001, Register::Edx, 27),
+    (CpuFeatures::INVARIANT_TSC, 0x8000_0007, Register::Edx, 8),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+// A cache of the CPU, as reported by the cache parameters leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cache {
+    pub level: u8,
+    pub cache_type: CacheType,
+    pub line_size: u32,
+    pub ways: u32,
+    pub sets: u32,
+    pub partitions: u32,
+}
+
+impl Cache {
+    // Decodes a subleaf of the cache parameters leaf. Intel and AMD use the same layout. Returns
+    // None for the subleaf terminating the list.
+    #[inline]
+    fn decode(result: CpuidResult) -> Option<Cache> {
+        let cache_type = match result.eax & 0x1F {
+            1 => CacheType::Data,
+            2 => CacheType::Instruction,
+            3 => CacheType::Unified,
+            _ => return None,
+        };
+
+        Some(Cache {
+            level: ((result.eax >> 5) & 0x7) as u8,
+            cache_type,
+            line_size: (result.ebx & 0xFFF) + 1,
+            partitions: ((result.ebx >> 12) & 0x3FF) + 1,
+            ways: (result.ebx >> 22) + 1,
+            sets: result.ecx + 1,
+        })
+    }
+
+    // The size of the cache in bytes.
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.line_size as u64 * self.partitions as u64 * self.ways as u64 * self.sets as u64
+    }
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cache_type = match self.cache_type {
+            CacheType::Data => "data",
+            CacheType::Instruction => "instruction",
+            CacheType::Unified => "unified",
+        };
+
+        write!(
+            f,
+            "L{} {} {} KiB, {}-way, {} byte lines",
+            self.level,
+            cache_type,
+            self.size() / 1024,
+            self.ways,
+            self.line_size
+        )
+    }
+}
+
+// Everything the kernel knows about the processor. The values are the same on all CPUs of the
+// system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    pub features: CpuFeatures,
+    pub physical_address_bits: u8,
+    pub virtual_address_bits: u8,
+    caches: [Option<Cache>; MAX_CACHES],
+}
+
+impl CpuInfo {
+    // Queries the processor.
+    pub fn read() -> CpuInfo {
+        let vendor_leaf = cpuid(0, 0);
+        let mut vendor = [0; 12];
+        for (i, register) in [vendor_leaf.ebx, vendor_leaf.edx, vendor_leaf.ecx]
+            .iter()
+            .enumerate()
+        {
+            vendor[i * 4..(i + 1) * 4].copy_from_slice(&register.to_le_bytes());
+        }
+
+        let mut brand = [0; 48];
+        if has_leaf(0x8000_0004) {
+            for (i, leaf) in (0x8000_0002..=0x8000_0004).enumerate() {
+                let result = cpuid(leaf, 0);
+                for (j, register) in [result.eax, result.ebx, result.ecx, result.edx]
+                    .iter()
+                    .enumerate()
+                {
+                    let start = i * 16 + j * 4;
+                    brand[start..start + 4].copy_from_slice(&register.to_le_bytes());
+                }
+            }
+        }
+
+        let (family, model, stepping) = decode_signature(cpuid(0x1, 0).eax);
+
+        let mut features = CpuFeatures::empty();
+        for (feature, leaf, register, bit) in FEATURE_BITS {
+            if !has_leaf(leaf) {
+                continue;
+            }
+
+            let result = cpuid(leaf, 0);
+            let value = match register {
+                Register::Ebx => result.ebx,
+                Register::Ecx => result.ecx,
+                Register::Edx => result.edx,
+            };
+            features.set(feature, value & (1 << bit) != 0);
+        }
+
+        // Without the address size leaf, the CPU supports the minimum widths of long mode.
+        let (physical_address_bits, virtual_address_bits) = if has_leaf(0x8000_0008) {
+            let eax = cpuid(0x8000_0008, 0).eax;
+            ((eax & 0xFF) as u8, ((eax >> 8) & 0xFF) as u8)
+        } else {
+            (36, 48)
+        };
+
+        let mut info = CpuInfo {
+            vendor,
+            brand,
+            family,
+            model,
+            stepping,
+            features,
+            physical_address_bits,
+            virtual_address_bits,
+            caches: [None; MAX_CACHES],
+        };
+        info.read_caches();
+        info
+    }
+
+    fn read_caches(&mut self) {
+        // AMD reports the topology extensions in bit 22 of ecx of the extended feature leaf.
+        let leaf = if self.vendor() == "AuthenticAMD" {
+            if !has_leaf(AMD_CACHE_LEAF) || cpuid(0x8000_0001, 0).ecx & (1 << 22) == 0 {
+                return;
+            }
+            AMD_CACHE_LEAF
+        } else {
+            if !has_leaf(INTEL_CACHE_LEAF) {
+                return;
+            }
+            INTEL_CACHE_LEAF
+        };
+
+        for (subleaf, cache) in self.caches.iter_mut().enumerate() {
+            *cache = Cache::decode(cpuid(leaf, subleaf as u32));
+            if cache.is_none() {
+                break;
+            }
+        }
+    }
+
+    // The vendor identification string, e.g. GenuineIntel or AuthenticAMD.
+    #[inline]
+    pub fn vendor(&self) -> &str {
+        core::str::from_utf8(&self.vendor).unwrap_or("")
+    }
+
+    // The processor brand string, or an empty string if the CPU does not report one.
+    #[inline]
+    pub fn brand(&self) -> &str {
+        trim_brand(&self.brand)
+    }
+
+    #[inline]
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().map_while(|cache| cache.as_ref())
+    }
+
+    #[inline]
+    pub fn has(&self, features: CpuFeatures) -> bool {
+        self.features.contains(features)
+    }
+
+    // Logs the processor information.
+    pub fn log(&self) {
+        log::info!(
+            "CPU: {} {} (family {:#x}, model {:#x}, stepping {})",
+            self.vendor(),
+            self.brand(),
+            self.family,
+            self.model,
+            self.stepping
+        );
+
+        log::info!("CPU features: {}", self.features);
+
+        log::info!(
+            "CPU address widths: {} bit physical, {} bit virtual",
+            self.physical_address_bits,
+            self.virtual_address_bits
+        );
+
+        for cache in self.caches() {
+            log::info!("CPU cache: {}", cache);
+        }
+    }
+}
+
+lazy_static! {
+    static ref CPU_INFO: CpuInfo = CpuInfo::read();
+}
+
+// Returns the processor information, which is read on first use.
+#[inline]
+pub fn cpu_info() -> &'static CpuInfo {
+    &CPU_INFO
+}
+
+// Returns true if the CPU supports all given features.
+#[inline]
+pub fn has(features: CpuFeatures) -> bool {
+    cpu_info().has(features)
+}
+
+// Decodes the family, model and stepping from eax of leaf 1. The extended family is only used for
+// family 0xF, the extended model only for families 0x6 and 0xF.
+#[inline]
+fn decode_signature(eax: u32) -> (u32, u32, u32) {
+    let stepping = eax & 0xF;
+    let base_model = (eax >> 4) & 0xF;
+    let base_family = (eax >> 8) & 0xF;
+    let extended_model = (eax >> 16) & 0xF;
+    let extended_family = (eax >> 20) & 0xFF;
+
+    let family = if base_family == 0xF {
+        base_family + extended_family
+    } else {
+        base_family
+    };
+    let model = if base_family == 0x6 || base_family == 0xF {
+        (extended_model << 4) | base_model
+    } else {
+        base_model
+    };
+
+    (family, model, stepping)
+}
+
+// The brand string is padded with spaces at the start and NUL bytes at the end.
+#[inline]
+fn trim_brand(brand: &[u8]) -> &str {
+    let end = brand
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(brand.len());
+    core::str::from_utf8(&brand[..end]).unwrap_or("").trim()
+}
+
+#[test_case]
+fn test_decode_signature() {
+    // Intel Core i7-8700, family 6 with an extended model
+    assert_eq!(decode_signature(0x000906EA), (0x6, 0x9E, 0xA));
+
+    // AMD Zen 2, family 0xF with an extended family
+    assert_eq!(decode_signature(0x00870F10), (0x17, 0x71, 0x0));
+
+    // Pentium, neither extension applies
+    assert_eq!(decode_signature(0x00F70543), (0x5, 0x4, 0x3));
+}
+
+#[test_case]
+fn test_long_mode_features() {
+    let info = cpu_info();
+    assert!(info.has(CpuFeatures::PAE | CpuFeatures::APIC | CpuFeatures::SSE2));
+    assert!(info.physical_address_bits >= 32);
+    assert!(info.virtual_address_bits >= 48);
+    assert_eq!(trim_brand(b"  QEMU Virtual CPU\0\0\0"), "QEMU Virtual CPU");
+}
@@ -1,2 +1,5 @@
 pub mod control;
+pub mod cpuid;
+pub mod model_specific;
+pub mod rflags;
 pub mod segment;
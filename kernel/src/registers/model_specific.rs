@@ -0,0 +1,430 @@
+use bitflags::bitflags;
+use core::arch::asm;
+
+use crate::interrupts::privilege::KernelRings;
+use crate::memory::frame::Frame;
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::vaddr::VirtualAddress;
+use crate::registers::rflags::RFlags;
+use crate::registers::segment::SegmentSelector;
+
+// A model specific register, addressed by its 32 bit index. MSRs are read and written with the
+// rdmsr and wrmsr instructions, which fault if the MSR does not exist on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Msr(u32);
+
+impl Msr {
+    #[inline]
+    pub const fn new(register: u32) -> Msr {
+        Msr(register)
+    }
+
+    #[inline]
+    pub const fn register(&self) -> u32 {
+        self.0
+    }
+
+    // ## Safety
+    // The MSR has to exist on the CPU, otherwise this causes a general protection fault.
+    #[inline]
+    pub unsafe fn read(&self) -> u64 {
+        let (high, low): (u32, u32);
+        unsafe {
+            asm!(
+                "rdmsr",
+                in("ecx") self.0,
+                out("eax") low,
+                out("edx") high,
+                options(nomem, nostack, preserves_flags)
+            );
+        }
+
+        ((high as u64) << 32) | (low as u64)
+    }
+
+    // ## Safety
+    // The MSR has to exist on the CPU and accept the value. Many MSRs change how the CPU executes
+    // code, so the caller has to make sure the write does not break memory safety.
+    #[inline]
+    pub unsafe fn write(&self, value: u64) {
+        unsafe {
+            asm!(
+                "wrmsr",
+                in("ecx") self.0,
+                in("eax") value as u32,
+                in("edx") (value >> 32) as u32,
+                options(nostack, preserves_flags)
+            );
+        }
+    }
+}
+
+// The Extended Feature Enable Register is a model specific register that enables long mode and the
+// no-execute bit in page tables, among others.
+#[derive(Debug)]
+pub struct Efer;
+
+// The base address of the FS segment.
+#[derive(Debug)]
+pub struct FsBase;
+
+// The base address of the GS segment.
+#[derive(Debug)]
+pub struct GsBase;
+
+// The value swapped with GsBase by the swapgs instruction. While user code runs, it holds the
+// kernel GS base.
+#[derive(Debug)]
+pub struct KernelGsBase;
+
+// The segment selectors loaded by the syscall and sysret instructions.
+#[derive(Debug)]
+pub struct Star;
+
+// The instruction pointer the syscall instruction jumps to in 64 bit mode.
+#[derive(Debug)]
+pub struct LStar;
+
+// The RFLAGS bits cleared by the syscall instruction.
+#[derive(Debug)]
+pub struct SfMask;
+
+// The physical address and the mode of the local APIC.
+#[derive(Debug)]
+pub struct ApicBase;
+
+// The value returned in ecx by the rdtscp instruction. Kernels usually store the CPU number in it.
+#[derive(Debug)]
+pub struct TscAux;
+
+impl Efer {
+    pub const MSR: Msr = Msr::new(0xC000_0080);
+}
+
+impl FsBase {
+    pub const MSR: Msr = Msr::new(0xC000_0100);
+}
+
+impl GsBase {
+    pub const MSR: Msr = Msr::new(0xC000_0101);
+}
+
+impl KernelGsBase {
+    pub const MSR: Msr = Msr::new(0xC000_0102);
+}
+
+impl Star {
+    pub const MSR: Msr = Msr::new(0xC000_0081);
+}
+
+impl LStar {
+    pub const MSR: Msr = Msr::new(0xC000_0082);
+}
+
+impl SfMask {
+    pub const MSR: Msr = Msr::new(0xC000_0084);
+}
+
+impl ApicBase {
+    pub const MSR: Msr = Msr::new(0x1B);
+}
+
+impl TscAux {
+    pub const MSR: Msr = Msr::new(0xC000_0103);
+}
+
+// https://wiki.osdev.org/CPU_Registers_x86-64#IA32_EFER
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct EferFlags: u64 {
+        const SYSTEM_CALL_EXTENSIONS = 1;
+        const LONG_MODE_ENABLE = 1 << 8;
+        const LONG_MODE_ACTIVE = 1 << 10;
+
+        // Makes the NO_EXECUTE page table flag usable. Without it, bit 63 of an entry is reserved.
+        const NO_EXECUTE_ENABLE = 1 << 11;
+
+        const SECURE_VIRTUAL_MACHINE_ENABLE = 1 << 12;
+        const LONG_MODE_SEGMENT_LIMIT_ENABLE = 1 << 13;
+        const FAST_FXSAVE_FXRSTOR = 1 << 14;
+        const TRANSLATION_CACHE_EXTENSION = 1 << 15;
+    }
+}
+
+// https://wiki.osdev.org/APIC#Local_APIC_configuration
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct ApicBaseFlags: u64 {
+        // Set on the processor that was started by the firmware
+        const BOOTSTRAP_PROCESSOR = 1 << 8;
+
+        // Switches the local APIC from memory mapped registers to MSRs
+        const X2APIC_ENABLE = 1 << 10;
+
+        const GLOBAL_ENABLE = 1 << 11;
+    }
+}
+
+impl Efer {
+    #[inline]
+    pub fn read() -> EferFlags {
+        EferFlags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        // EFER exists on every x86_64 processor.
+        unsafe { Self::MSR.read() }
+    }
+
+    // Writes the flags while keeping the reserved bits.
+    //
+    // ## Safety
+    // Clearing LONG_MODE_ENABLE or NO_EXECUTE_ENABLE while they are in use breaks the kernel.
+    #[inline]
+    pub unsafe fn write(flags: EferFlags) {
+        let value = (Self::read_raw() & !EferFlags::all().bits()) | flags.bits();
+        unsafe { Self::MSR.write(value) };
+    }
+
+    // Updates the flags with the given closure.
+    //
+    // ## Safety
+    // Same as write.
+    #[inline]
+    pub unsafe fn update<F: FnOnce(&mut EferFlags)>(f: F) {
+        let mut flags = Self::read();
+        f(&mut flags);
+        unsafe { Self::write(flags) };
+    }
+}
+
+impl FsBase {
+    #[inline]
+    pub fn read() -> VirtualAddress {
+        VirtualAddress::new(unsafe { Self::MSR.read() })
+    }
+
+    // ## Safety
+    // Code using FS relative addressing, e.g. thread locals, has to expect the new base.
+    #[inline]
+    pub unsafe fn write(address: VirtualAddress) {
+        unsafe { Self::MSR.write(address.address()) };
+    }
+}
+
+impl GsBase {
+    #[inline]
+    pub fn read() -> VirtualAddress {
+        VirtualAddress::new(unsafe { Self::MSR.read() })
+    }
+
+    // ## Safety
+    // Code using GS relative addressing, e.g. per-CPU data, has to expect the new base.
+    #[inline]
+    pub unsafe fn write(address: VirtualAddress) {
+        unsafe { Self::MSR.write(address.address()) };
+    }
+}
+
+impl KernelGsBase {
+    #[inline]
+    pub fn read() -> VirtualAddress {
+        VirtualAddress::new(unsafe { Self::MSR.read() })
+    }
+
+    // ## Safety
+    // The value becomes the GS base after the next swapgs.
+    #[inline]
+    pub unsafe fn write(address: VirtualAddress) {
+        unsafe { Self::MSR.write(address.address()) };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StarError {
+    // sysret loads the user code segment from 16 and the user stack segment from 8 bytes above the
+    // base selector, so the user data segment has to come right before the user code segment.
+    InvalidUserSelectors,
+
+    // syscall loads the kernel stack segment from 8 bytes above the kernel code segment.
+    InvalidKernelSelectors,
+
+    // The user selectors need ring 3 and the kernel selectors ring 0.
+    InvalidPrivilegeLevel,
+}
+
+impl Star {
+    // Returns the base selectors used by sysret and syscall.
+    #[inline]
+    pub fn read_raw() -> (u16, u16) {
+        let value = unsafe { Self::MSR.read() };
+        ((value >> 48) as u16, (value >> 32) as u16)
+    }
+
+    // Returns the user code, user stack, kernel code and kernel stack selectors, in this order.
+    #[inline]
+    pub fn read() -> (
+        SegmentSelector,
+        SegmentSelector,
+        SegmentSelector,
+        SegmentSelector,
+    ) {
+        let (sysret_base, syscall_base) = Self::read_raw();
+        (
+            SegmentSelector(sysret_base + 16),
+            SegmentSelector(sysret_base + 8),
+            SegmentSelector(syscall_base),
+            SegmentSelector(syscall_base + 8),
+        )
+    }
+
+    // ## Safety
+    // The selectors have to point to valid segments of the active GDT.
+    #[inline]
+    pub unsafe fn write_raw(sysret_base: u16, syscall_base: u16) {
+        let mut value = unsafe { Self::MSR.read() } & 0x_0000_0000_FFFF_FFFF;
+        value |= (sysret_base as u64) << 48 | (syscall_base as u64) << 32;
+        unsafe { Self::MSR.write(value) };
+    }
+
+    // Checks that the selectors are laid out the way syscall and sysret expect them and writes
+    // them.
+    //
+    // ## Safety
+    // The selectors have to point to valid segments of the active GDT.
+    pub unsafe fn write(
+        user_code: SegmentSelector,
+        user_stack: SegmentSelector,
+        kernel_code: SegmentSelector,
+        kernel_stack: SegmentSelector,
+    ) -> Result<(), StarError> {
+        let ring = |selector: SegmentSelector| selector.0 & 0b11;
+
+        if user_code.0.wrapping_sub(16) != user_stack.0.wrapping_sub(8) {
+            return Err(StarError::InvalidUserSelectors);
+        }
+        if kernel_code.0 + 8 != kernel_stack.0 {
+            return Err(StarError::InvalidKernelSelectors);
+        }
+        if ring(user_code) != KernelRings::Ring3 as u16
+            || ring(kernel_code) != KernelRings::Ring0 as u16
+        {
+            return Err(StarError::InvalidPrivilegeLevel);
+        }
+
+        unsafe { Self::write_raw(user_stack.0 - 8, kernel_code.0) };
+        Ok(())
+    }
+}
+
+impl LStar {
+    #[inline]
+    pub fn read() -> VirtualAddress {
+        VirtualAddress::new(unsafe { Self::MSR.read() })
+    }
+
+    // ## Safety
+    // The address has to point to a valid system call entry point.
+    #[inline]
+    pub unsafe fn write(address: VirtualAddress) {
+        unsafe { Self::MSR.write(address.address()) };
+    }
+}
+
+impl SfMask {
+    #[inline]
+    pub fn read() -> RFlags {
+        RFlags::from_bits_truncate(unsafe { Self::MSR.read() })
+    }
+
+    // ## Safety
+    // The system call entry point has to expect the RFLAGS it runs with.
+    #[inline]
+    pub unsafe fn write(flags: RFlags) {
+        unsafe { Self::MSR.write(flags.bits()) };
+    }
+}
+
+impl ApicBase {
+    const BASE_MASK: u64 = 0x_000F_FFFF_FFFF_F000;
+
+    // Returns the frame of the memory mapped local APIC registers and the flags.
+    #[inline]
+    pub fn read() -> (Frame, ApicBaseFlags) {
+        let value = unsafe { Self::MSR.read() };
+        (
+            Frame::new(PhysicalAddress::new(value & Self::BASE_MASK)),
+            ApicBaseFlags::from_bits_truncate(value),
+        )
+    }
+
+    // ## Safety
+    // The CPU has to have a local APIC. Moving or disabling it breaks interrupt delivery.
+    #[inline]
+    pub unsafe fn write(frame: Frame, flags: ApicBaseFlags) {
+        let reserved =
+            unsafe { Self::MSR.read() } & !(Self::BASE_MASK | ApicBaseFlags::all().bits());
+        unsafe { Self::MSR.write(reserved | frame.start_address().address() | flags.bits()) };
+    }
+}
+
+impl TscAux {
+    // ## Safety
+    // The CPU has to support rdtscp.
+    #[inline]
+    pub unsafe fn read() -> u32 {
+        unsafe { Self::MSR.read() as u32 }
+    }
+
+    // ## Safety
+    // The CPU has to support rdtscp.
+    #[inline]
+    pub unsafe fn write(value: u32) {
+        unsafe { Self::MSR.write(value as u64) };
+    }
+}
+
+#[test_case]
+fn test_efer_long_mode_is_active() {
+    let flags = Efer::read();
+    assert!(flags.contains(EferFlags::LONG_MODE_ENABLE | EferFlags::LONG_MODE_ACTIVE));
+}
+
+#[test_case]
+fn test_kernel_gs_base_round_trip() {
+    let previous = KernelGsBase::read();
+    let address = VirtualAddress::new(0x_ffff_8000_1234_5000);
+
+    unsafe { KernelGsBase::write(address) };
+    assert_eq!(KernelGsBase::read(), address);
+    unsafe { KernelGsBase::write(previous) };
+}
+
+#[test_case]
+fn test_star_checks_selector_layout() {
+    let selector = |index: u16, ring: KernelRings| SegmentSelector::new(index, ring);
+
+    assert_eq!(
+        unsafe {
+            Star::write(
+                selector(4, KernelRings::Ring3),
+                selector(4, KernelRings::Ring3),
+                selector(1, KernelRings::Ring0),
+                selector(2, KernelRings::Ring0),
+            )
+        },
+        Err(StarError::InvalidUserSelectors)
+    );
+    assert_eq!(
+        unsafe {
+            Star::write(
+                selector(4, KernelRings::Ring3),
+                selector(3, KernelRings::Ring3),
+                selector(1, KernelRings::Ring0),
+                selector(3, KernelRings::Ring0),
+            )
+        },
+        Err(StarError::InvalidKernelSelectors)
+    );
+}
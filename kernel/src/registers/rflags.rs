@@ -0,0 +1,62 @@
+use bitflags::bitflags;
+use core::arch::asm;
+
+// RFLAGS holds the status flags of the last arithmetic instruction and flags that control the
+// execution of the current task, like the interrupt flag.
+#[derive(Debug)]
+pub struct RFLAGS;
+
+// https://wiki.osdev.org/CPU_Registers_x86-64#RFLAGS_Register
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct RFlags: u64 {
+        const CARRY_FLAG = 1;
+        const PARITY_FLAG = 1 << 2;
+        const AUXILIARY_CARRY_FLAG = 1 << 4;
+        const ZERO_FLAG = 1 << 6;
+        const SIGN_FLAG = 1 << 7;
+
+        // Raises a debug exception after every instruction
+        const TRAP_FLAG = 1 << 8;
+
+        // Maskable interrupts are only delivered while this is set
+        const INTERRUPT_FLAG = 1 << 9;
+
+        const DIRECTION_FLAG = 1 << 10;
+        const OVERFLOW_FLAG = 1 << 11;
+        const IOPL_LOW = 1 << 12;
+        const IOPL_HIGH = 1 << 13;
+        const NESTED_TASK = 1 << 14;
+        const RESUME_FLAG = 1 << 16;
+        const VIRTUAL_8086_MODE = 1 << 17;
+
+        // Allows the kernel to access user accessible pages while CR4.SMAP is set
+        const ALIGNMENT_CHECK = 1 << 18;
+
+        const VIRTUAL_INTERRUPT_FLAG = 1 << 19;
+        const VIRTUAL_INTERRUPT_PENDING = 1 << 20;
+        const ID = 1 << 21;
+    }
+}
+
+impl RFLAGS {
+    #[inline]
+    pub fn read() -> RFlags {
+        RFlags::from_bits_truncate(Self::read_raw())
+    }
+
+    #[inline]
+    pub fn read_raw() -> u64 {
+        let value: u64;
+        unsafe {
+            asm!(
+                "pushfq",
+                "pop {}",
+                out(reg) value,
+                options(nomem, preserves_flags)
+            );
+        }
+
+        value
+    }
+}
@@ -0,0 +1,137 @@
+// Support for multiple processors. The firmware only starts the bootstrap processor (BSP). The other
+// CPUs, called application processors (APs), wait until the BSP sends them an INIT IPI followed by
+// startup IPIs, which run the trampoline. The APs are listed in the MADT. More info can be found at
+// https://wiki.osdev.org/SMP.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::acpi;
+use crate::acpi::pm_timer;
+use crate::interrupts;
+use crate::interrupts::apic::{local_apic, IpiDeliveryMode, LocalApic};
+use crate::memory::frame_allocator;
+use crate::memory::protection;
+use crate::memory::stack::{KernelStack, StackError};
+use crate::smp::trampoline::{Trampoline, TrampolineError};
+
+pub mod percpu;
+pub mod trampoline;
+
+// The stack every AP starts on.
+const AP_STACK_PAGES: u64 = 16;
+
+// The delays of the INIT-SIPI-SIPI sequence, and how long an AP gets to report in.
+const INIT_DELAY_US: u64 = 10_000;
+const STARTUP_DELAY_US: u64 = 200;
+const AP_STARTUP_TIMEOUT_US: u64 = 100_000;
+const AP_STARTUP_POLL_US: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SmpError {
+    // There is no MADT listing the CPUs.
+    NoMadt,
+
+    // The local APIC of the BSP is not in use.
+    NoLocalApic,
+
+    // There is no frame in the first megabyte for the trampoline.
+    NoStartupFrame,
+
+    Trampoline(TrampolineError),
+    Stack(StackError),
+
+    // The AP with the given local APIC ID did not report in.
+    Timeout(u32),
+}
+
+// The number of CPUs that are running, starting with the BSP.
+static CPUS_ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+// Set by an AP once it no longer uses the trampoline.
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub fn cpus_online() -> usize {
+    CPUS_ONLINE.load(Ordering::Acquire)
+}
+
+// Starts every usable AP listed in the MADT, one after the other, and returns the number of CPUs
+// online. APs that do not start are skipped. The ACPI tables, memory and interrupts need to be
+// initialized on the BSP.
+pub fn init() -> Result<usize, SmpError> {
+    let madt = acpi::madt().ok_or(SmpError::NoMadt)?;
+    let local_apic = local_apic().ok_or(SmpError::NoLocalApic)?;
+    let startup_frame = frame_allocator()
+        .startup_frame()
+        .ok_or(SmpError::NoStartupFrame)?;
+
+    let bsp_apic_id = local_apic.id();
+    let mut trampoline =
+        unsafe { Trampoline::install(startup_frame) }.map_err(SmpError::Trampoline)?;
+
+    let application_processors = madt
+        .usable_processors()
+        .filter(|processor| processor.apic_id != bsp_apic_id);
+    for processor in application_processors {
+        let cpu_index = cpus_online();
+        if let Err(error) = start_ap(&mut trampoline, local_apic, processor.apic_id, cpu_index) {
+            log::info!(
+                "CPU with APIC ID {} did not start: {:?}",
+                processor.apic_id,
+                error
+            );
+        }
+    }
+
+    // An AP that timed out may still run the trampoline later, in which case it faults.
+    unsafe { trampoline.remove() };
+    Ok(cpus_online())
+}
+
+// Starts the AP with the given local APIC ID and waits until it reports in.
+fn start_ap(
+    trampoline: &mut Trampoline,
+    local_apic: &LocalApic,
+    apic_id: u32,
+    cpu_index: usize,
+) -> Result<(), SmpError> {
+    // KernelStack does not unmap the stack when dropped, so it stays in use by the AP.
+    let stack = KernelStack::allocate(AP_STACK_PAGES).map_err(SmpError::Stack)?;
+    trampoline.prepare(stack.top(), ap_main, cpu_index as u64);
+    AP_STARTED.store(false, Ordering::Release);
+
+    // The second startup IPI is only needed if the first one got lost.
+    local_apic.send_ipi(apic_id, IpiDeliveryMode::Init, 0);
+    pm_timer::delay_us(INIT_DELAY_US);
+    for _ in 0..2 {
+        local_apic.send_ipi(apic_id, IpiDeliveryMode::StartUp, trampoline.vector());
+        pm_timer::delay_us(STARTUP_DELAY_US);
+        if AP_STARTED.load(Ordering::Acquire) {
+            return Ok(());
+        }
+    }
+
+    for _ in 0..AP_STARTUP_TIMEOUT_US / AP_STARTUP_POLL_US {
+        if AP_STARTED.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        pm_timer::delay_us(AP_STARTUP_POLL_US);
+    }
+
+    Err(SmpError::Timeout(apic_id))
+}
+
+// The entry point of the APs, called by the trampoline on the stack prepared by the BSP.
+extern "C" fn ap_main(cpu_index: u64) -> ! {
+    interrupts::init_application_processor(cpu_index as usize);
+    protection::enable_pcids();
+
+    let apic_id = local_apic().map_or(0, |local_apic| local_apic.id());
+    log::info!("CPU {} is online, APIC ID {}", cpu_index, apic_id);
+
+    CPUS_ONLINE.fetch_add(1, Ordering::AcqRel);
+    AP_STARTED.store(true, Ordering::Release);
+
+    interrupts::enable_hardware_interrupts();
+    crate::hlt();
+}
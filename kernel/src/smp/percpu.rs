@@ -0,0 +1,249 @@
+// Data every CPU keeps for itself, such as its GDT and TSS. Each CPU allocates a block on the heap
+// and points its GS base at it, so the block of the running CPU is found without knowing which
+// CPU that is. Assembly reaches the fields with GS relative addressing, e.g. gs:[SCRATCH_OFFSET].
+//
+// While the kernel runs, GS_BASE holds the block. Code entering the kernel from user mode has to
+// swapgs first, which exchanges GS_BASE with KERNEL_GS_BASE, and swapgs again before returning.
+
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::mem::offset_of;
+
+use crate::interrupts::tss::TaskStateSegment;
+use crate::interrupts::{self, GdtContainer, DOUBLE_FAULT_IST_INDEX, DOUBLE_FAULT_STACK_PAGES};
+use crate::memory::stack::KernelStack;
+use crate::memory::vaddr::VirtualAddress;
+use crate::registers::model_specific::GsBase;
+
+// The ID of the bootstrap processor. Application processors are numbered in the order they start.
+pub const BSP_CPU_ID: usize = 0;
+
+// The number of scratch slots, e.g. to save a register where no stack can be used.
+pub const SCRATCH_SLOTS: usize = 4;
+
+// The offset of the first scratch slot in the block, for GS relative addressing.
+pub const SCRATCH_OFFSET: usize = offset_of!(PerCpu, scratch);
+
+// The ID of the task running on a CPU that has no task yet.
+pub const IDLE_TASK: u64 = 0;
+
+// The block is only accessed by the CPU it belongs to, so the fields do not need to be atomic.
+// Interrupts can still interleave with accesses, but they leave the fields as they found them.
+#[repr(C)]
+pub struct PerCpu {
+    scratch: [Cell<u64>; SCRATCH_SLOTS],
+    cpu_id: usize,
+    current_task: Cell<u64>,
+
+    // The number of interrupt handlers running on the CPU, i.e. how deeply they are nested.
+    interrupt_depth: Cell<u64>,
+
+    // The stack the double fault handler runs on, referenced by the TSS.
+    double_fault_stack: KernelStack,
+    tss: TaskStateSegment,
+    gdt: GdtContainer,
+}
+
+impl PerCpu {
+    #[inline]
+    pub fn cpu_id(&self) -> usize {
+        self.cpu_id
+    }
+
+    #[inline]
+    pub fn current_task(&self) -> u64 {
+        self.current_task.get()
+    }
+
+    #[inline]
+    pub fn set_current_task(&self, task: u64) {
+        self.current_task.set(task);
+    }
+
+    #[inline]
+    pub fn interrupt_depth(&self) -> u64 {
+        self.interrupt_depth.get()
+    }
+
+    #[inline]
+    pub fn in_interrupt(&self) -> bool {
+        self.interrupt_depth() > 0
+    }
+
+    // Marks the CPU as handling an interrupt until the returned guard is dropped. Interrupt
+    // handlers call this first.
+    #[inline]
+    pub fn enter_interrupt(&self) -> InterruptGuard<'_> {
+        self.interrupt_depth.set(self.interrupt_depth.get() + 1);
+        InterruptGuard { cpu: self }
+    }
+
+    #[inline]
+    pub fn scratch(&self, slot: usize) -> u64 {
+        self.scratch[slot].get()
+    }
+
+    #[inline]
+    pub fn set_scratch(&self, slot: usize, value: u64) {
+        self.scratch[slot].set(value);
+    }
+
+    #[inline]
+    pub fn double_fault_stack(&self) -> &KernelStack {
+        &self.double_fault_stack
+    }
+}
+
+// Lowers the interrupt depth of the CPU when dropped.
+pub struct InterruptGuard<'a> {
+    cpu: &'a PerCpu,
+}
+
+impl Drop for InterruptGuard<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        let depth = &self.cpu.interrupt_depth;
+        depth.set(depth.get() - 1);
+    }
+}
+
+// Sets up the block of the calling CPU: allocates its double fault stack, loads its GDT and TSS
+// and points GS_BASE at it. Every CPU calls this before interrupts are enabled. Memory needs to be
+// initialized.
+//
+// Calling this again on a CPU reuses its block and only reloads the GDT. The TSS stays loaded.
+pub fn init(cpu_id: usize) -> &'static PerCpu {
+    if let Some(cpu) = try_this_cpu() {
+        assert_eq!(cpu.cpu_id, cpu_id, "The CPU already has a different ID");
+        interrupts::reload_gdt(&cpu.gdt);
+        set_gs_base(cpu);
+        return cpu;
+    }
+
+    let double_fault_stack = KernelStack::allocate(DOUBLE_FAULT_STACK_PAGES)
+        .expect("Failed to allocate the double fault stack");
+    let mut tss = TaskStateSegment::new();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack.top();
+
+    // The GDT references the TSS next to it, so the block is filled in place. It is used by the
+    // CPU for as long as the kernel runs.
+    let block = Box::leak(Box::<PerCpu>::new_uninit()).as_mut_ptr();
+    let cpu: &'static PerCpu = unsafe {
+        (&raw mut (*block).tss).write(tss);
+        let tss: &'static TaskStateSegment = &(*block).tss;
+        (&raw mut (*block).gdt).write(GdtContainer::new(tss));
+        (&raw mut (*block).scratch).write(Default::default());
+        (&raw mut (*block).cpu_id).write(cpu_id);
+        (&raw mut (*block).current_task).write(Cell::new(IDLE_TASK));
+        (&raw mut (*block).interrupt_depth).write(Cell::new(0));
+        (&raw mut (*block).double_fault_stack).write(double_fault_stack);
+        &*block
+    };
+
+    interrupts::load_gdt(&cpu.gdt);
+    set_gs_base(cpu);
+    cpu
+}
+
+// Points GS_BASE at the block. Loading the GS selector clears the GS base, so this comes after the
+// GDT is loaded.
+#[inline]
+fn set_gs_base(cpu: &'static PerCpu) {
+    unsafe { GsBase::write(VirtualAddress::new(cpu as *const PerCpu as u64)) };
+}
+
+// Returns the block of the calling CPU, or None if init was not called on it yet.
+#[inline]
+pub fn try_this_cpu() -> Option<&'static PerCpu> {
+    let address = GsBase::read().address();
+    if address == 0 {
+        return None;
+    }
+
+    // The GS base is only pointed at a block by init, and blocks are never freed.
+    Some(unsafe { &*(address as *const PerCpu) })
+}
+
+// Returns the block of the calling CPU. Panics if init was not called on it yet.
+#[inline]
+pub fn this_cpu() -> &'static PerCpu {
+    try_this_cpu().expect("Per-CPU data is not set up")
+}
+
+#[test_case]
+fn test_this_cpu_is_reachable_through_gs() {
+    let cpu = this_cpu();
+    assert_eq!(cpu.cpu_id(), BSP_CPU_ID);
+    assert!(!cpu.in_interrupt());
+
+    // A GS relative load sees the value written through the block.
+    cpu.set_scratch(0, 0x_dead_beef);
+    let value: u64;
+    unsafe {
+        core::arch::asm!(
+            "mov {}, gs:[{offset}]",
+            out(reg) value,
+            offset = const SCRATCH_OFFSET,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
+    assert_eq!(value, 0x_dead_beef);
+
+    let depth = {
+        let _guard = cpu.enter_interrupt();
+        cpu.interrupt_depth()
+    };
+    assert_eq!(depth, 1);
+    assert_eq!(cpu.interrupt_depth(), 0);
+}
+
+#[test_case]
+fn test_init_reuses_the_block() {
+    let cpu = this_cpu();
+    assert!(core::ptr::eq(init(BSP_CPU_ID), cpu));
+    assert!(core::ptr::eq(this_cpu(), cpu));
+}
+
+#[test_case]
+fn test_this_cpu_is_reachable_in_interrupt_handlers() {
+    use crate::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
+    use crate::interrupts::ExceptionStackFrame;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use lazy_static::lazy_static;
+
+    // The block and the interrupt depth seen by the breakpoint handler.
+    static BREAKPOINT_CPU: AtomicU64 = AtomicU64::new(0);
+    static BREAKPOINT_DEPTH: AtomicU64 = AtomicU64::new(0);
+
+    lazy_static! {
+        static ref IDT: InterruptDescriptorTable = {
+            let mut idt = InterruptDescriptorTable::new();
+            idt.add_interrupt_handler(
+                IdtIndex::BreakpointInterruptIndex,
+                crate::handler!(breakpoint_handler),
+            );
+            idt
+        };
+    }
+
+    extern "C" fn breakpoint_handler(_stack_frame: &ExceptionStackFrame) {
+        let cpu = this_cpu();
+        let _guard = cpu.enter_interrupt();
+        BREAKPOINT_CPU.store(cpu as *const PerCpu as u64, Ordering::SeqCst);
+        BREAKPOINT_DEPTH.store(cpu.interrupt_depth(), Ordering::SeqCst);
+    }
+
+    IDT.load();
+    unsafe { core::arch::asm!("int3", options(nomem, nostack)) };
+
+    let cpu = this_cpu();
+    assert_eq!(
+        BREAKPOINT_CPU.load(Ordering::SeqCst),
+        cpu as *const PerCpu as u64
+    );
+    assert_eq!(BREAKPOINT_DEPTH.load(Ordering::SeqCst), 1);
+    assert!(!cpu.in_interrupt());
+
+    // Later tests expect the handlers of the kernel.
+    crate::interrupts::testonly_idt_init();
+}
@@ -0,0 +1,252 @@
+// The startup code of application processors. A CPU started by a startup IPI runs in real mode at
+// the start of the frame given by the IPI vector, so the code is copied to a frame in the first
+// megabyte. It enters long mode directly from real mode: PAE, the kernel page tables and EFER.LME
+// are set up first, then protection and paging are enabled at once. More info can be found at
+// https://wiki.osdev.org/Entering_Long_Mode_Directly.
+//
+// While the CPU enables paging, it runs at the physical address of the frame. The frame is thus
+// identity mapped in the kernel page tables as long as the trampoline is installed.
+
+use core::arch::global_asm;
+use core::mem::size_of;
+
+use crate::memory::frame::Frame;
+use crate::memory::page::Page;
+use crate::memory::page_table::PageTableFlags;
+use crate::memory::paging::MappingError;
+use crate::memory::vaddr::VirtualAddress;
+use crate::memory::{frame_allocator, paging};
+use crate::registers::control::{CR3Flags, CR4Flags, CR0, CR3, CR4};
+use crate::registers::model_specific::{Efer, EferFlags};
+
+// The selector of the 64 bit code segment in the GDT of the trampoline.
+const TRAMPOLINE_CODE_SELECTOR: u16 = 0x08;
+
+// The end of the memory addressable in real mode.
+const REAL_MODE_MEMORY_END: u64 = 0x10_0000;
+
+// CR3 is loaded with a 32 bit value in real mode.
+const REAL_MODE_CR3_LIMIT: u64 = 1 << 32;
+
+// The data the startup code needs. Its layout is mirrored by the offsets in the assembly below.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct TrampolineData {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+
+    // The stack and entry point of the CPU, and the argument passed to the entry point.
+    stack_top: u64,
+    entry: u64,
+    argument: u64,
+
+    // The pointer to the GDT of the trampoline, for lgdt with 16 bit operand size.
+    gdt_limit: u16,
+    gdt_base: u32,
+
+    // The far pointer to the 64 bit code of the trampoline.
+    long_mode_offset: u32,
+    long_mode_selector: u16,
+}
+
+global_asm!(
+    r#"
+.section .rodata.ap_trampoline, "a"
+.global ap_trampoline_start
+.global ap_trampoline_long_mode
+.global ap_trampoline_gdt
+.global ap_trampoline_data
+.global ap_trampoline_end
+
+.code16
+.set TRAMPOLINE_DATA, ap_trampoline_data - ap_trampoline_start
+ap_trampoline_start:
+    cli
+    cld
+
+    // CS points to the start of the frame.
+    mov ax, cs
+    mov ds, ax
+
+    mov eax, dword ptr [TRAMPOLINE_DATA + 16]
+    mov cr4, eax
+    mov eax, dword ptr [TRAMPOLINE_DATA + 8]
+    mov cr3, eax
+    mov ecx, 0xC0000080
+    mov eax, dword ptr [TRAMPOLINE_DATA + 24]
+    xor edx, edx
+    wrmsr
+
+    lgdt [TRAMPOLINE_DATA + 56]
+    mov eax, dword ptr [TRAMPOLINE_DATA]
+    mov cr0, eax
+    jmp fword ptr [TRAMPOLINE_DATA + 62]
+
+.code64
+ap_trampoline_long_mode:
+    xor eax, eax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+
+    mov rsp, qword ptr [rip + ap_trampoline_data + 32]
+    mov rdi, qword ptr [rip + ap_trampoline_data + 48]
+    mov rax, qword ptr [rip + ap_trampoline_data + 40]
+    call rax
+    ud2
+
+// A null descriptor, a 64 bit code segment and a data segment.
+.align 8
+ap_trampoline_gdt:
+    .quad 0
+    .quad 0x00AF9A000000FFFF
+    .quad 0x00CF92000000FFFF
+
+.align 8
+ap_trampoline_data:
+    .skip 68
+ap_trampoline_end:
+.previous
+"#
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_long_mode: u8;
+    static ap_trampoline_gdt: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+// Returns the offset of a trampoline symbol from the start of the trampoline.
+#[inline]
+fn trampoline_offset(symbol: *const u8) -> u64 {
+    symbol as u64 - (&raw const ap_trampoline_start) as u64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrampolineError {
+    // The frame can't be reached in real mode.
+    FrameNotInRealModeMemory,
+
+    // The kernel page tables can't be loaded in real mode.
+    PageTablesNotInRealModeRange,
+
+    // The frame could not be identity mapped.
+    Mapping(MappingError),
+}
+
+// The startup code, installed in a frame in the first megabyte.
+#[derive(Debug)]
+pub struct Trampoline {
+    frame: Frame,
+    data: *mut TrampolineData,
+}
+
+impl Trampoline {
+    // Copies the startup code to frame and identity maps it. The CPUs started by it use the page
+    // tables and the control registers of the calling CPU.
+    //
+    // ## Safety
+    // The frame needs to be unused, and there must be no mapping at its physical address.
+    pub unsafe fn install(frame: Frame) -> Result<Trampoline, TrampolineError> {
+        let paddr = frame.start_address().address();
+        if paddr + frame.size() > REAL_MODE_MEMORY_END {
+            return Err(TrampolineError::FrameNotInRealModeMemory);
+        }
+
+        // With PCIDs enabled, the lower bits of CR3 hold the PCID instead of the flags. The
+        // application processors start without PCIDs.
+        let (page_table_frame, mut page_table_flags) = CR3::read();
+        if CR4::read().contains(CR4Flags::PCID) {
+            page_table_flags = CR3Flags::empty();
+        }
+        let cr3 = page_table_frame.start_address().address() | page_table_flags.bits();
+        if cr3 >= REAL_MODE_CR3_LIMIT {
+            return Err(TrampolineError::PageTablesNotInRealModeRange);
+        }
+
+        let mut paging = paging();
+        let page: Page = Page::new(VirtualAddress::new(paddr));
+        paging
+            .map_to(
+                page,
+                frame,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                &mut *frame_allocator(),
+            )
+            .map_err(TrampolineError::Mapping)?;
+
+        let size = trampoline_offset(&raw const ap_trampoline_end);
+        let start = (paddr + paging.paddr_offset()) as *mut u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(&raw const ap_trampoline_start, start, size as usize)
+        };
+
+        // PCIDs can only be enabled in long mode, and the CPU switches to long mode on its own.
+        let cr4 = CR4::read() - CR4Flags::PCID;
+        let efer = Efer::read() - EferFlags::LONG_MODE_ACTIVE;
+
+        let data = unsafe { start.add(trampoline_offset(&raw const ap_trampoline_data) as usize) }
+            as *mut TrampolineData;
+        unsafe {
+            data.write_unaligned(TrampolineData {
+                cr0: CR0::read().bits(),
+                cr3,
+                cr4: cr4.bits(),
+                efer: efer.bits(),
+                stack_top: 0,
+                entry: 0,
+                argument: 0,
+                gdt_limit: (3 * size_of::<u64>() - 1) as u16,
+                gdt_base: (paddr + trampoline_offset(&raw const ap_trampoline_gdt)) as u32,
+                long_mode_offset: (paddr + trampoline_offset(&raw const ap_trampoline_long_mode))
+                    as u32,
+                long_mode_selector: TRAMPOLINE_CODE_SELECTOR,
+            })
+        };
+
+        Ok(Trampoline { frame, data })
+    }
+
+    // The vector of the startup IPI that runs the trampoline.
+    #[inline]
+    pub fn vector(&self) -> u8 {
+        (self.frame.start_address().address() >> 12) as u8
+    }
+
+    // Sets the stack and the entry point of the next CPU to start. The entry point is called with
+    // argument as its first parameter.
+    pub fn prepare(
+        &mut self,
+        stack_top: VirtualAddress,
+        entry: extern "C" fn(u64) -> !,
+        argument: u64,
+    ) {
+        let mut data = unsafe { self.data.read_unaligned() };
+        data.stack_top = stack_top.address();
+        data.entry = entry as usize as u64;
+        data.argument = argument;
+        unsafe { self.data.write_unaligned(data) };
+    }
+
+    // Removes the identity mapping of the frame. The frame stays reserved for the next startup.
+    //
+    // ## Safety
+    // No CPU may run the trampoline anymore.
+    pub unsafe fn remove(self) {
+        let page: Page = Page::new(VirtualAddress::new(self.frame.start_address().address()));
+        paging().unmap(page).expect("Trampoline page is not mapped");
+    }
+}
+
+#[test_case]
+fn test_trampoline_data_layout() {
+    // The assembly reserves space for the data at its end.
+    let size = trampoline_offset(&raw const ap_trampoline_end)
+        - trampoline_offset(&raw const ap_trampoline_data);
+    assert_eq!(size, size_of::<TrampolineData>() as u64);
+    assert!(trampoline_offset(&raw const ap_trampoline_end) <= 4096);
+}
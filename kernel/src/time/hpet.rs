@@ -0,0 +1,295 @@
+// Support for the high precision event timer (HPET). The HPET has a main counter running at a fixed
+// frequency of at least 10 MHz, and a number of comparators. A comparator raises an interrupt when
+// the main counter reaches its value, once or periodically. The HPET is described by the ACPI HPET
+// table. More info can be found at https://wiki.osdev.org/HPET.
+
+use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
+use core::hint::spin_loop;
+
+use crate::acpi;
+use crate::acpi::sdt::AddressSpace;
+use crate::memory::mmio::{map_mmio, MmioError};
+use crate::memory::paddr::PhysicalAddress;
+use crate::memory::vaddr::VirtualAddress;
+
+// Register offsets in the memory mapped register block. Every comparator has a block of registers
+// of its own, starting at REGISTER_COMPARATOR_BASE.
+const REGISTER_CAPABILITIES: u64 = 0x000;
+const REGISTER_CONFIGURATION: u64 = 0x010;
+const REGISTER_MAIN_COUNTER: u64 = 0x0F0;
+const REGISTER_COMPARATOR_BASE: u64 = 0x100;
+const REGISTER_COMPARATOR_STRIDE: u64 = 0x20;
+const REGISTER_COMPARATOR_CONFIGURATION: u64 = 0x00;
+const REGISTER_COMPARATOR_VALUE: u64 = 0x08;
+const REGISTER_BLOCK_SIZE: u64 = 0x400;
+
+// The main counter only runs while this bit of the configuration register is set.
+const CONFIGURATION_ENABLE: u64 = 1;
+
+// The comparator sends its interrupt to the I/O APIC input given in bits 9-13 of its configuration
+// register. The inputs it can use are reported as bit mask in the upper 32 bits.
+const COMPARATOR_ROUTE_SHIFT: u64 = 9;
+const COMPARATOR_ROUTE_MASK: u64 = 0x1F << COMPARATOR_ROUTE_SHIFT;
+const COMPARATOR_ROUTES_SHIFT: u64 = 32;
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+const MICROSECONDS_PER_SECOND: u64 = 1_000_000;
+
+// https://wiki.osdev.org/HPET#Timer_N_Configuration_and_Capability_Register
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    struct ComparatorFlags: u64 {
+        const LEVEL_TRIGGERED = 1 << 1;
+        const INTERRUPT_ENABLE = 1 << 2;
+        const PERIODIC = 1 << 3;
+        const PERIODIC_CAPABLE = 1 << 4;
+
+        // Makes the next write to the value register of a periodic comparator set the value it
+        // fires at, and the following write the period.
+        const SET_VALUE = 1 << 6;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComparatorMode {
+    // The comparator fires once.
+    OneShot,
+
+    // The comparator fires every given number of counter ticks.
+    Periodic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HpetError {
+    // The firmware did not describe an HPET.
+    NotPresent,
+
+    // The registers are not memory mapped.
+    UnsupportedAddressSpace,
+
+    Mmio(MmioError),
+
+    // The HPET has no comparator with the given index.
+    InvalidComparator(u8),
+
+    // The comparator can't fire periodically.
+    PeriodicNotSupported,
+
+    // The comparator can't be connected to the global system interrupt.
+    InvalidRoute(u32),
+}
+
+#[derive(Debug)]
+pub struct Hpet {
+    base: VirtualAddress,
+
+    // The period of the main counter in femtoseconds.
+    period_fs: u64,
+    comparators: u8,
+}
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+
+// Returns the HPET, or None if it was not set up.
+#[inline]
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+// Maps the registers of the HPET described by the ACPI tables and starts its main counter. All
+// comparators are stopped.
+pub fn init() -> Result<&'static Hpet, HpetError> {
+    if let Some(hpet) = HPET.get() {
+        return Ok(hpet);
+    }
+
+    let table = acpi::tables()
+        .and_then(|tables| tables.hpet)
+        .ok_or(HpetError::NotPresent)?;
+    if table.address.address_space != AddressSpace::SystemMemory {
+        return Err(HpetError::UnsupportedAddressSpace);
+    }
+
+    let hpet = Hpet::new(PhysicalAddress::new(table.address.address))?;
+    Ok(HPET.get_or_init(|| hpet))
+}
+
+impl Hpet {
+    fn new(paddr: PhysicalAddress) -> Result<Hpet, HpetError> {
+        let base = map_mmio(paddr, REGISTER_BLOCK_SIZE).map_err(HpetError::Mmio)?;
+
+        let mut hpet = Hpet {
+            base,
+            period_fs: 0,
+            comparators: 0,
+        };
+
+        // The period is reported in the upper 32 bits, the index of the last comparator in bits
+        // 8-12. The period is never 0.
+        let capabilities = unsafe { hpet.read(REGISTER_CAPABILITIES) };
+        hpet.period_fs = (capabilities >> 32).max(1);
+        hpet.comparators = ((capabilities >> 8) & 0x1F) as u8 + 1;
+
+        for comparator in 0..hpet.comparators {
+            hpet.stop_comparator(comparator);
+        }
+
+        unsafe {
+            let configuration = hpet.read(REGISTER_CONFIGURATION);
+            hpet.write(REGISTER_CONFIGURATION, configuration | CONFIGURATION_ENABLE);
+        }
+
+        Ok(hpet)
+    }
+
+    // The frequency of the main counter in Hz.
+    #[inline]
+    pub fn frequency(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period_fs
+    }
+
+    #[inline]
+    pub fn comparators(&self) -> u8 {
+        self.comparators
+    }
+
+    #[inline]
+    pub fn counter(&self) -> u64 {
+        unsafe { self.read(REGISTER_MAIN_COUNTER) }
+    }
+
+    // Busy waits for about us microseconds.
+    pub fn delay_us(&self, us: u64) {
+        let ticks = self.us_to_ticks(us);
+        let start = self.counter();
+        while self.counter().wrapping_sub(start) < ticks {
+            spin_loop();
+        }
+    }
+
+    // The number of counter ticks in us microseconds. Saturates instead of overflowing.
+    #[inline]
+    fn us_to_ticks(&self, us: u64) -> u64 {
+        let ticks = us as u128 * self.frequency() as u128 / MICROSECONDS_PER_SECOND as u128;
+        ticks.min(u64::MAX as u128) as u64
+    }
+
+    // Returns the global system interrupts the comparator can be connected to, as bit mask.
+    #[inline]
+    pub fn routes(&self, comparator: u8) -> Result<u32, HpetError> {
+        let configuration = unsafe { self.read(self.comparator_register(comparator)?) };
+        Ok((configuration >> COMPARATOR_ROUTES_SHIFT) as u32)
+    }
+
+    // Makes the comparator raise the global system interrupt gsi after ticks counter ticks, once or
+    // periodically. The interrupt is edge triggered.
+    //
+    // ## Safety
+    // The global system interrupt needs to be routed to a vector whose handler expects it.
+    pub unsafe fn start_comparator(
+        &self,
+        comparator: u8,
+        mode: ComparatorMode,
+        ticks: u64,
+        gsi: u32,
+    ) -> Result<(), HpetError> {
+        let configuration_register = self.comparator_register(comparator)?;
+        let value_register = configuration_register + REGISTER_COMPARATOR_VALUE;
+        if gsi >= 32 || self.routes(comparator)? & (1 << gsi) == 0 {
+            return Err(HpetError::InvalidRoute(gsi));
+        }
+
+        let configuration = unsafe { self.read(configuration_register) };
+        let capabilities = ComparatorFlags::from_bits_truncate(configuration);
+        if mode == ComparatorMode::Periodic
+            && !capabilities.contains(ComparatorFlags::PERIODIC_CAPABLE)
+        {
+            return Err(HpetError::PeriodicNotSupported);
+        }
+
+        let mut flags = ComparatorFlags::INTERRUPT_ENABLE;
+        if mode == ComparatorMode::Periodic {
+            flags |= ComparatorFlags::PERIODIC | ComparatorFlags::SET_VALUE;
+        }
+        let cleared = ComparatorFlags::LEVEL_TRIGGERED
+            | ComparatorFlags::INTERRUPT_ENABLE
+            | ComparatorFlags::PERIODIC
+            | ComparatorFlags::SET_VALUE;
+        let configuration = (configuration & !(COMPARATOR_ROUTE_MASK | cleared.bits()))
+            | flags.bits()
+            | (gsi as u64) << COMPARATOR_ROUTE_SHIFT;
+
+        // A periodic comparator takes the first value it fires at, then the period. The main
+        // counter is halted meanwhile, so that it does not pass the first value.
+        unsafe {
+            let main_configuration = self.read(REGISTER_CONFIGURATION);
+            self.write(
+                REGISTER_CONFIGURATION,
+                main_configuration & !CONFIGURATION_ENABLE,
+            );
+
+            self.write(configuration_register, configuration);
+            self.write(value_register, self.counter() + ticks);
+            if mode == ComparatorMode::Periodic {
+                self.write(value_register, ticks);
+            }
+
+            self.write(
+                REGISTER_CONFIGURATION,
+                main_configuration | CONFIGURATION_ENABLE,
+            );
+        }
+
+        Ok(())
+    }
+
+    // Stops the comparator from raising interrupts. Does nothing for comparators that do not exist.
+    pub fn stop_comparator(&self, comparator: u8) {
+        if let Ok(register) = self.comparator_register(comparator) {
+            unsafe {
+                let configuration = self.read(register);
+                let flags = ComparatorFlags::INTERRUPT_ENABLE | ComparatorFlags::PERIODIC;
+                self.write(register, configuration & !flags.bits());
+            }
+        }
+    }
+
+    #[inline]
+    fn comparator_register(&self, comparator: u8) -> Result<u64, HpetError> {
+        if comparator >= self.comparators {
+            return Err(HpetError::InvalidComparator(comparator));
+        }
+
+        Ok(REGISTER_COMPARATOR_BASE
+            + comparator as u64 * REGISTER_COMPARATOR_STRIDE
+            + REGISTER_COMPARATOR_CONFIGURATION)
+    }
+
+    // ## Safety
+    // The register needs to exist and be readable.
+    #[inline]
+    unsafe fn read(&self, register: u64) -> u64 {
+        unsafe { ((self.base.address() + register) as *const u64).read_volatile() }
+    }
+
+    // ## Safety
+    // The register needs to exist and be writable. Writes can change how interrupts are raised.
+    #[inline]
+    unsafe fn write(&self, register: u64, value: u64) {
+        unsafe { ((self.base.address() + register) as *mut u64).write_volatile(value) }
+    }
+}
+
+#[test_case]
+fn test_us_to_ticks_does_not_overflow() {
+    // A counter running at 100 MHz.
+    let hpet = Hpet {
+        base: VirtualAddress::zero(),
+        period_fs: 10_000_000,
+        comparators: 0,
+    };
+    assert_eq!(hpet.us_to_ticks(1), 100);
+    assert_eq!(hpet.us_to_ticks(u64::MAX / 100), u64::MAX / 100 * 100);
+    assert_eq!(hpet.us_to_ticks(u64::MAX), u64::MAX);
+}
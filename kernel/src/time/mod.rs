@@ -0,0 +1,511 @@
+// Timekeeping. A clock source raises the timer interrupt at a fixed frequency, and the interrupt
+// handler counts the ticks. The tick count is monotonic and starts when the PIT is programmed.
+// Instants are read from the TSC, which has nanosecond resolution once it is calibrated. The
+// calendar time is read from the RTC once, and advanced by the uptime from then on.
+//
+// The PIT, a comparator of the HPET or the local APIC timer of the bootstrap processor can be the
+// clock source. All of them raise the interrupt on IdtIndex::TimerInterruptIndex.
+
+use core::hint::spin_loop;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use spin::Mutex;
+
+use crate::acpi;
+use crate::acpi::pm_timer;
+use crate::interrupts;
+use crate::interrupts::apic::{local_apic, TimerDivide, TimerMode};
+use crate::interrupts::idt::IdtIndex;
+use crate::interrupts::instructions::{are_interrupts_enabled, run_without_interrupts};
+use crate::interrupts::ioapic::RedirectionFlags;
+use crate::time::hpet::{hpet, ComparatorMode, HpetError};
+use crate::time::pit::Pit;
+use crate::time::rtc::{DateTime, Rtc, RtcInterrupts};
+
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod tsc;
+
+// The frequency of the timer interrupt in Hz, i.e. a tick per millisecond.
+pub const DEFAULT_TIMER_FREQUENCY: u32 = 1000;
+
+// The number of ticks the busy wait loop is calibrated over, and how long calibration waits for a
+// tick before it gives up.
+const CALIBRATION_TICKS: u64 = 10;
+const CALIBRATION_TIMEOUT_SPINS: u64 = 1 << 28;
+
+// How long the local APIC timer is measured for.
+const APIC_TIMER_CALIBRATION_US: u64 = 10_000;
+
+// The comparator of the HPET used as clock source.
+const HPET_CLOCK_COMPARATOR: u8 = 0;
+
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+const MICROSECONDS_PER_SECOND: u64 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum ClockSource {
+    Pit,
+    Hpet,
+    ApicTimer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClockError {
+    Hpet(HpetError),
+
+    // None of the interrupts the HPET comparator can raise is handled by an I/O APIC.
+    NoHpetRoute,
+
+    // The local APIC is not in use.
+    NoLocalApic,
+
+    // There is no timer to measure the frequency of the local APIC timer against.
+    NoReferenceTimer,
+}
+
+static PIT: Mutex<Pit> = Mutex::new(Pit::new());
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// The frequency of the timer interrupt, or 0 if the PIT was not programmed yet.
+static TIMER_FREQUENCY: AtomicU32 = AtomicU32::new(0);
+
+static CLOCK_SOURCE: AtomicU8 = AtomicU8::new(ClockSource::Pit as u8);
+
+static RTC: Mutex<Rtc> = Mutex::new(Rtc::new());
+
+// The interrupts the RTC raised, by cause.
+static RTC_PERIODIC_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static RTC_ALARMS: AtomicU64 = AtomicU64::new(0);
+
+// The calendar time read from the RTC, and the uptime when it was read.
+static WALL_CLOCK: OnceCell<WallClock> = OnceCell::uninit();
+
+struct WallClock {
+    since_epoch: Duration,
+    uptime: Duration,
+}
+
+// The iterations of the busy wait loop per tick, or 0 if the loop was not calibrated yet.
+static SPINS_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
+// Makes the clock source raise the timer interrupt at about frequency_hz and calibrates the busy
+// wait loop and the TSC against it. The PIT is used if the clock source can't be set up. The timer
+// IRQ needs to be routed and interrupts enabled, see interrupts::init. The HPET is taken from the
+// ACPI tables.
+pub fn init(source: ClockSource, frequency_hz: u32) {
+    let pit_frequency = unsafe { PIT.lock().set_frequency(frequency_hz) };
+    TIMER_FREQUENCY.store(pit_frequency, Ordering::Release);
+
+    match hpet::init() {
+        Ok(hpet) => log::info!("HPET runs at {} Hz", hpet.frequency()),
+        Err(HpetError::NotPresent) => {}
+        Err(error) => log::info!("HPET could not be set up: {:?}", error),
+    }
+
+    let started = match source {
+        ClockSource::Pit => Ok(pit_frequency),
+        ClockSource::Hpet => start_hpet(frequency_hz),
+        ClockSource::ApicTimer => start_apic_timer(frequency_hz),
+    };
+    match started {
+        Ok(frequency_hz) => {
+            // The other clock sources raise the same interrupt, so the PIT has to be silenced.
+            if source != ClockSource::Pit {
+                unsafe { PIT.lock().stop() };
+            }
+
+            TIMER_FREQUENCY.store(frequency_hz, Ordering::Release);
+            CLOCK_SOURCE.store(source as u8, Ordering::Release);
+            log::info!("Clock source: {:?} at {} Hz", source, frequency_hz);
+        }
+        Err(error) => log::info!(
+            "Clock source {:?} could not be started, using the PIT at {} Hz: {:?}",
+            source,
+            pit_frequency,
+            error
+        ),
+    }
+
+    match calibrate() {
+        Some(spins_per_tick) => {
+            SPINS_PER_TICK.store(spins_per_tick, Ordering::Release);
+            log::info!("Busy wait loop: {} iterations per tick", spins_per_tick);
+        }
+        None => log::info!("The timer interrupt did not arrive, busy waits use the PM timer"),
+    }
+
+    tsc::init();
+
+    // The RTC is set to UTC. The FADT tells where the century is kept.
+    let century_register = acpi::fadt().map_or(0, |fadt| fadt.century_register);
+    let date_time = run_without_interrupts(|| RTC.lock().read(century_register));
+    WALL_CLOCK.init_once(|| WallClock {
+        since_epoch: Duration::from_secs(date_time.unix_timestamp()),
+        uptime: uptime(),
+    });
+    log::info!("RTC time: {} UTC", date_time);
+}
+
+// The time since the Unix epoch, 1970-01-01 00:00:00 UTC, or None if the RTC was not read yet. It
+// advances with the uptime, so it has the resolution of a tick.
+#[inline]
+pub fn now() -> Option<Duration> {
+    let wall_clock = WALL_CLOCK.get()?;
+    Some(wall_clock.since_epoch + uptime().saturating_sub(wall_clock.uptime))
+}
+
+// The calendar date and time in UTC, or None if the RTC was not read yet.
+#[inline]
+pub fn date_time() -> Option<DateTime> {
+    now().map(|now| DateTime::from_unix_timestamp(now.as_secs()))
+}
+
+// Makes the RTC raise its interrupt at 32768 Hz >> (rate - 1), see Rtc::enable_periodic. Returns
+// the frequency.
+#[inline]
+pub fn enable_rtc_periodic(rate: u8) -> u32 {
+    run_without_interrupts(|| unsafe { RTC.lock().enable_periodic(rate) })
+}
+
+#[inline]
+pub fn disable_rtc_periodic() {
+    run_without_interrupts(|| RTC.lock().disable_periodic());
+}
+
+// Makes the RTC raise its interrupt when its time of day reaches the one of date_time.
+#[inline]
+pub fn set_rtc_alarm(date_time: DateTime) {
+    run_without_interrupts(|| unsafe {
+        RTC.lock()
+            .set_alarm(date_time.hour, date_time.minute, date_time.second)
+    });
+}
+
+#[inline]
+pub fn clear_rtc_alarm() {
+    run_without_interrupts(|| RTC.lock().clear_alarm());
+}
+
+#[inline]
+pub fn rtc_periodic_interrupts() -> u64 {
+    RTC_PERIODIC_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+#[inline]
+pub fn rtc_alarms() -> u64 {
+    RTC_ALARMS.load(Ordering::Relaxed)
+}
+
+// Called by the RTC interrupt handler. Acknowledges the interrupt, otherwise the RTC raises no
+// further ones.
+#[inline]
+pub(crate) fn rtc_interrupt() {
+    let interrupts = RTC.lock().acknowledge();
+    if interrupts.contains(RtcInterrupts::PERIODIC) {
+        RTC_PERIODIC_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    }
+    if interrupts.contains(RtcInterrupts::ALARM) {
+        RTC_ALARMS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// The clock source raising the timer interrupt.
+#[inline]
+pub fn clock_source() -> ClockSource {
+    match CLOCK_SOURCE.load(Ordering::Acquire) {
+        source if source == ClockSource::Hpet as u8 => ClockSource::Hpet,
+        source if source == ClockSource::ApicTimer as u8 => ClockSource::ApicTimer,
+        _ => ClockSource::Pit,
+    }
+}
+
+// Makes the HPET comparator raise the timer interrupt at about frequency_hz. Returns the frequency
+// it runs at.
+fn start_hpet(frequency_hz: u32) -> Result<u32, ClockError> {
+    let hpet = hpet().ok_or(ClockError::Hpet(HpetError::NotPresent))?;
+    let routes = hpet
+        .routes(HPET_CLOCK_COMPARATOR)
+        .map_err(ClockError::Hpet)?;
+
+    // The interrupt of the PIT is tried first, the comparator replaces the PIT on it. The
+    // comparator raises edge triggered, active high interrupts.
+    let timer_gsi = interrupts::timer_gsi();
+    let gsi = core::iter::once(timer_gsi)
+        .chain((0..32).filter(|&gsi| gsi != timer_gsi))
+        .filter(|&gsi| gsi < 32 && routes & (1 << gsi) != 0)
+        .find(|&gsi| unsafe {
+            interrupts::route_gsi(
+                gsi,
+                IdtIndex::TimerInterruptIndex,
+                RedirectionFlags::empty(),
+            )
+        })
+        .ok_or(ClockError::NoHpetRoute)?;
+
+    // The PIT stays the clock source, so the interrupt is handed back if the comparator fails.
+    let ticks = (hpet.frequency() / frequency_hz.max(1) as u64).max(1);
+    let started = unsafe {
+        hpet.start_comparator(HPET_CLOCK_COMPARATOR, ComparatorMode::Periodic, ticks, gsi)
+    };
+    if let Err(error) = started {
+        interrupts::unroute_gsi(gsi);
+        return Err(ClockError::Hpet(error));
+    }
+
+    Ok((hpet.frequency() / ticks) as u32)
+}
+
+// Makes the local APIC timer of the calling CPU raise the timer interrupt at about frequency_hz.
+// Its frequency is measured against the HPET or the ACPI PM timer first. Returns the frequency it
+// runs at.
+fn start_apic_timer(frequency_hz: u32) -> Result<u32, ClockError> {
+    let local_apic = local_apic().ok_or(ClockError::NoLocalApic)?;
+    if hpet().is_none() && !pm_timer::is_available() {
+        return Err(ClockError::NoReferenceTimer);
+    }
+
+    // The timer counts down once, from a count it can't reach zero from while it is measured.
+    local_apic.start_timer(
+        IdtIndex::ApicTimerInterruptIndex,
+        TimerMode::OneShot,
+        TimerDivide::By16,
+        u32::MAX,
+    );
+    match hpet() {
+        Some(hpet) => hpet.delay_us(APIC_TIMER_CALIBRATION_US),
+        None => pm_timer::delay_us(APIC_TIMER_CALIBRATION_US),
+    }
+    let counted = (u32::MAX - local_apic.timer_current_count()) as u64;
+    local_apic.stop_timer();
+
+    let counts_per_second = counted * MICROSECONDS_PER_SECOND / APIC_TIMER_CALIBRATION_US;
+    let initial_count = (counts_per_second / frequency_hz.max(1) as u64).clamp(1, u32::MAX as u64);
+    local_apic.start_timer(
+        IdtIndex::TimerInterruptIndex,
+        TimerMode::Periodic,
+        TimerDivide::By16,
+        initial_count as u32,
+    );
+
+    Ok((counts_per_second / initial_count) as u32)
+}
+
+// Returns true if the timer interrupt arrived while the busy wait loop was calibrated.
+#[inline]
+pub fn is_ticking() -> bool {
+    SPINS_PER_TICK.load(Ordering::Acquire) != 0
+}
+
+// Counts a tick. Called by the timer interrupt handler.
+#[inline]
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+// The number of timer interrupts since the PIT was programmed.
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// The frequency of the timer interrupt in Hz, or None if the PIT was not programmed yet.
+#[inline]
+pub fn frequency() -> Option<u32> {
+    match TIMER_FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        frequency_hz => Some(frequency_hz),
+    }
+}
+
+// The time since the PIT was programmed, with the resolution of a tick.
+#[inline]
+pub fn uptime() -> Duration {
+    let frequency_hz = match frequency() {
+        Some(frequency_hz) => frequency_hz as u128,
+        None => return Duration::ZERO,
+    };
+
+    let nanoseconds = ticks() as u128 * NANOSECONDS_PER_SECOND as u128 / frequency_hz;
+    Duration::from_nanos(nanoseconds as u64)
+}
+
+// Halts until count ticks have passed. Interrupts need to be enabled, otherwise the CPU never wakes
+// up.
+pub fn sleep_ticks(count: u64) {
+    assert!(
+        are_interrupts_enabled(),
+        "Sleeping with interrupts disabled"
+    );
+
+    let end = ticks() + count;
+    while ticks() < end {
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack, preserves_flags)) };
+    }
+}
+
+// Busy waits for about us microseconds, e.g. where interrupts are disabled. Before the loop is
+// calibrated, the ACPI PM timer is polled instead.
+pub fn delay_us(us: u64) {
+    let spins_per_tick = SPINS_PER_TICK.load(Ordering::Acquire);
+    let frequency_hz = TIMER_FREQUENCY.load(Ordering::Acquire) as u64;
+    if spins_per_tick == 0 || frequency_hz == 0 {
+        pm_timer::delay_us(us);
+        return;
+    }
+
+    let spins = us * spins_per_tick * frequency_hz / MICROSECONDS_PER_SECOND;
+    for _ in 0..spins {
+        spin();
+    }
+}
+
+// A single iteration of the busy wait loop. Calibration runs the same code, so it is never inlined.
+#[inline(never)]
+fn spin() -> u64 {
+    spin_loop();
+    ticks()
+}
+
+// Counts the iterations of the busy wait loop over CALIBRATION_TICKS ticks. Returns None if the
+// timer interrupt does not arrive.
+fn calibrate() -> Option<u64> {
+    if !are_interrupts_enabled() {
+        return None;
+    }
+
+    // Start right after a tick, so that whole ticks are measured.
+    let start = wait_for_tick(ticks())?;
+    let mut spins: u64 = 0;
+    let mut now = start;
+    while now < start + CALIBRATION_TICKS {
+        now = spin();
+        spins += 1;
+        if spins > CALIBRATION_TICKS * CALIBRATION_TIMEOUT_SPINS {
+            return None;
+        }
+    }
+
+    Some((spins / CALIBRATION_TICKS).max(1))
+}
+
+// Spins until the tick count changes from last and returns the new count.
+#[inline]
+fn wait_for_tick(last: u64) -> Option<u64> {
+    for _ in 0..CALIBRATION_TIMEOUT_SPINS {
+        let now = spin();
+        if now != last {
+            return Some(now);
+        }
+    }
+
+    None
+}
+
+// A point in time measured by the TSC, with nanosecond resolution. Instants are monotonic on a CPU
+// with an invariant TSC. Durations between instants are zero until the TSC is calibrated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    cycles: u64,
+}
+
+impl Instant {
+    #[inline]
+    pub fn now() -> Instant {
+        Instant {
+            cycles: tsc::read(),
+        }
+    }
+
+    // Returns the time passed since earlier, or zero if earlier is later than self.
+    #[inline]
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    // Returns the time passed since earlier, or None if earlier is later than self.
+    #[inline]
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        let cycles = self.cycles.checked_sub(earlier.cycles)?;
+        let nanoseconds = tsc::frequency().map_or(0, |frequency_hz| {
+            tsc::cycles_to_nanoseconds(cycles, frequency_hz)
+        });
+        Some(Duration::from_nanos(nanoseconds))
+    }
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    #[inline]
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let cycles = duration_to_cycles(duration)?;
+        self.cycles
+            .checked_add(cycles)
+            .map(|cycles| Instant { cycles })
+    }
+
+    #[inline]
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let cycles = duration_to_cycles(duration)?;
+        self.cycles
+            .checked_sub(cycles)
+            .map(|cycles| Instant { cycles })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    // Panics if the result overflows.
+    #[inline]
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("Overflow when adding a duration to an instant")
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    // Panics if the result underflows.
+    #[inline]
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("Overflow when subtracting a duration from an instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    #[inline]
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+// Converts a duration to TSC cycles. Returns None if the TSC is not calibrated or the duration
+// does not fit.
+#[inline]
+fn duration_to_cycles(duration: Duration) -> Option<u64> {
+    let nanoseconds = u64::try_from(duration.as_nanos()).ok()?;
+    Some(tsc::nanoseconds_to_cycles(nanoseconds, tsc::frequency()?))
+}
+
+#[test_case]
+fn test_instant_is_monotonic() {
+    let start = Instant::now();
+    let end = Instant::now();
+    assert!(end >= start);
+    assert_eq!(start.duration_since(end), Duration::ZERO);
+    assert_eq!(start.checked_duration_since(end), None);
+    assert!(end.checked_duration_since(start).is_some());
+}
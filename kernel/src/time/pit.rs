@@ -0,0 +1,116 @@
+// Support for the Intel 8253/8254 programmable interval timer (PIT). Its channel 0 is connected to
+// ISA IRQ 0 and raises it periodically. The PIT counts down from a divisor at a fixed input
+// frequency, and raises the IRQ every time the count reaches zero. More info can be found at
+// https://wiki.osdev.org/Programmable_Interval_Timer.
+
+use x86_64::instructions::port::Port;
+
+// The input frequency of the PIT in Hz.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0_PORT: u16 = 0x40;
+const COMMAND_PORT: u16 = 0x43;
+
+// The fields of the mode/command register. Channel 0 is programmed as rate generator (mode 2), with
+// the divisor written low byte first. The latch command freezes the count, so it can be read. Mode
+// 0 is used to stop the channel.
+const SELECT_CHANNEL_0: u8 = 0b00 << 6;
+const ACCESS_LOW_HIGH_BYTE: u8 = 0b11 << 4;
+const ACCESS_LATCH_COUNT: u8 = 0b00 << 4;
+const MODE_INTERRUPT_ON_TERMINAL_COUNT: u8 = 0b000 << 1;
+const MODE_RATE_GENERATOR: u8 = 0b010 << 1;
+
+// The divisor is 16 bit wide, where 0 stands for 65536.
+const MAX_DIVISOR: u32 = 0x1_0000;
+
+pub struct Pit {
+    channel_0: Port<u8>,
+    command: Port<u8>,
+}
+
+impl Pit {
+    // This function needs to be const as it is used to create the Pit instance in a static
+    // expression.
+    #[inline]
+    pub const fn new() -> Pit {
+        Pit {
+            channel_0: Port::new(CHANNEL_0_PORT),
+            command: Port::new(COMMAND_PORT),
+        }
+    }
+
+    // Makes channel 0 raise IRQ 0 at about frequency_hz and returns the frequency it runs at, which
+    // differs as the PIT frequency is divided by an integer. The frequency is clamped to what the
+    // PIT supports, about 18.2 Hz to 1.19 MHz.
+    //
+    // ## Safety
+    // The timer interrupt handler has to expect the new frequency.
+    pub unsafe fn set_frequency(&mut self, frequency_hz: u32) -> u32 {
+        let divisor = divisor(frequency_hz);
+        unsafe {
+            self.command
+                .write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH_BYTE | MODE_RATE_GENERATOR);
+            self.channel_0.write(divisor as u8);
+            self.channel_0.write((divisor >> 8) as u8);
+        }
+
+        frequency(divisor)
+    }
+
+    // Stops channel 0. Writing the mode stops the count until a divisor is written, and keeps the
+    // IRQ line low.
+    //
+    // ## Safety
+    // The tick count stops.
+    #[inline]
+    pub unsafe fn stop(&mut self) {
+        unsafe {
+            self.command
+                .write(SELECT_CHANNEL_0 | ACCESS_LOW_HIGH_BYTE | MODE_INTERRUPT_ON_TERMINAL_COUNT)
+        };
+    }
+
+    // Returns the current count of channel 0, which counts down from the divisor.
+    #[inline]
+    pub fn count(&mut self) -> u16 {
+        unsafe {
+            self.command.write(SELECT_CHANNEL_0 | ACCESS_LATCH_COUNT);
+            let low = self.channel_0.read();
+            let high = self.channel_0.read();
+            u16::from_le_bytes([low, high])
+        }
+    }
+}
+
+impl Default for Pit {
+    #[inline]
+    fn default() -> Pit {
+        Pit::new()
+    }
+}
+
+// Returns the divisor that gets closest to frequency_hz. A divisor of 65536 is written as 0.
+#[inline]
+fn divisor(frequency_hz: u32) -> u32 {
+    let frequency_hz = frequency_hz.max(1);
+    ((PIT_FREQUENCY + frequency_hz / 2) / frequency_hz).clamp(1, MAX_DIVISOR)
+}
+
+// Returns the frequency channel 0 runs at with the given divisor, rounded to the closest Hz.
+#[inline]
+fn frequency(divisor: u32) -> u32 {
+    (PIT_FREQUENCY + divisor / 2) / divisor
+}
+
+#[test_case]
+fn test_divisor() {
+    assert_eq!(divisor(1000), 1193);
+    assert_eq!(frequency(divisor(1000)), 1000);
+    assert_eq!(frequency(divisor(100)), 100);
+
+    // Frequencies out of range are clamped.
+    assert_eq!(divisor(1), MAX_DIVISOR);
+    assert_eq!(divisor(0), MAX_DIVISOR);
+    assert_eq!(divisor(PIT_FREQUENCY * 2), 1);
+    assert_eq!(MAX_DIVISOR as u16, 0);
+}
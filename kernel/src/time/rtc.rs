@@ -0,0 +1,410 @@
+// Support for the real-time clock (RTC) in the CMOS of PC compatible machines. The RTC keeps the
+// calendar date and time while the machine is off. Its registers are read through an index and a
+// data port, and hold the date as BCD or binary, with the hour in 12 or 24 hour format, depending
+// on status register B. The RTC raises ISA IRQ 8 periodically, when an alarm time is reached and
+// after every update. More info can be found at https://wiki.osdev.org/CMOS.
+
+use bitflags::bitflags;
+use core::fmt;
+use core::hint::spin_loop;
+use x86_64::instructions::port::Port;
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+// Setting the top bit of the index disables NMIs, so it is kept clear.
+const INDEX_MASK: u8 = 0x7F;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_ALARM_SECONDS: u8 = 0x01;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_ALARM_MINUTES: u8 = 0x03;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_ALARM_HOURS: u8 = 0x05;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+const REGISTER_STATUS_C: u8 = 0x0C;
+
+// Status register A: the RTC is updating the date and time while the update in progress bit is
+// set, and the low 4 bits select the periodic interrupt rate.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0F;
+
+// Bit 7 of the hour register is set for PM times in 12 hour format.
+const HOUR_PM: u8 = 1 << 7;
+
+// The periodic interrupt fires at 32768 Hz >> (rate - 1). Rates 1 and 2 do not work reliably.
+const BASE_FREQUENCY: u32 = 32768;
+pub const MIN_RATE: u8 = 3;
+pub const MAX_RATE: u8 = 15;
+
+// How often the date and time are read until two reads agree, as an update can happen between
+// them.
+const MAX_READ_ATTEMPTS: usize = 8;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+// Status register B.
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    struct StatusB: u8 {
+        const HOUR_24 = 1 << 1;
+        const BINARY = 1 << 2;
+        const UPDATE_ENDED_INTERRUPT = 1 << 4;
+        const ALARM_INTERRUPT = 1 << 5;
+        const PERIODIC_INTERRUPT = 1 << 6;
+    }
+}
+
+// The causes of an RTC interrupt, reported in status register C.
+bitflags! {
+    #[derive(Debug, Clone, Copy, Ord, Eq, PartialEq, PartialOrd, Hash)]
+    pub struct RtcInterrupts: u8 {
+        const UPDATE_ENDED = 1 << 4;
+        const ALARM = 1 << 5;
+        const PERIODIC = 1 << 6;
+    }
+}
+
+// A calendar date and time in UTC, as the RTC of a PC is expected to be set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    // The seconds since the Unix epoch, 1970-01-01 00:00:00. Dates before the epoch are not
+    // supported and return 0.
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        if days < 0 {
+            return 0;
+        }
+
+        days as u64 * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    pub fn from_unix_timestamp(timestamp: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((timestamp / SECONDS_PER_DAY) as i64);
+        let seconds = timestamp % SECONDS_PER_DAY;
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub struct Rtc {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Rtc {
+    // This function needs to be const as it is used to create the Rtc instance in a static
+    // expression.
+    #[inline]
+    pub const fn new() -> Rtc {
+        Rtc {
+            index: Port::new(INDEX_PORT),
+            data: Port::new(DATA_PORT),
+        }
+    }
+
+    // Reads the date and time. The century is read from the CMOS RAM index century_register, as
+    // given by the FADT. If it is 0, years 70-99 are taken as 19xx and all others as 20xx.
+    pub fn read(&mut self, century_register: u8) -> DateTime {
+        // An update changes the registers one by one, so the date is read while no update is in
+        // progress, until two reads in a row agree.
+        let mut date_time = self.read_raw(century_register);
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let again = self.read_raw(century_register);
+            if again == date_time {
+                break;
+            }
+            date_time = again;
+        }
+
+        let status = StatusB::from_bits_truncate(self.read_register(REGISTER_STATUS_B));
+        let decode = |value: u8| match status.contains(StatusB::BINARY) {
+            true => value,
+            false => bcd_to_binary(value),
+        };
+
+        let hour = decode_hour(date_time.hour, status);
+        let year = decode(date_time.year) as u16;
+        let year = match century_register {
+            0 if year >= 70 => 1900 + year,
+            0 => 2000 + year,
+            _ => decode(date_time.century) as u16 * 100 + year,
+        };
+
+        DateTime {
+            year,
+            month: decode(date_time.month),
+            day: decode(date_time.day),
+            hour,
+            minute: decode(date_time.minute),
+            second: decode(date_time.second),
+        }
+    }
+
+    // Makes the RTC raise IRQ 8 periodically and returns the frequency in Hz, which is 32768 Hz >>
+    // (rate - 1). The rate is clamped to MIN_RATE..=MAX_RATE, i.e. 8192 Hz down to 2 Hz.
+    //
+    // ## Safety
+    // The handler of IRQ 8 needs to acknowledge the interrupts, see acknowledge.
+    pub unsafe fn enable_periodic(&mut self, rate: u8) -> u32 {
+        let rate = rate.clamp(MIN_RATE, MAX_RATE);
+        let status_a = self.read_register(REGISTER_STATUS_A);
+        self.write_register(REGISTER_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+        self.update_status_b(StatusB::PERIODIC_INTERRUPT, true);
+
+        BASE_FREQUENCY >> (rate - 1)
+    }
+
+    #[inline]
+    pub fn disable_periodic(&mut self) {
+        self.update_status_b(StatusB::PERIODIC_INTERRUPT, false);
+    }
+
+    // Makes the RTC raise IRQ 8 once a day, when the time reaches hour:minute:second.
+    //
+    // ## Safety
+    // The handler of IRQ 8 needs to acknowledge the interrupts, see acknowledge.
+    pub unsafe fn set_alarm(&mut self, hour: u8, minute: u8, second: u8) {
+        let status = StatusB::from_bits_truncate(self.read_register(REGISTER_STATUS_B));
+        let encode = |value: u8| match status.contains(StatusB::BINARY) {
+            true => value,
+            false => binary_to_bcd(value),
+        };
+
+        self.write_register(REGISTER_ALARM_SECONDS, encode(second));
+        self.write_register(REGISTER_ALARM_MINUTES, encode(minute));
+        self.write_register(REGISTER_ALARM_HOURS, encode_hour(hour, status));
+        self.update_status_b(StatusB::ALARM_INTERRUPT, true);
+    }
+
+    #[inline]
+    pub fn clear_alarm(&mut self) {
+        self.update_status_b(StatusB::ALARM_INTERRUPT, false);
+    }
+
+    // Returns the causes of the pending interrupt. The RTC raises no further interrupts until this
+    // is called.
+    #[inline]
+    pub fn acknowledge(&mut self) -> RtcInterrupts {
+        RtcInterrupts::from_bits_truncate(self.read_register(REGISTER_STATUS_C))
+    }
+
+    // Reads the date and time registers as they are, once no update is in progress.
+    fn read_raw(&mut self, century_register: u8) -> RawDateTime {
+        while self.read_register(REGISTER_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+            spin_loop();
+        }
+
+        RawDateTime {
+            second: self.read_register(REGISTER_SECONDS),
+            minute: self.read_register(REGISTER_MINUTES),
+            hour: self.read_register(REGISTER_HOURS),
+            day: self.read_register(REGISTER_DAY),
+            month: self.read_register(REGISTER_MONTH),
+            year: self.read_register(REGISTER_YEAR),
+            century: match century_register {
+                0 => 0,
+                register => self.read_register(register),
+            },
+        }
+    }
+
+    #[inline]
+    fn update_status_b(&mut self, flags: StatusB, enabled: bool) {
+        let mut status = StatusB::from_bits_retain(self.read_register(REGISTER_STATUS_B));
+        status.set(flags, enabled);
+        self.write_register(REGISTER_STATUS_B, status.bits());
+    }
+
+    #[inline]
+    fn read_register(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register & INDEX_MASK);
+            self.data.read()
+        }
+    }
+
+    #[inline]
+    fn write_register(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register & INDEX_MASK);
+            self.data.write(value);
+        }
+    }
+}
+
+impl Default for Rtc {
+    #[inline]
+    fn default() -> Rtc {
+        Rtc::new()
+    }
+}
+
+// The registers of the date and time before they are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawDateTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+#[inline]
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+#[inline]
+fn binary_to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+// Converts an hour register to 0-23. In 12 hour format, 12 AM is midnight and 12 PM is noon.
+#[inline]
+fn decode_hour(value: u8, status: StatusB) -> u8 {
+    let pm = value & HOUR_PM != 0;
+    let value = value & !HOUR_PM;
+    let hour = match status.contains(StatusB::BINARY) {
+        true => value,
+        false => bcd_to_binary(value),
+    };
+
+    match (status.contains(StatusB::HOUR_24), pm) {
+        (true, _) => hour,
+        (false, false) => hour % 12,
+        (false, true) => hour % 12 + 12,
+    }
+}
+
+// Converts an hour of 0-23 to the format of the hour registers.
+#[inline]
+fn encode_hour(hour: u8, status: StatusB) -> u8 {
+    let (value, pm) = match status.contains(StatusB::HOUR_24) {
+        true => (hour, false),
+        false if hour.is_multiple_of(12) => (12, hour >= 12),
+        false => (hour % 12, hour >= 12),
+    };
+
+    let value = match status.contains(StatusB::BINARY) {
+        true => value,
+        false => binary_to_bcd(value),
+    };
+
+    match pm {
+        true => value | HOUR_PM,
+        false => value,
+    }
+}
+
+// The days since 1970-01-01 of a date in the proleptic Gregorian calendar. See
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_of_year = (month + 9) % 12;
+    let day_of_year = (153 * month_of_year + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+// The date of a number of days since 1970-01-01, the inverse of days_from_civil.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_of_year = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_of_year + 2) / 5 + 1;
+    let month = if month_of_year < 10 {
+        month_of_year + 3
+    } else {
+        month_of_year - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[test_case]
+fn test_date_time_conversion() {
+    assert_eq!(bcd_to_binary(0x59), 59);
+    assert_eq!(binary_to_bcd(59), 0x59);
+
+    // 12 hour format: 12 AM is midnight, 12 PM is noon.
+    let bcd_12_hour = StatusB::empty();
+    assert_eq!(decode_hour(0x12, bcd_12_hour), 0);
+    assert_eq!(decode_hour(0x12 | HOUR_PM, bcd_12_hour), 12);
+    assert_eq!(decode_hour(0x11 | HOUR_PM, bcd_12_hour), 23);
+    assert_eq!(decode_hour(23, StatusB::BINARY | StatusB::HOUR_24), 23);
+    for hour in 0..24 {
+        assert_eq!(
+            decode_hour(encode_hour(hour, bcd_12_hour), bcd_12_hour),
+            hour
+        );
+    }
+
+    let epoch = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    assert_eq!(epoch.unix_timestamp(), 0);
+    assert_eq!(DateTime::from_unix_timestamp(0), epoch);
+
+    // 2000 is a leap year, 2100 is not.
+    let date_time = DateTime {
+        year: 2000,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 59,
+        second: 58,
+    };
+    assert_eq!(date_time.unix_timestamp(), 951_868_798);
+    assert_eq!(DateTime::from_unix_timestamp(951_868_798), date_time);
+    assert_eq!(DateTime::from_unix_timestamp(4_107_542_400).month, 3);
+    assert_eq!(DateTime::from_unix_timestamp(4_107_542_400).day, 1);
+}
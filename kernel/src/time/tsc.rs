@@ -0,0 +1,157 @@
+// The time stamp counter (TSC) counts CPU cycles since reset and is read with a single instruction,
+// which makes it the clock with the highest resolution. Its frequency is not reported reliably, so
+// it is measured against the HPET, the timer interrupt or the ACPI PM timer, whichever is there. On
+// older CPUs the TSC follows the CPU frequency, so it only measures time if it is invariant. More
+// info can be found at https://wiki.osdev.org/TSC.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::acpi::pm_timer;
+use crate::interrupts::instructions::are_interrupts_enabled;
+use crate::registers::cpuid::{self, CpuFeatures};
+use crate::time;
+use crate::time::hpet::hpet;
+
+// How long the TSC is measured for.
+const CALIBRATION_MS: u64 = 50;
+
+const MILLISECONDS_PER_SECOND: u64 = 1000;
+const MICROSECONDS_PER_MILLISECOND: u64 = 1000;
+const NANOSECONDS_PER_SECOND: u64 = 1_000_000_000;
+
+// The frequency of the TSC in Hz, or 0 if it was not calibrated yet.
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+// Returns the current value of the TSC.
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// Returns true if the TSC runs at a constant rate, no matter the frequency and power state of the
+// CPU.
+#[inline]
+pub fn is_invariant() -> bool {
+    cpuid::has(CpuFeatures::INVARIANT_TSC)
+}
+
+// The frequency of the TSC in Hz, or None if it was not calibrated yet.
+#[inline]
+pub fn frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Acquire) {
+        0 => None,
+        frequency_hz => Some(frequency_hz),
+    }
+}
+
+// Measures the frequency of the TSC. The clock source needs to be running, see time::init.
+pub fn init() {
+    if !cpuid::has(CpuFeatures::TSC) {
+        log::info!("The CPU has no TSC");
+        return;
+    }
+
+    let frequency_hz = match calibrate_with_hpet()
+        .or_else(calibrate_with_timer)
+        .or_else(calibrate_with_pm_timer)
+    {
+        Some(frequency_hz) => frequency_hz,
+        None => {
+            log::info!("The TSC could not be calibrated");
+            return;
+        }
+    };
+
+    TSC_FREQUENCY.store(frequency_hz, Ordering::Release);
+    log::info!(
+        "TSC runs at {} kHz, invariant: {}",
+        frequency_hz / 1000,
+        is_invariant()
+    );
+}
+
+// Converts a number of TSC cycles to nanoseconds.
+#[inline]
+pub fn cycles_to_nanoseconds(cycles: u64, frequency_hz: u64) -> u64 {
+    (cycles as u128 * NANOSECONDS_PER_SECOND as u128 / frequency_hz as u128) as u64
+}
+
+// Converts nanoseconds to a number of TSC cycles.
+#[inline]
+pub fn nanoseconds_to_cycles(nanoseconds: u64, frequency_hz: u64) -> u64 {
+    (nanoseconds as u128 * frequency_hz as u128 / NANOSECONDS_PER_SECOND as u128) as u64
+}
+
+// Counts the TSC cycles while the main counter of the HPET advances. Returns None if there is no
+// HPET.
+fn calibrate_with_hpet() -> Option<u64> {
+    let hpet = hpet()?;
+
+    let start = read();
+    let start_counter = hpet.counter();
+    hpet.delay_us(CALIBRATION_MS * MICROSECONDS_PER_MILLISECOND);
+    let cycles = read() - start;
+    let counted = hpet.counter() - start_counter;
+
+    Some((cycles as u128 * hpet.frequency() as u128 / counted.max(1) as u128) as u64)
+}
+
+// Counts the TSC cycles over whole timer ticks. Returns None if the timer interrupt does not
+// arrive.
+fn calibrate_with_timer() -> Option<u64> {
+    let timer_frequency = time::frequency()? as u64;
+    if !time::is_ticking() || !are_interrupts_enabled() {
+        return None;
+    }
+
+    let ticks = (CALIBRATION_MS * timer_frequency / MILLISECONDS_PER_SECOND).max(1);
+
+    // Start right after a tick, so that whole ticks are measured.
+    time::sleep_ticks(1);
+    let start_tick = time::ticks();
+    let start = read();
+    time::sleep_ticks(ticks);
+    let cycles = read() - start;
+    let ticks = time::ticks() - start_tick;
+
+    Some(cycles * timer_frequency / ticks)
+}
+
+// Counts the TSC cycles while the ACPI PM timer advances. Returns None if there is no PM timer.
+fn calibrate_with_pm_timer() -> Option<u64> {
+    if !pm_timer::is_available() {
+        return None;
+    }
+
+    let start = read();
+    pm_timer::delay_us(CALIBRATION_MS * MICROSECONDS_PER_MILLISECOND);
+    let cycles = read() - start;
+
+    Some(cycles * MILLISECONDS_PER_SECOND / CALIBRATION_MS)
+}
+
+#[test_case]
+fn test_cycle_conversion() {
+    let frequency_hz = 3_579_545;
+    assert_eq!(
+        cycles_to_nanoseconds(frequency_hz, frequency_hz),
+        1_000_000_000
+    );
+    assert_eq!(
+        nanoseconds_to_cycles(1_000_000_000, frequency_hz),
+        frequency_hz
+    );
+
+    // A few minutes at 4 GHz overflow 64 bit intermediate results.
+    let frequency_hz = 4_000_000_000;
+    let cycles = 600 * frequency_hz;
+    assert_eq!(
+        cycles_to_nanoseconds(cycles, frequency_hz),
+        600 * 1_000_000_000
+    );
+    assert_eq!(
+        nanoseconds_to_cycles(600 * 1_000_000_000, frequency_hz),
+        cycles
+    );
+}
@@ -0,0 +1,159 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::address_space::{self, AddressSpace};
+use kernel::memory::frame_allocator::FrameAllocator;
+use kernel::memory::page::Page;
+use kernel::memory::page_table::PageTableFlags;
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::registers::control::{CR4Flags, CR4};
+use kernel::registers::cpuid::{self, CpuFeatures};
+use kernel::{exit_qemu, memory, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// An address in the lower half that is only mapped in the new address space.
+const TEST_ADDRESS: u64 = 0x_5555_3000_0000;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_address_space...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    // Interrupts look up the GDT and the IDT, so both need to be part of the shared upper half.
+    unsafe { memory::init(&boot_info.memory_regions, physical_memory_offset) };
+    kernel::interrupts::testonly_gdt_init();
+    kernel::interrupts::testonly_idt_init();
+
+    let used_frames = memory::frame_allocator().used_frames();
+
+    let address_space = AddressSpace::new().unwrap();
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let frame = memory::frame_allocator().allocate_frame().unwrap();
+    address_space
+        .paging()
+        .map_to(
+            page,
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            &mut *memory::frame_allocator(),
+        )
+        .unwrap();
+
+    // The kernel keeps running on the new page tables, including its stack and heap.
+    unsafe { address_space.activate() };
+    assert!(address_space.is_active());
+
+    let ptr = page.start_address().address() as *mut u64;
+    unsafe { ptr.write_volatile(0xdead_beef) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 0xdead_beef);
+
+    let boxed = Box::new(0xcafe_u64);
+    assert_eq!(*boxed, 0xcafe);
+
+    // The write went to the frame mapped in the new address space.
+    let frame_ptr = (frame.start_address().address() + physical_memory_offset) as *const u64;
+    assert_eq!(unsafe { frame_ptr.read_volatile() }, 0xdead_beef);
+
+    // Heap allocations made while the address space was active stay valid in the kernel page
+    // tables.
+    unsafe { address_space::activate_kernel_space() };
+    assert!(!address_space.is_active());
+    assert!(memory::paging().translate(page.start_address()).is_none());
+    assert_eq!(*boxed, 0xcafe);
+    drop(boxed);
+
+    drop(address_space);
+    memory::frame_allocator().deallocate_frame(frame);
+    assert_eq!(memory::frame_allocator().used_frames(), used_frames);
+
+    test_switching_between_address_spaces(physical_memory_offset);
+    assert_eq!(memory::frame_allocator().used_frames(), used_frames);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+// Maps TEST_ADDRESS to a frame of its own in each of two address spaces, and checks that each
+// sees its own frame while switching back and forth. With PCIDs, the TLB entries of both address
+// spaces stay cached, so this also checks that changed mappings do not survive in the TLB.
+fn test_switching_between_address_spaces(physical_memory_offset: u64) {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let ptr = page.start_address().address() as *mut u64;
+
+    let first = AddressSpace::new().unwrap();
+    let second = AddressSpace::new().unwrap();
+    if cpuid::has(CpuFeatures::PCID) {
+        assert!(CR4::read().contains(CR4Flags::PCID));
+        assert!(first.pcid().is_some());
+        assert!(second.pcid().is_some());
+        assert_ne!(first.pcid(), second.pcid());
+    }
+
+    let mut frames = [None; 3];
+    for frame in frames.iter_mut() {
+        *frame = memory::frame_allocator().allocate_frame();
+    }
+    let [Some(first_frame), Some(second_frame), Some(third_frame)] = frames else {
+        panic!("Out of frames");
+    };
+    for (address_space, frame) in [(&first, first_frame), (&second, second_frame)] {
+        address_space
+            .paging()
+            .map_to(page, frame, flags, &mut *memory::frame_allocator())
+            .unwrap();
+    }
+
+    unsafe {
+        first.activate();
+        ptr.write_volatile(1);
+        second.activate();
+        ptr.write_volatile(2);
+
+        first.activate();
+        assert_eq!(ptr.read_volatile(), 1);
+        second.activate();
+        assert_eq!(ptr.read_volatile(), 2);
+    }
+
+    // The first address space changes while the second one is active. Its cached translation of
+    // the page must not be used anymore.
+    let third_ptr = (third_frame.start_address().address() + physical_memory_offset) as *mut u64;
+    unsafe { third_ptr.write_volatile(3) };
+    let mut first_paging = first.paging();
+    assert_eq!(first_paging.unmap(page), Ok(first_frame));
+    first_paging
+        .map_to(page, third_frame, flags, &mut *memory::frame_allocator())
+        .unwrap();
+
+    unsafe {
+        first.activate();
+        assert_eq!(ptr.read_volatile(), 3);
+        second.activate();
+        assert_eq!(ptr.read_volatile(), 2);
+        address_space::activate_kernel_space();
+    }
+
+    drop(first);
+    drop(second);
+    for frame in [first_frame, second_frame, third_frame] {
+        memory::frame_allocator().deallocate_frame(frame);
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
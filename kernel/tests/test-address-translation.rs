@@ -1,16 +1,12 @@
 #![no_std]
 #![no_main]
 
-use bootloader_api::{config::Mapping, BootloaderConfig};
+use bootloader_api::BootloaderConfig;
 use core::panic::PanicInfo;
 use kernel::memory::{paging::Paging, vaddr::VirtualAddress};
 use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
 
-pub static BOOTLOADER_CONFIG: BootloaderConfig = {
-    let mut config = BootloaderConfig::new_default();
-    config.mappings.physical_memory = Some(Mapping::Dynamic);
-    config
-};
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
 
 bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
 
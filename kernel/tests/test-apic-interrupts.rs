@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use kernel::interrupts::apic::{local_apic, TimerDivide, TimerMode};
+use kernel::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
+use kernel::interrupts::ExceptionStackFrame;
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// The number of local APIC timer interrupts to wait for an interrupt from the I/O APIC.
+const MAX_APIC_TIMER_TICKS: u64 = 1000;
+
+static PIT_TICKS: AtomicU64 = AtomicU64::new(0);
+static KEYBOARD_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+static APIC_TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.add_interrupt_handler(
+            IdtIndex::TimerInterruptIndex,
+            kernel::handler!(timer_interrupt_handler),
+        );
+        idt.add_interrupt_handler(
+            IdtIndex::KeyboardInterruptIndex,
+            kernel::handler!(keyboard_interrupt_handler),
+        );
+        idt.add_interrupt_handler(
+            IdtIndex::ApicTimerInterruptIndex,
+            kernel::handler!(apic_timer_interrupt_handler),
+        );
+        idt.add_interrupt_handler(
+            IdtIndex::ApicSpuriousInterruptIndex,
+            kernel::handler!(spurious_interrupt_handler),
+        );
+
+        idt
+    };
+}
+
+extern "C" fn timer_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    PIT_TICKS.fetch_add(1, Ordering::Relaxed);
+    local_apic().unwrap().end_of_interrupt();
+}
+
+extern "C" fn keyboard_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    // Reading the scancode empties the output buffer of the keyboard controller.
+    let mut port: Port<u8> = Port::new(0x60);
+    let _scancode: u8 = unsafe { port.read() };
+
+    KEYBOARD_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+    local_apic().unwrap().end_of_interrupt();
+}
+
+extern "C" fn apic_timer_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
+    APIC_TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+    local_apic().unwrap().end_of_interrupt();
+}
+
+extern "C" fn spurious_interrupt_handler(_stack_frame: &ExceptionStackFrame) {}
+
+// Halts until the counter is incremented by an interrupt handler. The periodic local APIC timer
+// wakes the CPU up regularly, so this fails instead of hanging if the interrupt never arrives.
+fn wait_for(counter: &AtomicU64, name: &str) {
+    let start = APIC_TIMER_TICKS.load(Ordering::Relaxed);
+    while counter.load(Ordering::Relaxed) == 0 {
+        if APIC_TIMER_TICKS.load(Ordering::Relaxed) - start > MAX_APIC_TIMER_TICKS {
+            serial_println!("[{} interrupt did not arrive]", name);
+            exit_qemu(QemuExitCode::Failed);
+        }
+
+        unsafe { core::arch::asm!("hlt", options(nomem, nostack, preserves_flags)) };
+    }
+}
+
+// The status register of the keyboard controller tells whether it is ready for the next byte and
+// whether a byte is waiting to be read.
+fn wait_for_keyboard_controller(mask: u8, set: bool) {
+    let mut status: Port<u8> = Port::new(0x64);
+    while (unsafe { status.read() } & mask != 0) != set {}
+}
+
+fn keyboard_controller_command(command: u8) {
+    let mut port: Port<u8> = Port::new(0x64);
+    wait_for_keyboard_controller(0b10, false);
+    unsafe { port.write(command) };
+}
+
+fn keyboard_controller_write(byte: u8) {
+    let mut port: Port<u8> = Port::new(0x60);
+    wait_for_keyboard_controller(0b10, false);
+    unsafe { port.write(byte) };
+}
+
+// Makes sure the keyboard controller raises IRQ 1 for bytes from the keyboard. This needs to happen
+// before the IRQ is routed, as reading the configuration raises the IRQ as well.
+fn enable_keyboard_interrupts() {
+    let mut port: Port<u8> = Port::new(0x60);
+    keyboard_controller_command(0x20);
+    wait_for_keyboard_controller(0b01, true);
+    let configuration = unsafe { port.read() };
+
+    keyboard_controller_command(0x60);
+    keyboard_controller_write(configuration | 1);
+}
+
+// Makes the keyboard controller raise IRQ 1 by placing a byte in its output buffer, as if the
+// keyboard had sent it.
+fn inject_keyboard_byte(byte: u8) {
+    keyboard_controller_command(0xD2);
+    keyboard_controller_write(byte);
+}
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_apic_interrupts...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    // The APIC registers are mapped into the kernel address space.
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    kernel::interrupts::testonly_gdt_init();
+    IDT.load();
+
+    enable_keyboard_interrupts();
+    kernel::interrupts::init_apic().expect("APIC initialization failed");
+    let local_apic = local_apic().unwrap();
+    assert_eq!(
+        unsafe { kernel::interrupts::PICS.lock().read_masks() },
+        [0xFF, 0xFF]
+    );
+
+    local_apic.start_timer(
+        IdtIndex::ApicTimerInterruptIndex,
+        TimerMode::Periodic,
+        TimerDivide::By16,
+        100_000,
+    );
+    kernel::interrupts::enable_hardware_interrupts();
+
+    wait_for(&APIC_TIMER_TICKS, "Local APIC timer");
+    wait_for(&PIT_TICKS, "PIT");
+
+    inject_keyboard_byte(0x1E);
+    wait_for(&KEYBOARD_INTERRUPTS, "Keyboard");
+
+    local_apic.stop_timer();
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::time::{self, ClockSource, Instant};
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_apic_timer...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The APIC timer is measured against the HPET or the PM timer, which are described by the ACPI
+    // tables.
+    let rsdp_address = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("The firmware did not provide ACPI tables");
+    unsafe { kernel::acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+        .expect("ACPI initialization failed");
+
+    kernel::interrupts::init();
+    time::init(ClockSource::ApicTimer, time::DEFAULT_TIMER_FREQUENCY);
+    assert_eq!(time::clock_source(), ClockSource::ApicTimer);
+    assert!(time::is_ticking());
+
+    // The APIC timer raises the timer interrupt. The host may preempt the guest, so there is no
+    // upper bound.
+    let start = Instant::now();
+    let ticks = time::ticks();
+    time::sleep_ticks(20);
+    let elapsed = start.elapsed();
+    assert!(time::ticks() >= ticks + 20);
+    assert!(
+        elapsed >= Duration::from_millis(15),
+        "20 ticks took {:?}",
+        elapsed
+    );
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::address_space::{self, AddressSpace};
+use kernel::memory::cow::frame_refcount;
+use kernel::memory::frame::Frame;
+use kernel::memory::frame_allocator::FrameAllocator;
+use kernel::memory::frame_descriptor::FrameOwner;
+use kernel::memory::page::Page;
+use kernel::memory::page_table::PageTableFlags;
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::{exit_qemu, memory, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// An address in the lower half that is shared between the parent and the child.
+const TEST_ADDRESS: u64 = 0x_5555_5000_0000;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_copy_on_write...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { memory::init(&boot_info.memory_regions, physical_memory_offset) };
+    kernel::interrupts::testonly_gdt_init();
+    kernel::interrupts::testonly_idt_init();
+
+    let parent = AddressSpace::new().unwrap();
+    let page: Page = Page::new(VirtualAddress::new(TEST_ADDRESS));
+    let frame: Frame = memory::frame_allocator().allocate_frame().unwrap();
+    parent
+        .paging()
+        .map_to(
+            page,
+            frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            &mut *memory::frame_allocator(),
+        )
+        .unwrap();
+
+    let ptr = page.start_address().address() as *mut u64;
+    unsafe { parent.activate() };
+    unsafe { ptr.write_volatile(1) };
+
+    let child = parent.fork().unwrap();
+    assert_eq!(frame_refcount(frame), 2);
+
+    // The child sees the memory of the parent. Its first write faults and copies the frame.
+    unsafe { child.activate() };
+    assert_eq!(unsafe { ptr.read_volatile() }, 1);
+    unsafe { ptr.write_volatile(2) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 2);
+
+    let copy = child.paging().translate(page.start_address()).unwrap();
+    assert_ne!(copy, frame.start_address());
+    assert_eq!(frame_refcount(frame), 1);
+
+    // The parent still sees its own data and, as the last owner, writes to its frame in place.
+    unsafe { parent.activate() };
+    assert_eq!(unsafe { ptr.read_volatile() }, 1);
+    unsafe { ptr.write_volatile(3) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 3);
+    assert_eq!(
+        parent.paging().translate(page.start_address()),
+        Some(frame.start_address())
+    );
+
+    // Dropping a child that never wrote releases its reference, so the parent writes in place.
+    let child_to_drop = parent.fork().unwrap();
+    assert_eq!(frame_refcount(frame), 2);
+    drop(child_to_drop);
+    assert_eq!(frame_refcount(frame), 1);
+    unsafe { ptr.write_volatile(4) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 4);
+    assert_eq!(
+        parent.paging().translate(page.start_address()),
+        Some(frame.start_address())
+    );
+
+    // Dropping the child frees its copy of the frame.
+    unsafe { address_space::activate_kernel_space() };
+    drop(child);
+    assert_eq!(
+        memory::frame_allocator()
+            .descriptors()
+            .and_then(|descriptors| descriptors.get(Frame::new(copy)))
+            .map(|descriptor| descriptor.owner()),
+        Some(FrameOwner::Free)
+    );
+
+    drop(parent);
+    memory::frame_allocator().deallocate_frame(frame);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::page::{Page, PAGE_SIZE};
+use kernel::memory::page_table::PageTableFlags;
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::memory::vma::{AreaKind, Backing, VirtualMemoryArea, KERNEL_AREAS};
+use kernel::{exit_qemu, memory, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// Addresses in the lower half that are not used by the bootloader.
+const LAZY_AREA_ADDRESS: u64 = 0x_5555_0000_0000;
+const READ_ONLY_AREA_ADDRESS: u64 = 0x_5555_1000_0000;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_demand_paging...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { memory::init(&boot_info.memory_regions, physical_memory_offset) };
+    kernel::interrupts::testonly_gdt_init();
+    kernel::interrupts::testonly_idt_init();
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let area = VirtualMemoryArea::new(
+        VirtualAddress::new(LAZY_AREA_ADDRESS),
+        4 * PAGE_SIZE,
+        AreaKind::Anonymous,
+        flags,
+        Backing::Lazy,
+    );
+    KERNEL_AREAS.lock().insert(area).unwrap();
+
+    let used_frames = memory::frame_allocator().used_frames();
+    let first_page: Page = Page::new(area.start());
+    let second_page: Page = Page::new(area.start() + PAGE_SIZE);
+    assert!(memory::paging().translate(area.start()).is_none());
+
+    // The first write faults, the page fault handler maps the page and the write is restarted.
+    let ptr = (second_page.start_address().address() + 0x42) as *mut u64;
+    unsafe { ptr.write_volatile(0xdead_beef) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 0xdead_beef);
+
+    assert_eq!(memory::paging().page_flags(second_page), Some(flags));
+    assert!(memory::paging()
+        .translate(first_page.start_address())
+        .is_none());
+    assert!(memory::frame_allocator().used_frames() > used_frames);
+
+    // Lazily mapped pages start out zeroed.
+    let ptr = (first_page.start_address().address() + 0x100) as *const u64;
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+    assert!(memory::paging()
+        .translate(first_page.start_address())
+        .is_some());
+
+    // Reading from a read only area maps the page with the flags of the area.
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    let area = VirtualMemoryArea::new(
+        VirtualAddress::new(READ_ONLY_AREA_ADDRESS),
+        PAGE_SIZE,
+        AreaKind::Anonymous,
+        flags,
+        Backing::Lazy,
+    );
+    KERNEL_AREAS.lock().insert(area).unwrap();
+
+    let page: Page = Page::new(area.start());
+    let ptr = page.start_address().address() as *const u8;
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+    assert_eq!(memory::paging().page_flags(page), Some(flags));
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
+use kernel::memory::page_table::PageFaultErrorCodes;
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::registers::control::CR2;
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use lazy_static::lazy_static;
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.add_interrupt_handler(
+            IdtIndex::PageFaultInterruptIndex,
+            kernel::handler_with_error_code!(page_fault_interrupt_handler),
+        );
+
+        idt
+    };
+}
+
+// A ret instruction in writable data.
+static mut DATA: [u8; 1] = [0xc3];
+
+extern "C" fn page_fault_interrupt_handler(
+    _stack_frame: &kernel::interrupts::ExceptionStackFrame,
+    error_code: u64,
+) -> ! {
+    // The instruction fetch hit a present page that is not executable.
+    let error_code = PageFaultErrorCodes::from_bits_truncate(error_code);
+    assert!(error_code.contains(
+        PageFaultErrorCodes::PAGE_PROTECTION_VIOLATION | PageFaultErrorCodes::INSTRUCTION_FETCH
+    ));
+    assert_eq!(CR2::read(), VirtualAddress::from_ptr(&raw const DATA));
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_execute_data...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+    kernel::interrupts::testonly_gdt_init();
+    IDT.load();
+
+    let function: extern "C" fn() = unsafe { core::mem::transmute(&raw const DATA) };
+    function();
+
+    serial_println!("[Executing data did not fault]");
+    exit_qemu(QemuExitCode::Failed);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(kernel::test_runner)]
+#![reexport_test_harness_main = "run_test"]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, vec::Vec};
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::frame_allocator::MemoryMapFrameAllocator;
+use kernel::memory::heap::{self, HEAP_SIZE};
+use kernel::memory::paging::Paging;
+use kernel::test_panic_handler;
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    let mut paging: Paging = Paging::init(physical_memory_offset);
+    let mut frame_allocator =
+        unsafe { MemoryMapFrameAllocator::init(&boot_info.memory_regions, physical_memory_offset) };
+    heap::init_heap(&mut paging, &mut frame_allocator).expect("Heap initialization failed");
+
+    run_test();
+    kernel::hlt();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info);
+}
+
+#[test_case]
+fn test_simple_allocation() {
+    let heap_value_1 = Box::new(41);
+    let heap_value_2 = Box::new(13);
+    assert_eq!(*heap_value_1, 41);
+    assert_eq!(*heap_value_2, 13);
+}
+
+#[test_case]
+fn test_large_vec() {
+    let n = 1000;
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
+}
+
+#[test_case]
+fn test_large_allocation() {
+    // Half of the heap in a single allocation.
+    let size = HEAP_SIZE as usize / 2;
+    let mut buffer: Vec<u8> = Vec::with_capacity(size);
+    buffer.resize(size, 0x42);
+    assert!(buffer.iter().all(|byte| *byte == 0x42));
+}
+
+#[test_case]
+fn test_many_small_allocations() {
+    // Allocate more memory than the heap holds in total. This only works if freed memory is
+    // reused.
+    for i in 0..HEAP_SIZE as usize {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+}
+
+// A bump allocator only reuses memory once every allocation is freed, so a single long lived
+// allocation makes it run out of memory.
+#[cfg(not(feature = "bump-allocator"))]
+#[test_case]
+fn test_many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE as usize {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+#[test_case]
+fn test_freed_memory_is_reused() {
+    let stats_before = heap::heap_stats();
+
+    let strings: Vec<String> = (0..100).map(|i| alloc::format!("string {}", i)).collect();
+    let mut map = BTreeMap::new();
+    for (i, string) in strings.iter().enumerate() {
+        map.insert(i, string.clone());
+    }
+    assert_eq!(map[&42], "string 42");
+
+    drop(map);
+    drop(strings);
+
+    // Every freed byte is available again. Depending on the allocator design, the free memory may
+    // be split into a different number of blocks than before.
+    let stats_after = heap::heap_stats();
+    assert_eq!(stats_after.free_bytes, stats_before.free_bytes);
+    assert_eq!(stats_after.live_allocations, stats_before.live_allocations);
+}
@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::interrupts::idt::IdtIndex;
+use kernel::interrupts::ioapic::RedirectionFlags;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::time::hpet::ComparatorMode;
+use kernel::time::{self, hpet, ClockSource, Instant};
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// The comparator the clock runs on, and the one armed once.
+const CLOCK_COMPARATOR: u8 = 0;
+const ONE_SHOT_COMPARATOR: u8 = 1;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_hpet...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The HPET is described by the ACPI HPET table.
+    let rsdp_address = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("The firmware did not provide ACPI tables");
+    unsafe { kernel::acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+        .expect("ACPI initialization failed");
+
+    kernel::interrupts::init();
+    time::init(ClockSource::Hpet, time::DEFAULT_TIMER_FREQUENCY);
+    assert_eq!(time::clock_source(), ClockSource::Hpet);
+    assert_eq!(time::frequency(), Some(time::DEFAULT_TIMER_FREQUENCY));
+
+    // The main counter runs at 10 MHz or more.
+    let hpet = hpet::hpet().expect("The HPET is not set up");
+    assert!(hpet.frequency() >= 10_000_000);
+    let counter = hpet.counter();
+    hpet.delay_us(100);
+    assert!(hpet.counter() > counter);
+
+    // The comparator raises the timer interrupt, at the requested frequency. The host may preempt
+    // the guest, so there is no upper bound.
+    let start = Instant::now();
+    let ticks = time::ticks();
+    time::sleep_ticks(20);
+    let elapsed = start.elapsed();
+    assert!(time::ticks() >= ticks + 20);
+    assert!(
+        elapsed >= Duration::from_millis(15),
+        "20 ticks took {:?}",
+        elapsed
+    );
+
+    // Once the clock comparator is stopped, a one-shot comparator raises the timer interrupt
+    // exactly once.
+    hpet.stop_comparator(CLOCK_COMPARATOR);
+    let routes = hpet.routes(ONE_SHOT_COMPARATOR).unwrap();
+    let gsi = (0..32)
+        .filter(|&gsi| routes & (1 << gsi) != 0)
+        .find(|&gsi| unsafe {
+            kernel::interrupts::route_gsi(
+                gsi,
+                IdtIndex::TimerInterruptIndex,
+                RedirectionFlags::empty(),
+            )
+        })
+        .expect("The comparator can't raise any routed interrupt");
+
+    let ticks = time::ticks();
+    let one_millisecond = hpet.frequency() / 1000;
+    unsafe {
+        hpet.start_comparator(
+            ONE_SHOT_COMPARATOR,
+            ComparatorMode::OneShot,
+            one_millisecond,
+            gsi,
+        )
+    }
+    .unwrap();
+    hpet.delay_us(20_000);
+    assert_eq!(time::ticks(), ticks + 1);
+    hpet.stop_comparator(ONE_SHOT_COMPARATOR);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
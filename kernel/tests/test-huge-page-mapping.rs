@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::frame::Frame;
+use kernel::memory::frame_allocator::MemoryMapFrameAllocator;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::memory::page::Page;
+use kernel::memory::page_size::{PageSize, Size1GiB, Size2MiB, Size4KiB};
+use kernel::memory::page_table::PageTableFlags;
+use kernel::memory::paging::{MappingError, Paging};
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::registers::cpuid::{self, CpuFeatures};
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// 1GB aligned addresses in the lower half that are not used by the bootloader.
+const TEST_2MIB_PAGE_ADDRESS: u64 = 0x_5555_0000_0000;
+const TEST_1GIB_PAGE_ADDRESS: u64 = 0x_5556_0000_0000;
+
+// The VGA text buffer lives in the first 2MB of physical memory.
+const VGA_BUFFER_ADDRESS: u64 = 0xb8000;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_huge_page_mapping...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    let mut paging: Paging = Paging::init(physical_memory_offset);
+    let mut frame_allocator =
+        unsafe { MemoryMapFrameAllocator::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // Map the first 2MB of physical memory read only with a single level 2 entry.
+    let page: Page<Size2MiB> = Page::new(VirtualAddress::new(TEST_2MIB_PAGE_ADDRESS));
+    let frame: Frame<Size2MiB> = Frame::new(PhysicalAddress::zero());
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    paging
+        .map_to(page, frame, flags, &mut frame_allocator)
+        .unwrap();
+
+    assert_eq!(paging.page_flags(page), Some(flags));
+    assert_eq!(
+        paging.translate(page.start_address() + VGA_BUFFER_ADDRESS),
+        Some(PhysicalAddress::new(VGA_BUFFER_ADDRESS))
+    );
+
+    // Reading through the huge page must match reading through the physical memory mapping.
+    let huge_page_ptr = (page.start_address().address() + VGA_BUFFER_ADDRESS) as *const u64;
+    let physical_ptr = (physical_memory_offset + VGA_BUFFER_ADDRESS) as *const u64;
+    assert_eq!(unsafe { huge_page_ptr.read_volatile() }, unsafe {
+        physical_ptr.read_volatile()
+    });
+
+    // A 4KB page inside the huge page cannot be mapped or unmapped on its own.
+    let small_page: Page<Size4KiB> = Page::new(page.start_address() + Size4KiB::SIZE);
+    assert_eq!(
+        paging.unmap(small_page),
+        Err(MappingError::ParentEntryHugePage)
+    );
+
+    assert_eq!(paging.unmap(page), Ok(frame));
+    assert!(paging.translate(page.start_address()).is_none());
+
+    // Map a 1GB page, if the CPU supports them. We only check the translation and never access
+    // the page.
+    let page: Page<Size1GiB> = Page::new(VirtualAddress::new(TEST_1GIB_PAGE_ADDRESS));
+    let frame: Frame<Size1GiB> = Frame::new(PhysicalAddress::zero());
+    if !cpuid::has(CpuFeatures::HUGE_PAGE_1G) {
+        assert_eq!(
+            paging.map_to(page, frame, flags, &mut frame_allocator),
+            Err(MappingError::PageSizeNotSupported)
+        );
+        assert!(paging.translate(page.start_address()).is_none());
+
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+        kernel::hlt()
+    }
+
+    paging
+        .map_to(page, frame, flags, &mut frame_allocator)
+        .unwrap();
+
+    assert_eq!(paging.page_flags(page), Some(flags));
+    assert_eq!(
+        paging.translate(page.start_address() + (Size2MiB::SIZE + 0x42)),
+        Some(PhysicalAddress::new(Size2MiB::SIZE + 0x42))
+    );
+
+    assert_eq!(paging.unmap(page), Ok(frame));
+    assert!(paging.translate(page.start_address()).is_none());
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::frame_allocator::{FrameAllocator, MemoryMapFrameAllocator};
+use kernel::memory::page::Page;
+use kernel::memory::page_table::PageTableFlags;
+use kernel::memory::paging::{MappingError, Paging};
+use kernel::memory::vaddr::VirtualAddress;
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// An address in the lower half that is not used by the bootloader.
+const TEST_PAGE_ADDRESS: u64 = 0x_5555_0000_0000;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_page_mapping...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    let mut paging: Paging = Paging::init(physical_memory_offset);
+    let mut frame_allocator =
+        unsafe { MemoryMapFrameAllocator::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    let page = Page::new(VirtualAddress::new(TEST_PAGE_ADDRESS));
+    assert!(paging.translate(page.start_address()).is_none());
+
+    // Map the page and write to it through the new mapping.
+    let frame = frame_allocator.allocate_frame().unwrap();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    paging
+        .map_to(page, frame, flags, &mut frame_allocator)
+        .unwrap();
+
+    assert_eq!(
+        paging.translate(page.start_address() + 0x42 as u64),
+        Some(frame.start_address() + 0x42 as u64)
+    );
+    assert_eq!(paging.page_flags(page), Some(flags));
+
+    let ptr = page.start_address().address() as *mut u64;
+    unsafe { ptr.write_volatile(0xdead_beef) };
+
+    // The value needs to be visible through the physical memory mapping of the frame.
+    let physical_ptr = (frame.start_address().address() + physical_memory_offset) as *const u64;
+    assert_eq!(unsafe { physical_ptr.read_volatile() }, 0xdead_beef);
+
+    // Mapping the same page twice is an error.
+    let other_frame = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(
+        paging.map_to(page, other_frame, flags, &mut frame_allocator),
+        Err(MappingError::PageAlreadyMapped(frame))
+    );
+
+    // Make the page read only.
+    paging.update_flags(page, PageTableFlags::PRESENT).unwrap();
+    assert_eq!(paging.page_flags(page), Some(PageTableFlags::PRESENT));
+    assert_eq!(unsafe { ptr.read_volatile() }, 0xdead_beef);
+
+    // Remove the mapping again.
+    assert_eq!(paging.unmap(page), Ok(frame));
+    assert!(paging.translate(page.start_address()).is_none());
+    assert_eq!(paging.unmap(page), Err(MappingError::PageNotMapped));
+    assert_eq!(
+        paging.update_flags(page, flags),
+        Err(MappingError::PageNotMapped)
+    );
+
+    frame_allocator.deallocate_frame(frame);
+    frame_allocator.deallocate_frame(other_frame);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
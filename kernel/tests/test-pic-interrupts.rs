@@ -1,6 +1,7 @@
 #![no_std]
 #![no_main]
 
+use bootloader_api::BootloaderConfig;
 use core::panic::PanicInfo;
 use kernel::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
 use kernel::interrupts::pic::Pics;
@@ -8,7 +9,9 @@ use kernel::interrupts::ExceptionStackFrame;
 use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
 use lazy_static::lazy_static;
 
-bootloader_api::entry_point!(test_main);
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
 
 pub const PRIMARY_PIC_OFFSET: u8 = 104;
 pub const SECONDARY_PIC_OFFSET: u8 = 112;
@@ -38,9 +41,16 @@ extern "C" fn timer_interrupt_handler(_stack_frame: &ExceptionStackFrame) {
     kernel::hlt()
 }
 
-fn test_main(_boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     kernel::serial_println!("Cascaded PICs Interrupt Test");
 
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
     kernel::interrupts::testonly_gdt_init();
     IDT.load();
 
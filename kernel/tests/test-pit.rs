@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::time;
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_pit...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // Routes the timer IRQ and enables interrupts.
+    kernel::interrupts::init();
+    time::init(time::ClockSource::Pit, time::DEFAULT_TIMER_FREQUENCY);
+    assert_eq!(time::frequency(), Some(time::DEFAULT_TIMER_FREQUENCY));
+
+    let start = time::ticks();
+    time::sleep_ticks(10);
+    assert!(time::ticks() >= start + 10);
+    assert!(time::uptime() >= Duration::from_millis(10));
+
+    // The calibrated busy wait of 20 ms spans about 20 ticks. The bounds are loose enough for a
+    // preempted guest, but still catch a calibration that is off by an order of magnitude.
+    let start = time::ticks();
+    time::delay_us(20_000);
+    let elapsed = time::ticks() - start;
+    assert!(
+        (10..=200).contains(&elapsed),
+        "20 ms took {} ticks",
+        elapsed
+    );
+
+    // The ticks never go backwards.
+    let mut previous = time::ticks();
+    for _ in 0..1000 {
+        let ticks = time::ticks();
+        assert!(ticks >= previous);
+        previous = ticks;
+    }
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
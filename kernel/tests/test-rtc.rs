@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::time::{self, rtc::DateTime, ClockSource};
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// QEMU starts the RTC at the time of the host, which is later than this.
+const EARLIEST_TIMESTAMP: u64 = 1_700_000_000;
+
+// How long the alarm is waited for, in timer ticks.
+const ALARM_TIMEOUT_TICKS: u64 = 4 * time::DEFAULT_TIMER_FREQUENCY as u64;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_rtc...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The FADT tells where the RTC keeps the century.
+    let rsdp_address = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("The firmware did not provide ACPI tables");
+    unsafe { kernel::acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+        .expect("ACPI initialization failed");
+
+    kernel::interrupts::init();
+    time::init(ClockSource::Pit, time::DEFAULT_TIMER_FREQUENCY);
+
+    let now = time::now().expect("The RTC was not read");
+    assert!(now.as_secs() > EARLIEST_TIMESTAMP, "RTC time: {:?}", now);
+    let date_time = time::date_time().unwrap();
+    assert_eq!(date_time.unix_timestamp(), now.as_secs());
+
+    // The wall clock advances with the uptime.
+    time::sleep_ticks(20);
+    assert!(time::now().unwrap() >= now + Duration::from_millis(15));
+
+    // The periodic interrupt arrives on IRQ 8.
+    let frequency_hz = time::enable_rtc_periodic(6);
+    assert_eq!(frequency_hz, 1024);
+    time::sleep_ticks(20);
+    time::disable_rtc_periodic();
+    assert!(time::rtc_periodic_interrupts() > 0);
+
+    // So does the alarm, a few seconds from now.
+    let alarm = DateTime::from_unix_timestamp(time::now().unwrap().as_secs() + 2);
+    time::set_rtc_alarm(alarm);
+    let start = time::ticks();
+    while time::rtc_alarms() == 0 {
+        assert!(
+            time::ticks() - start < ALARM_TIMEOUT_TICKS,
+            "The alarm did not fire"
+        );
+        time::sleep_ticks(1);
+    }
+    time::clear_rtc_alarm();
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
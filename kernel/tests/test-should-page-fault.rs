@@ -1,13 +1,16 @@
 #![no_std]
 #![no_main]
 
+use bootloader_api::BootloaderConfig;
 use core::panic::PanicInfo;
 use kernel::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
 use kernel::interrupts::utils::generate_page_fault;
 use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
 use lazy_static::lazy_static;
 
-bootloader_api::entry_point!(test_main);
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -37,9 +40,16 @@ extern "C" fn page_fault_interrupt_handler(
     kernel::hlt()
 }
 
-fn test_main(_boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     kernel::serial_println!("Page Fault Test");
 
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
     kernel::interrupts::testonly_gdt_init();
     IDT.load();
 
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::{power, serial_print};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// Powers the machine off through ACPI. QEMU exits with status 0 once the machine is off, which the
+// test runner reports as success. If the machine keeps running, the test fails.
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_shutdown...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The PM1 control registers are described by the FADT, the sleep types by the DSDT.
+    let rsdp_address = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("The firmware did not provide ACPI tables");
+    unsafe { kernel::acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+        .expect("ACPI initialization failed");
+
+    // The registers were mapped while the tables were parsed, so the power paths take no locks.
+    let fadt = kernel::acpi::fadt().expect("The firmware did not provide a FADT");
+    let pm1a_control = fadt.pm1a_control_block.expect("No PM1a control block");
+    assert!(pm1a_control.is_accessible());
+    if let Some(reset_register) = fadt.reset_register {
+        assert!(reset_register.is_accessible());
+    }
+
+    let error = power::try_shutdown();
+    panic!("The machine is still running: {:?}", error);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use kernel::memory::paddr::PhysicalAddress;
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+// QEMU is started with -smp 4.
+const EXPECTED_CPUS: usize = 4;
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_smp...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The CPUs are listed in the MADT.
+    let rsdp_address = boot_info
+        .rsdp_addr
+        .into_option()
+        .expect("The firmware did not provide ACPI tables");
+    unsafe { kernel::acpi::init(PhysicalAddress::new(rsdp_address), physical_memory_offset) }
+        .expect("ACPI initialization failed");
+
+    kernel::interrupts::init();
+
+    let cpus = kernel::smp::init().expect("SMP initialization failed");
+    assert_eq!(cpus, EXPECTED_CPUS);
+    assert_eq!(kernel::smp::cpus_online(), EXPECTED_CPUS);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -1,15 +1,20 @@
 #![no_std]
 #![no_main]
 
+use bootloader_api::BootloaderConfig;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 use kernel::interrupts::idt::{IdtIndex, InterruptDescriptorTable};
+use kernel::registers::control::CR2;
 use lazy_static::lazy_static;
 
 use kernel::exit_qemu;
 
 const DOUBLE_FAULT_IST_INDEX: u8 = 0;
 
-bootloader_api::entry_point!(test_main);
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -24,6 +29,12 @@ lazy_static! {
     };
 }
 
+// Set once the double fault caused by the overflow of the kernel stack was handled.
+static KERNEL_STACK_OVERFLOWED: AtomicBool = AtomicBool::new(false);
+
+// The first double fault is caused by the overflow of the kernel stack. The handler then overflows
+// the double fault stack itself. Its guard page turns that into a second double fault, which the
+// CPU delivers on the same double fault stack, starting again from its top.
 extern "C" fn double_fault_interrupt_handler(
     stack_frame: &kernel::interrupts::ExceptionStackFrame,
     error_code: u64,
@@ -34,15 +45,34 @@ extern "C" fn double_fault_interrupt_handler(
         &*stack_frame
     );
 
+    if !KERNEL_STACK_OVERFLOWED.swap(true, Ordering::SeqCst) {
+        kernel::serial_println!("Overflowing the double fault stack");
+        generate_stack_overflow();
+        panic!("Execution continues after overflowing the double fault stack.");
+    }
+
+    // The faulting access has to hit the guard page below the double fault stack.
+    let guard_page = kernel::interrupts::double_fault_stack().guard_page();
+    let fault_address = CR2::read();
+    assert!(guard_page.start_address() <= fault_address);
+    assert!(fault_address < guard_page.start_address() + guard_page.size());
+
     kernel::serial_println!("[ok]");
     exit_qemu(kernel::QemuExitCode::Success);
 
     kernel::hlt()
 }
 
-fn test_main(_boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
     kernel::serial_println!("Stack Overflow Test");
 
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
     kernel::interrupts::testonly_gdt_init();
     IDT.load();
 
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use bootloader_api::BootloaderConfig;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use kernel::time::{self, tsc, Instant};
+use kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+pub static BOOTLOADER_CONFIG: BootloaderConfig = kernel::bootloader_config();
+
+bootloader_api::entry_point!(test_main, config = &BOOTLOADER_CONFIG);
+
+fn test_main(boot_info: &'static mut bootloader_api::BootInfo) -> ! {
+    serial_print!("test_tsc...\t");
+
+    let physical_memory_offset: u64 = match boot_info.physical_memory_offset.into_option() {
+        Some(address) => address,
+        None => panic!("Physical memory offset not enabled in the bootloader"),
+    };
+
+    unsafe { kernel::memory::init(&boot_info.memory_regions, physical_memory_offset) };
+
+    // The TSC is calibrated against the PIT, which needs the timer interrupt.
+    kernel::interrupts::init();
+    time::init(time::ClockSource::Pit, time::DEFAULT_TIMER_FREQUENCY);
+    assert!(tsc::frequency().is_some());
+
+    // 20 PIT ticks measure about 20 ms by the TSC. A wrong TSC frequency shows up as a multiple of
+    // that, so anything beyond 10 times as long fails.
+    let start = Instant::now();
+    time::sleep_ticks(20);
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(15) && elapsed <= Duration::from_millis(200),
+        "20 ticks took {:?}",
+        elapsed
+    );
+
+    let later = start + Duration::from_millis(5);
+    assert!(later > start);
+
+    // Converting to cycles and back rounds down.
+    let difference = Duration::from_millis(5) - (later - start);
+    assert!(difference < Duration::from_micros(1));
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    kernel::hlt()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    kernel::test_panic_handler(info);
+}
@@ -36,6 +36,8 @@ fn main() {
             "isa-debug-exit,iobase=0xf4,iosize=0x04",
             "-display",
             "none",
+            "-smp",
+            "4",
         ])
         .status()
         .unwrap();
@@ -21,6 +21,8 @@ fn main() {
         &format!("format=raw,file={}", env!("UEFI_IMAGE")),
         "-serial",
         "stdio",
+        "-smp",
+        "4",
     ]);
     let exit_status = qemu.status().unwrap();
     process::exit(exit_status.code().unwrap_or(-1));